@@ -1,221 +1,117 @@
-use std::collections::HashMap;
-
 // use rand::{seq::SliceRandom, SeedableRng};
 use rltk::RGB;
 use specs::prelude::*;
 
-use crate::{components::{Confusion, Consumable, Explosion, InBackpack, InstantHarm, Invulnerability, Item, LingerType, LingeringEffect, Name, Potion, ProvidesHealing, Renderable, Strength, Teleport, WantsToMixPotions, Weight}, gamelog::GameLog, AlchemySeed};
-
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-enum PotionEffect {
-    Heal(ProvidesHealing),
-    Teleport(Teleport),
-    Confusion(Confusion),
-    Harm(InstantHarm),
-    Linger(LingeringEffect),
-    Explosion(Explosion),
-    Invulnerability(Invulnerability),
-    Strength(Strength)
-}
+use crate::{components::{AlchemyStation, Busy, Consumable, Diluted, Explosion, InBackpack, Item, Name, Position, Potion, Renderable, Temperature, Unidentified, WantsToDilute, WantsToMixPotions, Weight, MAX_POTENCY, MIN_POTENCY, STANDARD_POTENCY}, effects::{potion_effects, EffectQueue, EffectSource, EffectType, PotionEffect, PotionStorages, Targets}, gameevent::{GameEvent, GameEvents, MixOutcome}, identify_system::{PotionAppearances, PotionKind}, journal::AlchemyJournal, map::{self, Map, TileType}, raws::{RawEffect, RawIngredient, RawRecipe, Raws}, simulation::TurnCounter, AlchemySeed};
+
+/// Flasks that fit into a mix made in the field, without a station.
+pub const FIELD_SLOTS: usize = 2;
 
 pub struct AlchemySystem {}
 
 impl<'a> System<'a> for AlchemySystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = ( WriteStorage<'a, WantsToMixPotions>,
                         Entities<'a>,
-                        WriteExpect<'a, GameLog>,
-                        ReadExpect<'a, Entity>    ,
+                        WriteExpect<'a, GameEvents>,
                         WriteStorage<'a, Renderable>,
                         WriteStorage<'a, Potion>,
+                        ReadStorage<'a, Diluted>,
                         WriteStorage<'a, Item>,
                         WriteStorage<'a, Consumable>,
                         WriteStorage<'a, InBackpack>,
                         WriteStorage<'a, Name>,
                         WriteStorage<'a, Weight>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, AlchemyStation>,
+                        WriteStorage<'a, Busy>,
                         ReadExpect<'a, AlchemySeed>,
-                        
-                        WriteStorage<'a, ProvidesHealing>,
-                        WriteStorage<'a, Teleport>,
-                        WriteStorage<'a, Confusion>,
-                        WriteStorage<'a, InstantHarm>,
-                        WriteStorage<'a, LingeringEffect>,
-                        WriteStorage<'a, Explosion>,
-                        WriteStorage<'a, Invulnerability>,
-                        WriteStorage<'a, Strength>
+                        ReadExpect<'a, Raws>,
+                        WriteExpect<'a, PotionAppearances>,
+                        WriteStorage<'a, Unidentified>,
+                        WriteExpect<'a, AlchemyJournal>,
+                        ReadExpect<'a, TurnCounter>,
+                        PotionStorages<'a>,
+                        Read<'a, LazyUpdate>,
+                        WriteExpect<'a, EffectQueue>
                     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut intentmix, entities, mut log, playerentity, mut renders, mut potions, mut items, mut consumables, mut inbackpack, mut names, mut weight, seed,   mut heal, mut tp, mut confusion, mut harm, mut linger, mut explosion, mut invuln, mut strength) = data;
-
-        for intent in (&mut intentmix).join() {
-            let WantsToMixPotions { first, second } = intent;
-            if !potions.contains(*first) || !potions.contains(*second) {
-                log.entries.push("You cannot mix that.".to_owned());
+        let (mut intentmix, entities, mut events, mut renders, mut potions, diluted, mut items, mut consumables, mut inbackpack, mut names, mut weight, positions, stations, mut busy, seed, raws, mut appearances, mut unidentified, mut journal, turn, potion_storages, lazy, mut effects) = data;
+
+        for (mixer, intent) in (&entities, &mut intentmix).join() {
+            let WantsToMixPotions { ingredients, station, temperature } = intent;
+            let at_station = station.and_then(|s| stations.get(s).copied().map(|st| (s, st)));
+            if !can_mix(ingredients, mixer, at_station.map(|(_, st)| st), *temperature, (&potions, &diluted), &inbackpack)
+                || station.is_some_and(|s| at_station.is_none() || positions.get(s).map(|p| (p.x, p.y)) != positions.get(mixer).map(|p| (p.x, p.y))) {
+                events.emit(GameEvent::MixFailed);
                 continue;
             }
-            entities.delete(*first).expect("Unable to delete first mix component");
-            entities.delete(*second).expect("Unable to delete second mix component");
+            for ingredient in ingredients.iter() {
+                entities.delete(*ingredient).expect("Unable to delete mix ingredient");
+            }
 
-            log.entries.push(format!("You mix {} and {}.", names.get(*first).map_or("something", |n| &n.name), names.get(*second).map_or("something", |n| &n.name)));
+            let ingredient_names: Vec<String> = ingredients.iter().map(|i| names.get(*i).map_or("something".to_owned(), |n| n.name.clone())).collect();
+            let station_name = station.and_then(|s| names.get(s)).map(|n| n.name.clone());
+            // Варка на станции занимает время, смесь в поле готова сразу
+            if let Some((_, station)) = at_station {
+                let turns = brew_turns(station, *temperature);
+                if turns > 1 {
+                    busy.insert(mixer, Busy { turns: turns - 1 }).expect("Unable to insert busy");
+                }
+            }
 
-            // special case
-            // heal + harm combo
-            if  heal.contains(*first) && harm.contains(*second) ||
-                harm.contains(*first) && heal.contains(*second) {
+            let flasks: Vec<Flask> = ingredients.iter()
+                .map(|i| Flask { effects: potion_effects(&potion_storages, *i), potency: potions.get(*i).map_or(STANDARD_POTENCY, |p| p.potency) })
+                .collect();
+            // Одна фляга на огне уваривается, это не смесь и в журнал не идёт
+            let brewed = match flasks.as_slice() {
+                [flask] => Brew::Even(flask.at_potency(flask.potency + 1)),
+                _ => {
+                    let brewed = brew(&flasks, *temperature, Cookbook { recipes: &raws.recipes, seed: seed.0 });
+                    journal.record(turn.0, &flasks, *temperature, &brewed);
+                    brewed
+                }
+            };
+            if brewed == Brew::Exploded {
                 // BOOOOM!!!
-
-                // entities.delete(*first).expect("Unable to delete first mix component");
-                // entities.delete(*second).expect("Unable to delete second mix component");
-                log.entries.push("The mix violently explodes!".to_owned());
-
-                explosion.insert(*playerentity, crate::components::Explosion { maxdmg: 20, radius: 5 })
-                         .expect("Unable to explode the player");
-
+                events.emit(GameEvent::PotionMixed { names: ingredient_names, station: station_name, temperature: *temperature, outcome: MixOutcome::Exploded });
+                effects.push(EffectType::Potion(PotionEffect::Explosion(MIX_EXPLOSION)), EffectSource::Mix, Some(mixer), Targets::Single(mixer));
                 continue;
             }
 
-            let mut effects_first: Vec<PotionEffect> = vec![];
-            let mut effects_second: Vec<PotionEffect> = vec![];
-
-            // INFLICTS
-            let mut contains: u8 = 0;
-            use PotionEffect::*;
-            heal     .get(*first).map(|h| { effects_first.push(Heal(*h)); contains |= 1});
-            tp       .get(*first).map(|t| { effects_first.push(Teleport(*t)); contains |= 2});
-            confusion.get(*first).map(|c| { effects_first.push(Confusion(*c)); contains |= 4});
-            harm     .get(*first).map(|h| { effects_first.push(Harm(*h)); contains |= 8});
-            linger   .get(*first).map(|l| { effects_first.push(Linger(*l)); contains |= 16});
-            explosion.get(*first).map(|e| { effects_first.push(Explosion(*e)); contains |= 32});
-
-            heal     .get(*second).map(|h| { effects_second.push(Heal(*h)); contains |= 1});
-            tp       .get(*second).map(|t| { effects_second.push(Teleport(*t)); contains |= 2});
-            confusion.get(*second).map(|c| { effects_second.push(Confusion(*c)); contains |= 4});
-            harm     .get(*second).map(|h| { effects_second.push(Harm(*h)); contains |= 8});
-            linger   .get(*second).map(|l| { effects_second.push(Linger(*l)); contains |= 16});
-            explosion.get(*second).map(|e| { effects_second.push(Explosion(*e)); contains |= 32});
-
-            let mut color = renders.get(*first).map_or(RGB::named(rltk::GREEN), |c| c.fg);
-            // entities.delete(*first).expect("Unable to delete first mix component");
-            // entities.delete(*second).expect("Unable to delete second mix component");
-
             let new_potion = entities.create();
             items.insert(new_potion, Item {}).expect("Unable to insert item in mix");
-            potions.insert(new_potion, Potion {}).expect("Unable to insert potion in mix");
             consumables.insert(new_potion, Consumable {}).expect("Unable to insert consumable in mix");
-            
-            let mut name: Vec<String> = Vec::new();
-            
-            let specials = generate_combos(seed.0);
-
-            if specials.contains_key(&contains) {
-                effects_first = vec![*specials.get(&contains).unwrap()];
-                let name = match effects_first[0] {
-                    Strength(_) => "Strength",
-                    Invulnerability(_) => "Invulnerability",
-                    Heal(_) => "Health",
-                    _ => "something",
-                };
-                log.entries.push(format!("You get a potion of {name}!"));
-            } else {
-                effects_first.append(&mut effects_second);
-                log.entries.push("Two potions mix evenly.".to_owned());
-            }
 
-            effects_first.sort();
-            effects_first = effects_first.iter().fold(vec![], |mut acc, effect| {
-                if !acc.is_empty() && *acc.last().unwrap() == *effect {
-                    let new_effect = acc.pop();
-                    match new_effect {
-                        None => return vec![],
-                        Some(new_effect) => match (effect, new_effect) {
-                            (&Heal(mut h1), Heal(h2)) => {
-                                h1.heal_amount += h2.heal_amount;
-                                acc.push(Heal(h1));
-                            }
-                            (&Harm(mut h1), Harm(h2)) => {
-                                h1.dmg = ((h1.dmg + h2.dmg) as f32 * 0.6).round() as i32;
-                                acc.push(Harm(h1));
-                            }
-                            (&Explosion(mut e1), Explosion(e2)) => {
-                                e1.maxdmg = ((e1.maxdmg + e2.maxdmg) as f32 * 0.6).round() as i32;
-                                acc.push(Explosion(e1));
-                            }
-
-                            (&Linger(mut l1), Linger(l2)) => {
-                                if l2.etype == LingerType::Fire {
-                                    l1.etype = LingerType::Fire;
-                                }
-                                l1.duration = ((l1.duration + l2.duration) as f32 * 0.6).round() as i32;
-                                acc.push(Linger(l1));
-                            }
-                            (&Confusion(mut c1), Confusion(c2)) => {
-                                c1.turns = ((c1.turns + c2.turns) as f32 * 0.6).round() as i32;
-                                acc.push(Confusion(c1));
-                            }
-
-                            (_, popped) => { acc.push(popped) }
-                        }
-                    }
-                } else {
-                    acc.push(*effect);
+            let mixed = match brewed {
+                Brew::Special(made) => {
+                    let name = PotionKind::all_of(&made.effects).iter().map(|kind| kind.name()).collect::<Vec<_>>().join(" + ");
+                    let outcome = MixOutcome::Special { potion: new_potion, name };
+                    events.emit(GameEvent::PotionMixed { names: ingredient_names, station: station_name, temperature: *temperature, outcome });
+                    appearances.learn(&made.effects, &mut events);
+                    made
                 }
-
-                acc
-            });
-            for effect in effects_first {
-                match effect {
-                    Heal(h) => { 
-                        heal.insert(new_potion, h).expect("Unable to insert heal in mix"); 
-                        color = mix_colors(color, RGB::named(rltk::MAGENTA));
-                        name.push("Health".to_owned());
-                    },
-                    Teleport(t) => { 
-                        tp.insert(new_potion, t).expect("Unable to insert tp in mix"); 
-                        color = mix_colors(color, RGB::named(rltk::VIOLET));
-                        name.push("Teleport".to_owned());
-                    },
-                    Confusion(c) => { 
-                        confusion.insert(new_potion, c).expect("Unable to insert confusion in mix"); 
-                        color = mix_colors(color, RGB::named(rltk::PINK));
-                        name.push("Confusion".to_owned());
-                    },
-                    Harm(h) => { 
-                        harm.insert(new_potion, h).expect("Unable to insert harm in mix"); 
-                        color = mix_colors(color, RGB::named(rltk::DARKRED));
-                        name.push("Harm".to_owned());
-                    },
-                    Linger(l) => { 
-                        linger.insert(new_potion, l).expect("Unable to insert linger in mix"); 
-                        let color2 = match l.etype {
-                            crate::components::LingerType::Fire => {
-                                name.push("Fire".to_owned());
-                                RGB::named(rltk::RED)
-                            },
-                            crate::components::LingerType::Poison => {
-                                name.push("Poison".to_owned());
-                                RGB::named(rltk::GREEN)
-                            },
-                        };
-                        color = mix_colors(color, color2);
-                    },
-                    Explosion(e) => { 
-                        name.push("Explosion".to_owned());
-                        explosion.insert(new_potion, e).expect("Unable to insert explosion in mix"); 
-                        color = mix_colors(color, RGB::named(rltk::ORANGE));
-                    },
-                    // special cases
-                    Invulnerability(invul) => {
-                        name.push("Invulnerability".to_owned());
-                        invuln.insert(new_potion, invul).expect("Unable to insert invul in mix");
-                        color = RGB::named(rltk::GOLD);
-                    }
-                    Strength(strong) => {
-                        name.push("Strength".to_owned());
-                        strength.insert(new_potion, strong).expect("Unable to insert strength in mix");
-                        color = RGB::named(rltk::BLUE);
-                    }
+                Brew::Even(mixed) => {
+                    let outcome = MixOutcome::Even { potion: new_potion };
+                    events.emit(GameEvent::PotionMixed { names: ingredient_names, station: station_name, temperature: *temperature, outcome });
+                    mixed
                 }
+                Brew::Exploded => unreachable!()
+            };
+
+            // Настоящий цвет смеси считаем от настоящего цвета первого зелья
+            let first_color = unidentified.get(ingredients[0]).map(|u| u.fg).or(renders.get(ingredients[0]).map(|c| c.fg));
+            let (mut name, mut color) = brew_look(&mixed.effects, first_color.unwrap_or(RGB::named(rltk::GREEN)));
+            if let Some((look, look_color)) = appearances.describe(&mixed.effects) {
+                unidentified.insert(new_potion, Unidentified { name, fg: color }).expect("Unable to insert unidentified mix");
+                (name, color) = (look, look_color);
             }
+            for effect in mixed.effects {
+                effect.attach(&lazy, new_potion);
+            }
+            let potion = Potion { potency: mixed.potency };
+            potions.insert(new_potion, potion).expect("Unable to insert potion in mix");
 
             renders.insert(new_potion, Renderable { 
                 glyph: rltk::to_cp437('¡'), 
@@ -224,67 +120,410 @@ impl<'a> System<'a> for AlchemySystem {
                 render_order: 2 
             }).expect("Unable to insert renderable in mix");
         
-            inbackpack.insert(new_potion, InBackpack { owner: *playerentity }).expect("Unable to insert mix in backpack");
-            let new_weight = weight.get(*first).map_or(1, |w| w.0) + weight.get(*second).map_or(1, |w| w.0);
+            inbackpack.insert(new_potion, InBackpack { owner: mixer }).expect("Unable to insert mix in backpack");
+            let new_weight = ingredients.iter().map(|i| weight.get(*i).map_or(1, |w| w.0)).sum();
             weight.insert(new_potion, Weight(new_weight)).expect("Unable to insert mix weight");
 
-            name.dedup();
-            let mut name = name.join(" + ");
-            name.push_str(" potion");
-            names.insert(new_potion, Name { name }).expect("Unable to name mix");
+            names.insert(new_potion, Name { name: potion.name(&name) }).expect("Unable to name mix");
         }
     
         intentmix.clear();
     }
 }
 
+/// Splits a potion in two flasks a tier weaker, topped up with water from the
+/// tile the player stands on or one next to it.
+pub struct DilutionSystem {}
+
+impl<'a> System<'a> for DilutionSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = ( WriteStorage<'a, WantsToDilute>,
+                        Entities<'a>,
+                        WriteExpect<'a, GameEvents>,
+                        ReadExpect<'a, Map>,
+                        ReadStorage<'a, Position>,
+                        WriteStorage<'a, Potion>,
+                        WriteStorage<'a, Diluted>,
+                        WriteStorage<'a, Item>,
+                        WriteStorage<'a, Consumable>,
+                        WriteStorage<'a, InBackpack>,
+                        WriteStorage<'a, Name>,
+                        WriteStorage<'a, Weight>,
+                        WriteStorage<'a, Renderable>,
+                        WriteStorage<'a, Unidentified>,
+                        PotionStorages<'a>,
+                        Read<'a, LazyUpdate>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut intentdilute, entities, mut events, map, positions, mut potions, mut diluted, mut items, mut consumables, mut inbackpack, mut names, mut weight, mut renders, mut unidentified, potion_storages, lazy) = data;
+
+        for (owner, intent, pos) in (&entities, &intentdilute, &positions).join() {
+            let item = intent.item;
+            let Some(potion) = potions.get(item).copied() else { continue; };
+            if inbackpack.get(item).is_none_or(|pack| pack.owner != owner) { continue; }
+            let name = names.get(item).map_or("something".to_owned(), |n| n.name.clone());
+            let idx = map.xy_idx(pos.x, pos.y);
+            if !std::iter::once(idx).chain(map::cardinal_neighbours(idx)).any(|i| matches!(map.tiles[i], TileType::ShallowWater | TileType::DeepWater)) {
+                events.emit(GameEvent::NoWaterHere);
+                continue;
+            }
+            if potion.potency <= MIN_POTENCY {
+                events.emit(GameEvent::TooWeakToDilute { name });
+                continue;
+            }
+
+            let contents = Flask { effects: potion_effects(&potion_storages, item), potency: potion.potency }.at_potency(potion.potency - 1);
+            let weaker = Potion { potency: contents.potency };
+            let plain = potion.plain_name(&name);
+            // Вторая половина: та же фляга, только без эффектов, их навесим ниже
+            let twin = entities.create();
+            items.insert(twin, Item {}).expect("Unable to insert diluted item");
+            consumables.insert(twin, Consumable {}).expect("Unable to insert diluted consumable");
+            inbackpack.insert(twin, InBackpack { owner }).expect("Unable to insert diluted potion in backpack");
+            if let Some(render) = renders.get(item).cloned() {
+                renders.insert(twin, render).expect("Unable to insert diluted renderable");
+            }
+            if let Some(real) = unidentified.get(item).cloned() {
+                unidentified.insert(twin, real).expect("Unable to insert diluted unidentified");
+            }
+            // Половины делят вес исходной фляги, но легче единицы ни одна: пустую нечем ни разлить, ни ударить
+            if let Some(Weight(total)) = weight.get(item).cloned() {
+                weight.insert(item, Weight((total - total / 2).max(1))).expect("Unable to insert diluted weight");
+                weight.insert(twin, Weight((total / 2).max(1))).expect("Unable to insert diluted weight");
+            }
+            for flask in [item, twin] {
+                potions.insert(flask, weaker).expect("Unable to insert diluted potion");
+                diluted.insert(flask, Diluted {}).expect("Unable to mark diluted potion");
+                names.insert(flask, Name { name: weaker.name(&plain) }).expect("Unable to name diluted potion");
+                for effect in contents.effects.iter() {
+                    effect.attach(&lazy, flask);
+                }
+            }
+            events.emit(GameEvent::PotionDiluted { name });
+        }
+
+        intentdilute.clear();
+    }
+}
+
+/// Two or more different potions from the mixer's backpack, no more than the
+/// slots there are, heated or cooled only where the station can do it. A single
+/// flask can only be heated, to distil it, and only while it can get any
+/// stronger and has not been watered down.
+fn can_mix(ingredients: &[Entity], mixer: Entity, station: Option<AlchemyStation>, temperature: Temperature,
+           (potions, diluted): (&WriteStorage<Potion>, &ReadStorage<Diluted>), backpacks: &WriteStorage<InBackpack>) -> bool {
+    let slots = station.map_or(FIELD_SLOTS, |s| s.slots as usize);
+    let temperature_ok = match temperature {
+        Temperature::Room => true,
+        Temperature::Heated => station.is_some_and(|s| s.heat),
+        Temperature::Cooled => station.is_some_and(|s| s.cool)
+    };
+    let distinct = ingredients.iter().enumerate().all(|(i, a)| !ingredients[..i].contains(a));
+    let count_ok = match ingredients {
+        [flask] => temperature == Temperature::Heated && potions.get(*flask).is_some_and(|p| p.potency < MAX_POTENCY) && !diluted.contains(*flask),
+        _ => (2..=slots).contains(&ingredients.len())
+    };
+    let owned = ingredients.iter().all(|i| backpacks.get(*i).is_some_and(|pack| pack.owner == mixer));
+    count_ok && distinct && owned && temperature_ok && ingredients.iter().all(|i| potions.contains(*i))
+}
+
+/// How many turns a brew at the station takes.
+pub fn brew_turns(station: AlchemyStation, temperature: Temperature) -> i32 {
+    if temperature == Temperature::Room { station.turns } else { station.turns + 1 }
+}
+
+/// Взрыв от смеси лечения и вреда
+pub const MIX_EXPLOSION: Explosion = Explosion { maxdmg: 20, radius: 5 };
+
+/// What comes of mixing two potions, in a flask or spilled on the floor.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Brew {
+    /// Heal and Harm blow up
+    Exploded,
+    /// One of the recipes from the raws
+    Special(Flask),
+    Even(Flask)
+}
+
+/// What is in a flask, or a puddle, and how strong it is. The effects are
+/// already as strong as `potency` makes them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Flask {
+    pub effects: Vec<PotionEffect>,
+    pub potency: i32
+}
+
+impl Flask {
+    /// The same flask distilled or diluted to `potency`, within the tiers there are.
+    pub fn at_potency(&self, potency: i32) -> Flask {
+        let potency = potency.clamp(MIN_POTENCY, MAX_POTENCY);
+        Flask { effects: self.effects.iter().map(|e| e.rescaled(self.potency, potency)).collect(), potency }
+    }
+}
+
+/// The recipes from the raws and the run's alchemy seed, which rolls the random ones.
+#[derive(Clone, Copy)]
+pub struct Cookbook<'r> {
+    pub recipes: &'r [RawRecipe],
+    pub seed: u64
+}
+
+/// The mixing rules: Heal and Harm from different flasks blow up, then the
+/// first recipe that fits wins (see `RawRecipe`), otherwise the effects of all
+/// flasks go together, the strongest of each kind. The brew is as strong as the
+/// flasks are on average; identical potions grow a tier for every extra flask.
+pub fn brew(flasks: &[Flask], temperature: Temperature, cookbook: Cookbook) -> Brew {
+    use PotionEffect::*;
+
+    // special case
+    // heal + harm combo
+    let has_heal = |flask: &Flask| flask.effects.iter().any(|e| matches!(e, Heal(_)));
+    let has_harm = |flask: &Flask| flask.effects.iter().any(|e| matches!(e, Harm(_)));
+    let exploded = flasks.iter().enumerate()
+        .any(|(i, first)| has_heal(first) && flasks.iter().enumerate().any(|(j, second)| i != j && has_harm(second)));
+    if exploded {
+        return Brew::Exploded;
+    }
+
+    let average = flasks.iter().map(|f| f.potency).sum::<i32>() / flasks.len().max(1) as i32;
+    let recipe = run_recipes(cookbook.recipes, cookbook.seed).into_iter()
+        .find(|(ingredients, needs, _)| needs.is_none_or(|needs| needs == temperature) && fits(ingredients, flasks));
+    if let Some((_, _, outputs)) = recipe {
+        let made = Flask { effects: outputs.iter().map(|o| o.to_potion_effect()).collect(), potency: STANDARD_POTENCY };
+        return Brew::Special(made.at_potency(average));
+    }
+
+    let kinds = |flask: &Flask| PotionKind::all_of(&flask.effects);
+    let identical = flasks.iter().all(|flask| kinds(flask) == kinds(&flasks[0]));
+    let potency = if identical { average + flasks.len() as i32 - 1 } else { average };
+
+    // Сравниваем эффекты на обычной крепости, от каждого вида остаётся сильнейший
+    let mut effects: Vec<PotionEffect> = flasks.iter()
+        .flat_map(|flask| flask.effects.iter().map(|e| e.rescaled(flask.potency, STANDARD_POTENCY)))
+        .collect();
+    effects.sort_by(|a, b| b.cmp(a));
+    effects.dedup_by_key(|e| PotionKind::of(e));
+    effects.reverse();
+    Brew::Even(Flask { effects, potency: STANDARD_POTENCY }.at_potency(potency))
+}
+
+/// Name and colour of a brew, starting from the colour of the first potion.
+pub fn brew_look(effects: &[PotionEffect], mut color: RGB) -> (String, RGB) {
+    use PotionEffect::*;
+
+    let mut name: Vec<String> = Vec::new();
+    for effect in effects {
+        match effect {
+            Heal(_) => { 
+                color = mix_colors(color, RGB::named(rltk::MAGENTA));
+                name.push("Health".to_owned());
+            },
+            Teleport(_) => { 
+                color = mix_colors(color, RGB::named(rltk::VIOLET));
+                name.push("Teleport".to_owned());
+            },
+            Confusion(_) => { 
+                color = mix_colors(color, RGB::named(rltk::PINK));
+                name.push("Confusion".to_owned());
+            },
+            Harm(_) => { 
+                color = mix_colors(color, RGB::named(rltk::DARKRED));
+                name.push("Harm".to_owned());
+            },
+            Linger(l) => { 
+                let color2 = match l.etype {
+                    crate::components::LingerType::Fire => {
+                        name.push("Fire".to_owned());
+                        RGB::named(rltk::RED)
+                    },
+                    crate::components::LingerType::Poison => {
+                        name.push("Poison".to_owned());
+                        RGB::named(rltk::GREEN)
+                    },
+                };
+                color = mix_colors(color, color2);
+            },
+            Explosion(_) => { 
+                name.push("Explosion".to_owned());
+                color = mix_colors(color, RGB::named(rltk::ORANGE));
+            },
+            // special cases
+            Invulnerability(_) => {
+                name.push("Invulnerability".to_owned());
+                color = RGB::named(rltk::GOLD);
+            }
+            Strength(_) => {
+                name.push("Strength".to_owned());
+                color = RGB::named(rltk::BLUE);
+            }
+        }
+    }
+
+    name.dedup();
+    let mut name = name.join(" + ");
+    name.push_str(" potion");
+    (name, color)
+}
+
 fn mix_colors(color1: RGB, color2: RGB) -> RGB {
     rltk::RgbLerp::new(color1, color2, 3)
                   .nth(1)
                   .unwrap_or(RGB::named(rltk::GREEN))
 }
 
-fn generate_combos(seed: u64) -> HashMap<u8, PotionEffect> {
-    let mut hashmap = HashMap::new();
-
-    // let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+/// Recipes as they are this run: random ones get their ingredients rolled
+/// from the seed, never the same kinds as another recipe if it can help it.
+fn run_recipes(recipes: &[RawRecipe], seed: u64) -> Vec<(Vec<RawIngredient>, Option<Temperature>, &[RawEffect])> {
     let mut rng = rltk::RandomNumberGenerator::seeded(seed);
-    let fields: Vec<u8> = (1..=5).map(|p| u8::pow(2, p as u32)).collect();
-
-    let mut combos = [0u8; 3];
-    for i in 0..=2 {
-        let mut tmp = rng.random_slice_entry(&fields).unwrap() | rng.random_slice_entry(&fields).unwrap();
-        // let mut tmp = fields.choose_multiple(&mut rng, 2).fold(0, |acc, x| {acc | *x});
-        while combos.contains(&tmp) {
-            // tmp = fields.choose_multiple(&mut rng, 2).fold(0, |acc, x| {acc | *x});
-            tmp = rng.random_slice_entry(&fields).unwrap() | rng.random_slice_entry(&fields).unwrap();
+    let kinds_of = |ingredients: &[RawIngredient]| -> Vec<PotionKind> {
+        let mut kinds: Vec<PotionKind> = ingredients.iter().map(|i| i.kind).collect();
+        kinds.sort();
+        kinds
+    };
+    // Постоянные рецепты заняты с самого начала, случайные не должны с ними спорить
+    let fixed: Vec<Vec<PotionKind>> = recipes.iter().filter(|r| r.random.is_none()).map(|r| kinds_of(&r.ingredients)).collect();
+    let mut taken: Vec<Vec<PotionKind>> = vec![];
+
+    recipes.iter().map(|recipe| {
+        let Some(random) = &recipe.random else {
+            return (recipe.ingredients.clone(), recipe.temperature, recipe.outputs.as_slice());
+        };
+        let mut roll = || {
+            // Без повторов: "огонь + огонь" спорил бы с рецептами из одного огня
+            let mut pool = random.from.clone();
+            let mut kinds: Vec<PotionKind> = (0..random.count)
+                .map(|_| pool.remove(rng.roll_dice(1, pool.len() as i32) as usize - 1))
+                .collect();
+            kinds.sort();
+            kinds
+        };
+        let mut kinds = roll();
+        // Свободных сочетаний может и не остаться, тогда повтор проиграет рецепту выше
+        for _ in 0..MAX_REROLLS {
+            if !fixed.contains(&kinds) && !taken.contains(&kinds) { break; }
+            kinds = roll();
         }
-        combos[i] = tmp;
-    }
-    
-    hashmap.insert(combos[0], PotionEffect::Invulnerability(Invulnerability { turns: 3 }));
-    hashmap.insert(combos[1], PotionEffect::Strength(Strength { turns: 3 }));
-    hashmap.insert(combos[2], PotionEffect::Heal(ProvidesHealing { heal_amount: 3 }));
+        taken.push(kinds.clone());
+        let ingredients = kinds.into_iter().map(|kind| RawIngredient { kind, count: 1, min_potency: 0 }).collect();
+        (ingredients, recipe.temperature, recipe.outputs.as_slice())
+    }).collect()
+}
 
-    hashmap
+const MAX_REROLLS: usize = 100;
 
+/// Whether the flasks together hold exactly the recipe's kinds, in as many
+/// flasks and as strong as the recipe asks for.
+fn fits(ingredients: &[RawIngredient], flasks: &[Flask]) -> bool {
+    PotionKind::all_of(&flasks.iter().flat_map(|f| f.effects.clone()).collect::<Vec<_>>()).len() == ingredients.len() && ingredients.iter().all(|ingredient| {
+        let holding: Vec<&Flask> = flasks.iter().filter(|f| f.effects.iter().any(|e| PotionKind::of(e) == ingredient.kind)).collect();
+        holding.len() as i32 >= ingredient.count && holding.iter().all(|f| f.potency >= ingredient.min_potency)
+    })
 }
 
-// fn bitfield_to_string(bitfield: u8) -> String {
-//     let variants = vec![
-//         "Health",
-//         "Teleport",
-//         "Confusion",
-//         "Harm",
-//         "Linger",
-//         "Explosion"
-//     ];
-//     let mut names = vec![];
-//     for (i, shift) in (0..=5).enumerate() {
-//         if bitfield & (1 << shift) != 0 {
-//             names.push(variants[i]);
-//         }
-//     }
-
-//     names.join(" + ")
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use rltk::Point;
+
+    use super::*;
+    use crate::raws;
+    use crate::rng::RunSeed;
+    use crate::simulation::{PlayerCommand, Simulation};
+    use crate::spawner;
+
+    fn give(sim: &mut Simulation, id: &str) -> Entity {
+        let Point { x, y } = *sim.ecs.fetch::<Point>();
+        let player = *sim.ecs.fetch::<Entity>();
+        let item = spawner::spawn_named(&mut sim.ecs, id, x, y).expect("Unable to spawn item");
+        sim.ecs.write_storage::<Position>().remove(item);
+        sim.ecs.write_storage::<InBackpack>().insert(item, InBackpack { owner: player }).expect("Unable to insert backpack");
+        item
+    }
+
+    #[test]
+    fn only_flasks_in_the_mixers_backpack_can_be_mixed() {
+        let mut sim = Simulation::new(RunSeed(1), raws::load().expect("Unable to load raws"));
+        sim.run_until_input();
+        let Point { x, y } = *sim.ecs.fetch::<Point>();
+        let first = spawner::spawn_named(&mut sim.ecs, "health_potion", x, y).expect("Unable to spawn potion");
+        let second = spawner::spawn_named(&mut sim.ecs, "potion_of_fire", x, y).expect("Unable to spawn potion");
+
+        // На полу, а не в рюкзаке
+        sim.play(PlayerCommand::Mix { first, second });
+        assert!(sim.ecs.is_alive(first) && sim.ecs.is_alive(second));
+
+        let player = *sim.ecs.fetch::<Entity>();
+        for flask in [first, second] {
+            sim.ecs.write_storage::<Position>().remove(flask);
+            sim.ecs.write_storage::<InBackpack>().insert(flask, InBackpack { owner: player }).expect("Unable to insert backpack");
+        }
+        sim.play(PlayerCommand::Mix { first, second });
+        assert!(!sim.ecs.is_alive(first) && !sim.ecs.is_alive(second));
+    }
+
+    #[test]
+    fn diluted_flasks_cannot_be_distilled_back() {
+        let mut sim = Simulation::new(RunSeed(1), raws::load().expect("Unable to load raws"));
+        sim.run_until_input();
+        let Point { x, y } = *sim.ecs.fetch::<Point>();
+        {
+            let mut map = sim.ecs.write_resource::<Map>();
+            let idx = map.xy_idx(x + 1, y);
+            map.tiles[idx] = TileType::ShallowWater;
+        }
+        let station = spawner::spawn_named(&mut sim.ecs, "cauldron", x, y).expect("Unable to spawn station");
+        let player = *sim.ecs.fetch::<Entity>();
+        let heat = |flask: Entity| {
+            let mut ingredients = [None; crate::simulation::STATION_SLOTS];
+            ingredients[0] = Some(flask);
+            PlayerCommand::Brew { station, ingredients, temperature: Temperature::Heated }
+        };
+
+        let potion = give(&mut sim, "health_potion");
+        sim.play(PlayerCommand::Dilute { item: potion });
+        let halves: Vec<(Entity, i32)> = {
+            let entities = sim.ecs.entities();
+            let backpacks = sim.ecs.read_storage::<InBackpack>();
+            let potions = sim.ecs.read_storage::<Potion>();
+            let weights = sim.ecs.read_storage::<Weight>();
+            (&entities, &backpacks, &potions, &weights).join()
+                .filter(|(_, pack, potion, _)| pack.owner == player && potion.potency == MIN_POTENCY)
+                .map(|(e, _, _, w)| (e, w.0))
+                .collect()
+        };
+        assert_eq!(halves.len(), 2);
+        assert!(halves.iter().all(|(_, w)| *w == 1), "a flask of weight 1 splits into two of weight 1");
+
+        sim.play(heat(halves[0].0));
+        assert!(sim.ecs.is_alive(halves[0].0));
+        assert_eq!(sim.ecs.read_storage::<Potion>().get(halves[0].0).map(|p| p.potency), Some(MIN_POTENCY));
+
+        // Неразбавленная фляга уваривается как прежде
+        let potion = give(&mut sim, "health_potion");
+        sim.play(heat(potion));
+        assert!(!sim.ecs.is_alive(potion));
+    }
+
+    #[test]
+    fn random_recipes_draw_kinds_no_other_recipe_uses() {
+        let raws = raws::load().expect("Unable to load raws");
+        for seed in 0..200 {
+            let recipes = run_recipes(&raws.recipes, seed);
+            let kinds: Vec<Vec<PotionKind>> = recipes.iter()
+                .map(|(ingredients, _, _)| {
+                    let mut kinds: Vec<PotionKind> = ingredients.iter().map(|i| i.kind).collect();
+                    kinds.sort();
+                    kinds
+                })
+                .collect();
+            for (i, (raw, drawn)) in raws.recipes.iter().zip(kinds.iter()).enumerate() {
+                if let Some(random) = &raw.random {
+                    let mut distinct = drawn.clone();
+                    distinct.dedup();
+                    assert_eq!(distinct.len(), random.count as usize, "seed {}: {} drew a kind twice", seed, raw.id);
+                }
+                assert!(!kinds[..i].contains(drawn), "seed {}: {} has the same kinds as another recipe", seed, raw.id);
+            }
+        }
+    }
+}
@@ -7,6 +7,8 @@ use serde::{Serialize, Deserialize};
 use specs::saveload::{Marker, ConvertSaveload};
 use rltk::{Point, RGB};
 
+use crate::map::GasType;
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct Position {
     pub x: i32,
@@ -45,6 +47,13 @@ pub struct Lobber {
     pub targetpos: Option<Point>
 }
 
+// Оставляет за собой облако газа
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Exhales {
+    pub gas: GasType,
+    pub density: i32
+}
+
 #[derive(Component, Debug, ConvertSaveload, Clone, Copy)]
 pub struct Boss {
     pub state: BossState,
@@ -69,6 +78,23 @@ pub struct Name {
 #[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct BlocksTile {}
 
+/// Furniture to brew at: up to `slots` ingredients at once, heated or cooled
+/// if the station can. A brew keeps the player busy for `turns` turns, one
+/// more when heated or cooled.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct AlchemyStation {
+    pub slots: i32,
+    pub heat: bool,
+    pub cool: bool,
+    pub turns: i32
+}
+
+/// The player is busy brewing and skips that many more turns.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Busy {
+    pub turns: i32
+}
+
 #[derive(Component, Debug, ConvertSaveload, Clone)]
 pub struct CombatStats {
     pub max_hp: i32,
@@ -104,26 +130,76 @@ pub struct Item {}
 #[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Consumable {}
 
-/// Лужа зелья
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy)]
-pub struct Puddle { 
-    pub lifetime: i32 
+/// `potency` is how strong the flask is, from `MIN_POTENCY` (weak) to
+/// `MAX_POTENCY` (concentrated). The effects on the potion are already that
+/// strong, see `PotionEffect::rescaled`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Potion {
+    pub potency: i32
+}
+
+pub const MIN_POTENCY: i32 = 1;
+pub const STANDARD_POTENCY: i32 = 2;
+pub const MAX_POTENCY: i32 = 4;
+
+impl Potion {
+    pub fn standard() -> Potion {
+        Potion { potency: STANDARD_POTENCY }
+    }
+
+    fn tier(&self) -> Option<&'static str> {
+        match self.potency {
+            ..=MIN_POTENCY => Some("Weak"),
+            STANDARD_POTENCY => None,
+            MAX_POTENCY.. => Some("Concentrated"),
+            _ => Some("Strong")
+        }
+    }
+
+    /// The name with the potency in front, "Weak health potion"; ordinary flasks keep the name as is.
+    pub fn name(&self, name: &str) -> String {
+        let Some(tier) = self.tier() else { return name.to_owned() };
+        let mut chars = name.chars();
+        let rest = chars.next().map_or(String::new(), |first| first.to_lowercase().chain(chars).collect());
+        format!("{} {}", tier, rest)
+    }
+
+    /// The other way round: the name of this flask without its potency.
+    pub fn plain_name(&self, name: &str) -> String {
+        let Some(rest) = self.tier().and_then(|tier| name.strip_prefix(tier)).and_then(|rest| rest.strip_prefix(' ')) else { return name.to_owned() };
+        let mut chars = rest.chars();
+        chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+    }
 }
 
+/// Watered down by `DilutionSystem`. The water does not boil off, so the
+/// flask cannot be distilled back up
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Diluted {}
+
+/// A flask the player cannot tell yet: `Name` and `Renderable` show its
+/// looks, the real name and colour wait here, without the potency. See `IdentifySystem`
 #[derive(Component, Serialize, Deserialize, Clone, Debug)]
-pub struct Potion {}
+pub struct Unidentified {
+    pub name: String,
+    pub fg: RGB
+}
+
+/// Tells what the potions in the backpack are
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Identifies {}
 //
 // ---=== Эффекты мобов / эффекты зелий ===---
 //
 
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ProvidesHealing {
     pub heal_amount: i32,
 }
 
 /// Safe teleport will always transport you to an empty place.
 /// Unsafe may teleport you inside a wall.
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Teleport {
     pub safe: bool
 }
@@ -132,7 +208,7 @@ pub struct Teleport {
 pub enum LingerType { Fire, Poison }
 
 // Продолжительный наносящий урон эффект (огонь, отравление)
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LingeringEffect {
     pub etype: LingerType,
     pub duration: i32,
@@ -140,24 +216,24 @@ pub struct LingeringEffect {
 }
 
 // Моментальный урон. Имеет особое взаимодействие с зельем лечения...
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct InstantHarm {
     pub dmg: i32,
 }
 
 // Boom!
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Explosion {
     pub maxdmg: i32,
     pub radius: i32
 }
 
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Invulnerability {
     pub turns: i32
 }
 
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Strength {
     pub turns: i32
 }
@@ -188,16 +264,34 @@ pub struct WantsToDropItem {
     pub item: Entity,
 }
 
+/// Split a potion in two weaker ones with water from a nearby tile.
+#[derive(Component, Debug, ConvertSaveload)]
+pub struct WantsToDilute {
+    pub item: Entity
+}
+
 #[derive(Component, Debug, ConvertSaveload)]
 pub struct WantsToThrowItem {
     pub item: Entity,
     pub target: Point
 }
 
-#[derive(Component, Debug, ConvertSaveload)]
+/// Two flasks mixed in the field, or up to `AlchemyStation::slots` at a station.
+/// Lives for one turn only and is not saved, see `register_components`.
+#[derive(Component, Debug)]
 pub struct WantsToMixPotions {
-    pub first: Entity,
-    pub second: Entity
+    pub ingredients: Vec<Entity>,
+    pub station: Option<Entity>,
+    pub temperature: Temperature
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Temperature {
+    #[default]
+    Room,
+    Heated,
+    Cooled
 }
 
 #[derive(Component, Debug, ConvertSaveload, Clone)]
@@ -215,7 +309,7 @@ pub struct AreaOfEffect {
     pub radius: i32
 }
 
-#[derive(Component, Debug, ConvertSaveload, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Component, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Confusion {
     pub turns: i32
 }
@@ -235,8 +329,3 @@ pub struct ParticleLifetime {
 }
 
 pub struct SerializeMe;
-
-#[derive(Component, Serialize, Deserialize, Clone)]
-pub struct SerializationHelper {
-    pub map : super::map::Map
-}
@@ -1,7 +1,7 @@
 use rltk::Point;
 use specs::prelude::*;
 
-use crate::{components::{Boss, CombatStats, Invulnerability, Name, Player, Position, Potion, SufferDamage, WantsToThrowItem}, gamelog::GameLog, map::{Map, TileType}, RunState};
+use crate::{components::{Boss, CombatStats, Invulnerability, Name, Player, Position, Potion, SufferDamage, WantsToThrowItem}, gameevent::{GameEvent, GameEvents}, map::{Map, TileType}, RunState};
 
 pub struct DamageSystem {}
 
@@ -12,14 +12,16 @@ impl<'a> System<'a> for DamageSystem {
                         WriteStorage<'a, WantsToThrowItem>,
                         ReadStorage<'a, Invulnerability>,
                         Entities<'a>,
-                        ReadStorage<'a, Position>);
+                        ReadStorage<'a, Position>,
+                        WriteExpect<'a, GameEvents>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut stats, mut damage, potions, mut intentthrow, invuln, entities, pos) = data;
+        let (mut stats, mut damage, potions, mut intentthrow, invuln, entities, pos, mut events) = data;
 
-        for (stats, damage, _invul) in (&mut stats, &damage, !&invuln).join() {
+        for (target, stats, damage, _invul) in (&entities, &mut stats, &damage, !&invuln).join() {
             for dmg in damage.amount.iter() {
                 stats.hp = stats.hp.saturating_sub(*dmg);
+                events.emit(GameEvent::Damaged { target, amount: *dmg });
             }
         }
 
@@ -44,24 +46,21 @@ pub fn clean_up_dead(ecs: &mut World, runstate: RunState) -> RunState {
         let combat_stats = ecs.read_storage::<CombatStats>();
         let players = ecs.read_storage::<Player>();
         let entities = ecs.entities();
-        let mut log = ecs.fetch_mut::<GameLog>();
+        let mut events = ecs.fetch_mut::<GameEvents>();
         for (entity, stats) in (&entities, &combat_stats).join() {
             if stats.hp < 1 { 
                 let player = players.get(entity);
+                let name = names.get(entity).map_or("someone".to_owned(), |n| n.name.clone());
                 match player {
                     None => { 
                         is_boss_dead = boss.contains(entity);
-                        if !is_boss_dead {
-                            if let Some(victim_name) = names.get(entity) {
-                                log.entries.push(format!("{} dies!", &victim_name.name));
-                            }
-                        }
+                        events.emit(GameEvent::Died { entity, name, boss: is_boss_dead });
                         dead.push(entity);
                     },
                     Some(_p) => {
-                        let msg_dead = "You are dead!".to_string();
-                        if log.entries.iter().last().is_some_and(|msg| msg != &msg_dead) {
-                            log.entries.push(msg_dead);
+                        // Мёртвый игрок остаётся в мире, сообщаем о смерти один раз
+                        if runstate != RunState::GameOver {
+                            events.emit(GameEvent::Died { entity, name, boss: false });
                         }
                         is_player_dead = true;
                     },
@@ -93,10 +92,6 @@ pub fn boss_dead(ecs: &mut World) {
             }
         }
     }
-    let mut log = ecs.write_resource::<GameLog>();
-    log.entries.push(String::new());
-    log.entries.push("\"You are a fool... You'll never leave...\"".to_owned());
-    log.entries.push("The Cursed Alchemist dies!".to_owned());
-    log.entries.push("You hear a rumbling sound; the door to the chamber opens!".to_owned());
+    ecs.write_resource::<GameEvents>().emit(GameEvent::FinalDoorOpened);
     
 }
\ No newline at end of file
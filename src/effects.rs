@@ -0,0 +1,417 @@
+use std::collections::VecDeque;
+
+use rltk::{Algorithm2D, BaseMap, Point, RGB};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use crate::components::{BlocksTile, CombatStats, Confusion, Explosion, InstantHarm, Invulnerability, Item, LingeringEffect, Name, Position, ProvidesHealing, Strength, SufferDamage, Teleport, Viewshed};
+use crate::gameevent::{GameEvent, GameEvents};
+use crate::gas_system;
+use crate::map::{tile_hazard, tile_opaque, Gas, GasType, Map, TileType, MAPWIDTH};
+use crate::map_builders::distances_from;
+use crate::particle_system::ParticleBuilder;
+use crate::rng::RandomStreams;
+
+/// Эффект зелья. На зелье лежит компонентом, в очередь попадает этим enum-ом.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum PotionEffect {
+    Heal(ProvidesHealing),
+    Teleport(Teleport),
+    Confusion(Confusion),
+    Harm(InstantHarm),
+    Linger(LingeringEffect),
+    Explosion(Explosion),
+    Invulnerability(Invulnerability),
+    Strength(Strength)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EffectType {
+    Potion(PotionEffect),
+    Damage { amount: i32 },
+    /// Teleports the items on the target tiles, for Teleport spilled into a reaction
+    Scatter
+}
+
+/// How the effect reached its targets.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EffectSource {
+    Quaff,
+    Splash,
+    Puddle,
+    Explosion,
+    Melee,
+    Mix,
+    /// Burning and poison ticks, fire spreading
+    Lingering,
+    /// Lava, burning grass
+    Terrain,
+    /// Different spills meeting on the floor
+    Reaction,
+    /// Standing in a gas cloud
+    Gas
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Targets {
+    Single(Entity),
+    Tile(Point),
+    Tiles(Vec<Point>)
+}
+
+pub struct EffectRequest {
+    pub effect: EffectType,
+    pub source: EffectSource,
+    pub creator: Option<Entity>,
+    pub targets: Targets
+}
+
+/// Every effect in the game goes through this queue and is applied by `run_effects_queue`.
+pub struct EffectQueue {
+    requests: VecDeque<EffectRequest>
+}
+
+impl EffectQueue {
+    pub fn new() -> EffectQueue {
+        EffectQueue { requests: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, effect: EffectType, source: EffectSource, creator: Option<Entity>, targets: Targets) {
+        self.requests.push_back(EffectRequest { effect, source, creator, targets });
+    }
+}
+
+/// Storages of every potion effect component, for systems that need to read a potion.
+pub type PotionStorages<'a> = ( ReadStorage<'a, ProvidesHealing>,
+                                ReadStorage<'a, Teleport>,
+                                ReadStorage<'a, Confusion>,
+                                ReadStorage<'a, InstantHarm>,
+                                ReadStorage<'a, LingeringEffect>,
+                                ReadStorage<'a, Explosion>,
+                                ReadStorage<'a, Invulnerability>,
+                                ReadStorage<'a, Strength> );
+
+pub fn potion_effects(storages: &PotionStorages, item: Entity) -> Vec<PotionEffect> {
+    let (heal, tp, confusion, harm, linger, explosion, invuln, strength) = storages;
+    let mut effects = vec![];
+
+    if let Some(h) = heal.get(item)      { effects.push(PotionEffect::Heal(*h)); }
+    if let Some(t) = tp.get(item)        { effects.push(PotionEffect::Teleport(*t)); }
+    if let Some(c) = confusion.get(item) { effects.push(PotionEffect::Confusion(*c)); }
+    if let Some(h) = harm.get(item)      { effects.push(PotionEffect::Harm(*h)); }
+    if let Some(l) = linger.get(item)    { effects.push(PotionEffect::Linger(*l)); }
+    if let Some(e) = explosion.get(item) { effects.push(PotionEffect::Explosion(*e)); }
+    if let Some(i) = invuln.get(item)    { effects.push(PotionEffect::Invulnerability(*i)); }
+    if let Some(s) = strength.get(item)  { effects.push(PotionEffect::Strength(*s)); }
+
+    effects
+}
+
+impl PotionEffect {
+    /// Puts the effect on a potion entity as a component.
+    pub fn attach(self, lazy: &LazyUpdate, potion: Entity) {
+        match self {
+            PotionEffect::Heal(h) => lazy.insert(potion, h),
+            PotionEffect::Teleport(t) => lazy.insert(potion, t),
+            PotionEffect::Confusion(c) => lazy.insert(potion, c),
+            PotionEffect::Harm(h) => lazy.insert(potion, h),
+            PotionEffect::Linger(l) => lazy.insert(potion, l),
+            PotionEffect::Explosion(e) => lazy.insert(potion, e),
+            PotionEffect::Invulnerability(i) => lazy.insert(potion, i),
+            PotionEffect::Strength(s) => lazy.insert(potion, s),
+        }
+    }
+
+    /// The effect of a `from` potion made as strong as a `to` one: magnitudes
+    /// and durations grow or shrink in proportion, never below 1.
+    pub fn rescaled(self, from: i32, to: i32) -> PotionEffect {
+        let scale = |value: i32| ((value * to + from / 2) / from).max(1);
+        match self {
+            PotionEffect::Heal(mut h) => { h.heal_amount = scale(h.heal_amount); PotionEffect::Heal(h) }
+            PotionEffect::Teleport(t) => PotionEffect::Teleport(t),
+            PotionEffect::Confusion(mut c) => { c.turns = scale(c.turns); PotionEffect::Confusion(c) }
+            PotionEffect::Harm(mut h) => { h.dmg = scale(h.dmg); PotionEffect::Harm(h) }
+            PotionEffect::Linger(mut l) => {
+                l.duration = scale(l.duration);
+                l.dmg = scale(l.dmg);
+                PotionEffect::Linger(l)
+            }
+            PotionEffect::Explosion(mut e) => { e.maxdmg = scale(e.maxdmg); PotionEffect::Explosion(e) }
+            PotionEffect::Invulnerability(mut i) => { i.turns = scale(i.turns); PotionEffect::Invulnerability(i) }
+            PotionEffect::Strength(mut s) => { s.turns = scale(s.turns); PotionEffect::Strength(s) }
+        }
+    }
+
+    /// Same as `attach`, for an entity that is still being built.
+    pub fn attach_to<B: Builder>(self, builder: B) -> B {
+        match self {
+            PotionEffect::Heal(h) => builder.with(h),
+            PotionEffect::Teleport(t) => builder.with(t),
+            PotionEffect::Confusion(c) => builder.with(c),
+            PotionEffect::Harm(h) => builder.with(h),
+            PotionEffect::Linger(l) => builder.with(l),
+            PotionEffect::Explosion(e) => builder.with(e),
+            PotionEffect::Invulnerability(i) => builder.with(i),
+            PotionEffect::Strength(s) => builder.with(s),
+        }
+    }
+}
+
+/// Applies everything in the queue, including effects queued while resolving
+/// (an explosion queues damage for whoever is caught in it).
+///
+/// Potion effects only work on creatures (anything with `CombatStats`);
+/// plain damage hits any entity, so explosions still shatter potions lying around.
+pub fn run_effects_queue(ecs: &mut World) {
+    loop {
+        let request = ecs.write_resource::<EffectQueue>().requests.pop_front();
+        match request {
+            Some(request) => apply_effect(ecs, request),
+            None => break
+        }
+    }
+}
+
+fn apply_effect(ecs: &mut World, request: EffectRequest) {
+    match request.effect {
+        EffectType::Damage { amount } => {
+            let mut suffer = ecs.write_storage::<SufferDamage>();
+            for target in target_entities(ecs, &request.targets) {
+                SufferDamage::new_damage(&mut suffer, target, amount);
+            }
+        }
+        EffectType::Scatter => {
+            let items: Vec<Entity> = {
+                let items = ecs.read_storage::<Item>();
+                target_entities(ecs, &request.targets).into_iter().filter(|e| items.contains(*e)).collect()
+            };
+            for item in items {
+                teleport(ecs, item, Teleport { safe: true });
+            }
+        }
+        EffectType::Potion(PotionEffect::Explosion(boom)) => {
+            for center in target_points(ecs, &request.targets) {
+                explode(ecs, center, boom, request.creator);
+            }
+        }
+        EffectType::Potion(effect) => {
+            let creatures: Vec<Entity> = {
+                let stats = ecs.read_storage::<CombatStats>();
+                target_entities(ecs, &request.targets).into_iter().filter(|e| stats.contains(*e)).collect()
+            };
+            for target in creatures {
+                apply_potion_effect(ecs, effect, request.source, target);
+            }
+        }
+    }
+}
+
+fn target_entities(ecs: &World, targets: &Targets) -> Vec<Entity> {
+    let map = ecs.fetch::<Map>();
+    match targets {
+        Targets::Single(entity) => vec![*entity],
+        Targets::Tile(point) => map.tile_content[map.xy_idx(point.x, point.y)].clone(),
+        Targets::Tiles(points) => points.iter()
+                                        .flat_map(|p| map.tile_content[map.xy_idx(p.x, p.y)].iter().copied())
+                                        .collect()
+    }
+}
+
+fn target_points(ecs: &World, targets: &Targets) -> Vec<Point> {
+    match targets {
+        Targets::Single(entity) => ecs.read_storage::<Position>()
+                                      .get(*entity)
+                                      .map_or(vec![], |pos| vec![Point::new(pos.x, pos.y)]),
+        Targets::Tile(point) => vec![*point],
+        Targets::Tiles(points) => points.clone()
+    }
+}
+
+fn apply_potion_effect(ecs: &mut World, effect: PotionEffect, source: EffectSource, target: Entity) {
+    let target_pos = ecs.read_storage::<Position>().get(target).map(|p| Point::new(p.x, p.y));
+    let target_name = ecs.read_storage::<Name>().get(target).map_or("someone".to_owned(), |n| n.name.clone());
+
+    match effect {
+        PotionEffect::Heal(healing) => {
+            if let Some(stats) = ecs.write_storage::<CombatStats>().get_mut(target) {
+                stats.hp = i32::min(stats.max_hp, stats.hp + healing.heal_amount);
+            }
+            if let Some(pos) = target_pos {
+                ecs.fetch_mut::<ParticleBuilder>().request(pos.x, pos.y, RGB::named(rltk::RED), RGB::named(rltk::BLACK), rltk::to_cp437('♥'), 200.0);
+            }
+            ecs.fetch_mut::<GameEvents>().emit(GameEvent::Healed { target, amount: healing.heal_amount });
+        }
+        PotionEffect::Harm(harming) => {
+            ecs.fetch_mut::<GameEvents>().emit(GameEvent::Harmed { target, name: target_name });
+            if let Some(pos) = target_pos {
+                ecs.fetch_mut::<ParticleBuilder>().request(pos.x, pos.y, RGB::named(rltk::VIOLETRED), RGB::named(rltk::BLACK), rltk::to_cp437('!'), 100.0);
+            }
+            SufferDamage::new_damage(&mut ecs.write_storage::<SufferDamage>(), target, harming.dmg);
+        }
+        PotionEffect::Teleport(teleporting) => teleport(ecs, target, teleporting),
+        PotionEffect::Confusion(confusion) => {
+            if add_status(ecs, target, source, confusion) {
+                if let Some(pos) = target_pos {
+                    ecs.fetch_mut::<ParticleBuilder>().request(pos.x, pos.y, RGB::named(rltk::PINK), RGB::named(rltk::BLACK), rltk::to_cp437('?'), 200.0);
+                }
+                ecs.fetch_mut::<GameEvents>().emit(GameEvent::Confused { target, name: target_name });
+            }
+        }
+        PotionEffect::Linger(lingering) => { add_status(ecs, target, source, lingering); }
+        PotionEffect::Invulnerability(invul) => {
+            if add_status(ecs, target, source, invul) {
+                ecs.fetch_mut::<GameEvents>().emit(GameEvent::BecameInvulnerable { target });
+            }
+        }
+        PotionEffect::Strength(strong) => {
+            if add_status(ecs, target, source, strong) {
+                ecs.fetch_mut::<GameEvents>().emit(GameEvent::BecameStronger { target });
+            }
+        }
+        // Взрыв обрабатывается отдельно: бьёт по площади, а не по существу
+        PotionEffect::Explosion(_) => {}
+    }
+}
+
+// Лужа и газ не обновляют уже действующий статус, иначе стоящий в них горел бы вечно
+fn add_status<C: Component>(ecs: &World, target: Entity, source: EffectSource, status: C) -> bool {
+    let mut storage = ecs.write_storage::<C>();
+    if matches!(source, EffectSource::Puddle | EffectSource::Gas) && storage.contains(target) {
+        return false;
+    }
+    storage.insert(target, status).expect("Unable to insert status effect");
+    true
+}
+
+// Взрыв останавливают стены, но не дым
+struct BlastView<'a>(&'a Map);
+
+impl Algorithm2D for BlastView<'_> {
+    fn dimensions(&self) -> Point {
+        self.0.dimensions()
+    }
+}
+
+impl BaseMap for BlastView<'_> {
+    fn is_opaque(&self, idx: usize) -> bool {
+        tile_opaque(self.0.tiles[idx])
+    }
+}
+
+fn explode(ecs: &mut World, center: Point, boom: Explosion, creator: Option<Entity>) {
+    let mut blast_tiles = {
+        let map = ecs.fetch::<Map>();
+        let mut tiles = rltk::field_of_view(center, boom.radius, &BlastView(&map));
+        tiles.retain(|p| p.x > 0 && p.x < map.width-1 && p.y > 0 && p.y < map.height-1);
+        tiles
+    };
+    blast_tiles.sort_by_key(|p| (p.y, p.x));
+
+    // Взрыв поджигает пары и траву и оставляет после себя дым
+    {
+        let mut map = ecs.fetch_mut::<Map>();
+        let mut effects = ecs.write_resource::<EffectQueue>();
+        let mut pbuilder = ecs.fetch_mut::<ParticleBuilder>();
+        let mut events = ecs.fetch_mut::<GameEvents>();
+        for tile in blast_tiles.iter() {
+            let idx = map.xy_idx(tile.x, tile.y);
+            gas_system::ignite(&mut map, idx, &mut effects, &mut pbuilder, &mut events);
+            map.set_alight(idx);
+        }
+        let idx = map.xy_idx(center.x, center.y);
+        map.release_gas(idx, Gas { gtype: GasType::Smoke, density: boom.radius * 20 });
+    }
+
+    for tile in blast_tiles.iter() {
+        ecs.fetch_mut::<ParticleBuilder>().request(tile.x, tile.y, RGB::named(rltk::ORANGE), RGB::named(rltk::BLACK), rltk::to_cp437('░'), 200.0);
+
+        let distance = rltk::DistanceAlg::Pythagoras.distance2d(center, *tile).round().clamp(1.0, 999.0);
+        let dmg = boom.maxdmg / (2.0f32 * distance) as i32;
+
+        {
+            let map = ecs.fetch::<Map>();
+            let names = ecs.read_storage::<Name>();
+            let mut events = ecs.fetch_mut::<GameEvents>();
+            for mob in map.tile_content[map.xy_idx(tile.x, tile.y)].iter() {
+                if let Some(name) = names.get(*mob) {
+                    events.emit(GameEvent::CaughtInExplosion { target: *mob, name: name.name.clone() });
+                }
+            }
+        }
+
+        ecs.write_resource::<EffectQueue>().push(EffectType::Damage { amount: dmg }, EffectSource::Explosion, creator, Targets::Tile(*tile));
+    }
+}
+
+fn teleport(ecs: &mut World, target: Entity, teleporting: Teleport) {
+    let player_entity = *ecs.fetch::<Entity>();
+    let (x, y) = {
+        let map = ecs.fetch::<Map>();
+        let mut rng = ecs.write_resource::<RandomStreams>();
+        let from = ecs.read_storage::<Position>().get(target).map(|pos| map.xy_idx(pos.x, pos.y));
+
+        match from {
+            // Безопасный телепорт не забросит в замурованный карман, в лаву, пропасть, глубокую воду или на другого
+            Some(from) if teleporting.safe => {
+                let positions = ecs.read_storage::<Position>();
+                let blockers = ecs.read_storage::<BlocksTile>();
+                let occupied: Vec<usize> = (&positions, &blockers).join().map(|(pos, _)| map.xy_idx(pos.x, pos.y)).collect();
+                let safe: Vec<usize> = distances_from(&map, from).iter().enumerate()
+                    .filter(|(idx, distance)| {
+                        distance.is_some()
+                            && *idx != from
+                            && !tile_hazard(map.tiles[*idx])
+                            && map.tiles[*idx] != TileType::DeepWater
+                            && !occupied.contains(idx)
+                    })
+                    .map(|(idx, _)| idx)
+                    .collect();
+                let idx = if safe.is_empty() { from } else { safe[rng.combat.roll_dice(1, safe.len() as i32) as usize - 1] };
+                ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32)
+            }
+            _ => (rng.combat.roll_dice(1, map.width-2)+1, rng.combat.roll_dice(1, map.height-2)+1)
+        }
+    };
+
+    let names = ecs.read_storage::<Name>();
+    let causer = names.get(target).map_or("someone", |n| &n.name);
+    let mut events = ecs.fetch_mut::<GameEvents>();
+
+    if let Some(pos) = ecs.write_storage::<Position>().get_mut(target) {
+        pos.x = x;
+        pos.y = y;
+        // О разбросанных предметах не пишем
+        if ecs.read_storage::<CombatStats>().contains(target) {
+            events.emit(GameEvent::Teleported { target, name: causer.to_owned() });
+        }
+    }
+    if target == player_entity {
+        *ecs.write_resource::<Point>() = Point::new(x, y);
+    }
+    if let Some(vs) = ecs.write_storage::<Viewshed>().get_mut(target) {
+        vs.dirty = true;
+    }
+
+    let map = ecs.fetch::<Map>();
+    let idx = map.xy_idx(x, y);
+    if map.tiles[idx] == TileType::Wall {
+        if let Some(stats) = ecs.write_storage::<CombatStats>().get_mut(target) {
+            stats.hp = 0;
+        }
+        events.emit(GameEvent::Suffocated { target });
+    } else if ecs.read_storage::<CombatStats>().contains(target) {
+        // Разбросанные предметы никого не телефрагают
+        let stats = ecs.read_storage::<CombatStats>();
+        let mut suffer = ecs.write_storage::<SufferDamage>();
+        for mob in map.tile_content[idx].iter().filter(|e| **e != target && stats.contains(**e)) {
+            SufferDamage::new_damage(&mut suffer, *mob, i32::MAX);
+            let victim = names.get(*mob).map_or("someone", |n| &n.name);
+            events.emit(GameEvent::Telefragged {
+                attacker: target,
+                attacker_name: causer.to_owned(),
+                victim: *mob,
+                victim_name: victim.to_owned()
+            });
+        }
+    }
+}
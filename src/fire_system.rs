@@ -0,0 +1,101 @@
+use specs::prelude::*;
+
+use crate::{components::{CombatStats, Item, LingerType, LingeringEffect, Name, Position, Viewshed}, effects::{EffectQueue, EffectSource, EffectType, PotionEffect, Targets}, gameevent::{GameEvent, GameEvents}, map::{self, Map, TileType, MAPCOUNT, MAPHEIGHT, MAPWIDTH}, rng::RandomStreams};
+
+// Горящая трава жжёт слабее лавы, зато её много
+const TILE_FIRE: LingeringEffect = LingeringEffect { etype: LingerType::Fire, duration: 2, dmg: 2 };
+/// One in how many turns lava sets a flammable neighbour alight.
+const LAVA_SPARK: i32 = 10;
+
+/// Fire on the map. Tiles with fuel (tall grass) are set alight by burning
+/// creatures and spilled Fire on them, by lava next to them, by explosions
+/// and by burning vapour. A burning tile sets each flammable neighbour alight
+/// with a 50% chance a turn, and turns to ash once its fuel is spent.
+///
+/// Whoever stands on a burning tile catches fire, flasks lying there crack.
+/// Burning tiles light up the floor around them, see `VisibilitySystem`,
+/// so viewsheds are redone while anything burns.
+pub struct FireSystem {}
+
+impl<'a> System<'a> for FireSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = ( WriteExpect<'a, Map>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, CombatStats>,
+                        ReadStorage<'a, Item>,
+                        ReadStorage<'a, Name>,
+                        ReadStorage<'a, LingeringEffect>,
+                        WriteStorage<'a, Viewshed>,
+                        WriteExpect<'a, RandomStreams>,
+                        WriteExpect<'a, GameEvents>,
+                        WriteExpect<'a, EffectQueue>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, positions, combat, items, names, linger, mut viewsheds, mut rng, mut events, mut effects) = data;
+        let had_fire = map.fire.iter().any(|fuel| *fuel > 0);
+
+        for (pos, _) in (&positions, &linger).join().filter(|(_, l)| l.etype == LingerType::Fire) {
+            let idx = map.xy_idx(pos.x, pos.y);
+            map.set_alight(idx);
+        }
+        for idx in 0..MAPCOUNT {
+            if map.fluids[idx].as_ref().is_some_and(|fluid| fluid.burns()) {
+                map.set_alight(idx);
+            }
+        }
+
+        // Новые очаги загораются со следующего хода, иначе огонь пробежит всю траву разом
+        let mut catching = vec![];
+        for idx in 0..MAPCOUNT {
+            let (lava, burning) = (map.tiles[idx] == TileType::Lava, map.fire[idx] > 0);
+            if !lava && !burning { continue; }
+            for n in neighbours(idx) {
+                if map.fire[n] > 0 || map::tile_fuel(map.tiles[n]) <= 0 { continue; }
+                let chance = if burning { 2 } else { LAVA_SPARK };
+                if rng.combat.roll_dice(1, chance) == 1 {
+                    catching.push(n);
+                }
+            }
+        }
+
+        for idx in 0..MAPCOUNT {
+            if map.fire[idx] <= 0 { continue; }
+            for ent in map.tile_content[idx].iter() {
+                if combat.contains(*ent) {
+                    let on_fire = linger.get(*ent).is_some_and(|l| l.etype == LingerType::Fire);
+                    if !on_fire {
+                        let name = names.get(*ent).map_or("someone".to_owned(), |n| n.name.clone());
+                        events.emit(GameEvent::Ignited { target: *ent, name });
+                    }
+                    effects.push(EffectType::Potion(PotionEffect::Linger(TILE_FIRE)), EffectSource::Terrain, None, Targets::Single(*ent));
+                } else if items.contains(*ent) {
+                    effects.push(EffectType::Damage { amount: 1 }, EffectSource::Terrain, None, Targets::Single(*ent));
+                }
+            }
+
+            map.fire[idx] -= 1;
+            if map.fire[idx] == 0 {
+                map.tiles[idx] = TileType::Ash;
+            }
+        }
+
+        for idx in catching {
+            map.set_alight(idx);
+        }
+
+        if had_fire || map.fire.iter().any(|fuel| *fuel > 0) {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
+
+/// All eight neighbours of the tile within the map; fire jumps across corners.
+fn neighbours(idx: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+    (-1..=1).flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |(nx, ny)| (*nx, *ny) != (x, y) && *nx >= 0 && *nx < MAPWIDTH as i32 && *ny >= 0 && *ny < MAPHEIGHT as i32)
+        .map(|(nx, ny)| ny as usize * MAPWIDTH + nx as usize)
+}
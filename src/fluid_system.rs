@@ -0,0 +1,171 @@
+use specs::prelude::*;
+
+use rltk::Point;
+
+use crate::{alchemy_system::{brew, brew_look, Brew, Cookbook, Flask, MIX_EXPLOSION}, components::{LingerType, Temperature, STANDARD_POTENCY}, effects::{EffectQueue, EffectSource, EffectType, PotionEffect, Targets}, gameevent::{GameEvent, GameEvents}, map::{self, Fluid, Gas, GasType, Map, TileType, MAPCOUNT, MAPWIDTH}, raws::Raws, AlchemySeed};
+
+/// What a flask of weight 1 spills; heavier flasks spill more.
+pub const SPLASH_VOLUME: i32 = 12;
+
+/// Spilled potions: they work on whoever stands in them, run out over the
+/// floor and dry up.
+///
+/// Every turn a tile gives each lower neighbour a fifth of the difference,
+/// if the neighbour holds the same potion or nothing, so a spill runs until
+/// it is too thin to flow and stays deepest in dead ends, which have fewer
+/// neighbours to lose it to. Then every tile dries by one, and shallow water
+/// washes a spill away three times as fast.
+///
+/// Where two different spills touch, they react, see `react`.
+pub struct FluidSystem {}
+
+impl<'a> System<'a> for FluidSystem {
+    type SystemData = ( WriteExpect<'a, Map>,
+                        ReadExpect<'a, AlchemySeed>,
+                        ReadExpect<'a, Raws>,
+                        WriteExpect<'a, GameEvents>,
+                        WriteExpect<'a, EffectQueue>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, seed, raws, mut events, mut effects) = data;
+        let cookbook = Cookbook { recipes: &raws.recipes, seed: seed.0 };
+
+        for idx in 0..MAPCOUNT {
+            let Some(fluid) = &map.fluids[idx] else { continue; };
+            for mob in map.tile_content[idx].iter() {
+                for effect in fluid.effects.iter() {
+                    effects.push(EffectType::Potion(*effect), EffectSource::Puddle, None, Targets::Single(*mob));
+                }
+            }
+        }
+
+        let volumes: Vec<i32> = map.fluids.iter().map(|f| f.as_ref().map_or(0, |f| f.volume)).collect();
+        let mut next = volumes.clone();
+        // Откуда натекло в пустую клетку; две разные лужи в одну не текут
+        let mut sources: Vec<Option<usize>> = vec![None; MAPCOUNT];
+        let mut contacts = vec![];
+        for idx in 0..MAPCOUNT {
+            let Some(fluid) = &map.fluids[idx] else { continue; };
+            for n in map::cardinal_neighbours(idx) {
+                if !map::tile_holds_fluid(map.tiles[n]) { continue; }
+                if map.fluids[n].as_ref().is_some_and(|other| other.effects != fluid.effects) {
+                    contacts.push((idx, n));
+                    continue;
+                }
+                let flow = (volumes[idx] - volumes[n]) / 5;
+                if flow <= 0 { continue; }
+                match (&map.fluids[n], sources[n]) {
+                    (None, Some(source)) if map.fluids[source].as_ref().is_some_and(|s| s.effects != fluid.effects) => continue,
+                    (None, _) => sources[n] = Some(idx),
+                    _ => {}
+                }
+                next[idx] -= flow;
+                next[n] += flow;
+            }
+        }
+
+        let spilled = map.fluids.clone();
+        for idx in 0..MAPCOUNT {
+            let evaporation = if map.tiles[idx] == TileType::ShallowWater { 3 } else { 1 };
+            let volume = next[idx] - evaporation;
+            if volume <= 0 {
+                map.fluids[idx] = None;
+                continue;
+            }
+            if map.fluids[idx].is_none() {
+                let source = sources[idx].expect("Liquid flows in from a neighbour");
+                map.fluids[idx] = spilled[source].clone();
+            }
+            if let Some(fluid) = map.fluids[idx].as_mut() {
+                fluid.volume = volume;
+            }
+        }
+
+        for (from, to) in contacts {
+            let (Some(incoming), Some(resident)) = (&map.fluids[from], &map.fluids[to]) else { continue; };
+            // Уже смешались через другую клетку
+            if incoming.effects == resident.effects { continue; }
+            let incoming = Fluid { volume: 0, ..incoming.clone() };
+            let tiles = puddle(&map, from);
+            react(&mut map, to, incoming, &tiles, cookbook, &mut effects, &mut events);
+        }
+    }
+}
+
+/// `incoming` runs into a different spill on `idx`. They mix by the same rules
+/// as flasks in `AlchemySystem`, and the brew takes over the whole of both
+/// puddles, the resident one and `incoming_tiles`, so that they react once.
+/// Heal and Harm blow up and leave nothing; Fire with Poison gives off poison
+/// gas; a brew with Teleport scatters the items lying in it.
+pub fn react(map: &mut Map, idx: usize, incoming: Fluid, incoming_tiles: &[usize], cookbook: Cookbook, effects: &mut EffectQueue, events: &mut GameEvents) {
+    let resident = match &map.fluids[idx] {
+        Some(resident) if resident.effects != incoming.effects => resident.clone(),
+        _ => {
+            map.pour(idx, incoming);
+            return;
+        }
+    };
+    let mut tiles = puddle(map, idx);
+    tiles.extend_from_slice(incoming_tiles);
+    let points: Vec<Point> = tiles.iter().map(|t| Point::new((t % MAPWIDTH) as i32, (t / MAPWIDTH) as i32)).collect();
+
+    // В луже эффекты уже такой силы, какой были во фляге, поэтому крепость обычная
+    let flasks = [&incoming, &resident].map(|fluid| Flask { effects: fluid.effects.clone(), potency: STANDARD_POTENCY });
+    let mixed = match brew(&flasks, Temperature::Room, cookbook) {
+        Brew::Exploded => {
+            for t in tiles {
+                map.fluids[t] = None;
+            }
+            let center = Point::new((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+            effects.push(EffectType::Potion(PotionEffect::Explosion(MIX_EXPLOSION)), EffectSource::Reaction, None, Targets::Tile(center));
+            events.emit(GameEvent::SpillsMixed { exploded: true });
+            return;
+        }
+        Brew::Special(made) => made.effects,
+        // Разлитое не уваривается: одинаковые лужи не крепнут
+        Brew::Even(mixed) => mixed.at_potency(STANDARD_POTENCY).effects
+    };
+
+    let (_, color) = brew_look(&mixed, incoming.color);
+    // Огонь с ядом дают ядовитый дым
+    let toxic = [LingerType::Fire, LingerType::Poison].iter()
+        .all(|etype| mixed.iter().any(|e| matches!(e, PotionEffect::Linger(l) if l.etype == *etype)));
+    for t in tiles {
+        let Some(fluid) = map.fluids[t].as_mut() else { continue; };
+        fluid.effects = mixed.clone();
+        fluid.color = color;
+        if toxic {
+            let density = fluid.volume * 10;
+            map.release_gas(t, Gas { gtype: GasType::Poison, density });
+        }
+    }
+    if let Some(fluid) = map.fluids[idx].as_mut() {
+        fluid.volume += incoming.volume;
+    }
+    if mixed.iter().any(|e| matches!(e, PotionEffect::Teleport(_))) {
+        effects.push(EffectType::Scatter, EffectSource::Reaction, None, Targets::Tiles(points));
+    }
+    events.emit(GameEvent::SpillsMixed { exploded: false });
+}
+
+// Все клетки лужи, связанные с `start` по сторонам
+fn puddle(map: &Map, start: usize) -> Vec<usize> {
+    let Some(effects) = map.fluids[start].as_ref().map(|f| f.effects.clone()) else { return vec![]; };
+    let mut tiles = vec![start];
+    let mut seen = vec![false; MAPCOUNT];
+    seen[start] = true;
+    let mut i = 0;
+    while i < tiles.len() {
+        let idx = tiles[i];
+        i += 1;
+        for n in map::cardinal_neighbours(idx) {
+            if !seen[n] && map.fluids[n].as_ref().is_some_and(|f| f.effects == effects) {
+                seen[n] = true;
+                tiles.push(n);
+            }
+        }
+    }
+    tiles
+}
+
@@ -0,0 +1,211 @@
+use specs::prelude::*;
+
+use crate::components::{BossState, LingerType, Temperature};
+use crate::gamelog::GameLog;
+use crate::identify_system::PotionKind;
+use crate::map::LEVELNUM;
+
+/// Что произошло в игре. Системы сообщают о событиях сюда, а текст лога
+/// строится из них в `publish_events`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum GameEvent {
+    /// Melee hit; `damage` is 0 when the attack could not get through the defence
+    Attacked { attacker: Entity, attacker_name: String, target: Entity, target_name: String, damage: i32 },
+    /// Hit points actually lost, whatever the cause
+    Damaged { target: Entity, amount: i32 },
+    Died { entity: Entity, name: String, boss: bool },
+    ItemPickedUp { item: Entity, name: String, macguffin: bool },
+    ItemDropped { item: Entity, name: String },
+    ItemUsed { item: Entity, name: String, quaffed: bool },
+    NothingToPickUp,
+    Overburdened,
+    NoWayDown,
+    NoWayUp,
+    LevelEntered { depth: i32 },
+    /// Came back to a level that was already visited
+    LevelRevisited { depth: i32, ascended: bool },
+    Healed { target: Entity, amount: i32 },
+    Harmed { target: Entity, name: String },
+    Confused { target: Entity, name: String },
+    BecameInvulnerable { target: Entity },
+    BecameStronger { target: Entity },
+    CaughtInExplosion { target: Entity, name: String },
+    Teleported { target: Entity, name: String },
+    /// Unsafe teleport ended inside a wall
+    Suffocated { target: Entity },
+    Telefragged { attacker: Entity, attacker_name: String, victim: Entity, victim_name: String },
+    MixFailed,
+    /// The player found out what flasks of this look are
+    PotionIdentified { appearance: String, kind: PotionKind },
+    /// A reagent kit used with no unknown potions in the backpack
+    NothingToIdentify,
+    /// `station` is the name of the station brewed at, if any
+    PotionMixed { names: Vec<String>, station: Option<String>, temperature: Temperature, outcome: MixOutcome },
+    /// `name` is the potion before it was split in two
+    PotionDiluted { name: String },
+    /// Diluting needs water on the tile or next to it
+    NoWaterHere,
+    TooWeakToDilute { name: String },
+    /// Two different spills ran together on the floor
+    SpillsMixed { exploded: bool },
+    VapourIgnited,
+    Lingering { target: Entity, name: String, etype: LingerType },
+    /// Set on fire by the ground, e.g. by stepping into lava
+    Ignited { target: Entity, name: String },
+    Extinguished { target: Entity, name: String },
+    FellIntoChasm { target: Entity, name: String },
+    Aiming { entity: Entity, name: String },
+    BossPhaseChanged { boss: Entity, from: BossState, to: BossState },
+    FinalDoorOpened,
+    AutosaveFailed { message: String }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MixOutcome {
+    Exploded,
+    /// One of the recipes from the raws
+    Special { potion: Entity, name: String },
+    Even { potion: Entity }
+}
+
+impl GameEvent {
+    /// Log lines for the event, as seen by the player. Some events are not logged at all.
+    pub fn describe(&self, player: Entity) -> Vec<String> {
+        match self {
+            GameEvent::Attacked { attacker_name, target_name, damage: 0, .. }
+                => vec![format!("{} is unable to hurt {}", attacker_name, target_name)],
+            GameEvent::Attacked { attacker_name, target_name, damage, .. }
+                => vec![format!("{} hurts {} for {} hp", attacker_name, target_name, damage)],
+            GameEvent::Died { entity, .. } if *entity == player => vec!["You are dead!".to_owned()],
+            GameEvent::Died { boss: true, .. } => vec![
+                String::new(),
+                "\"You are a fool... You'll never leave...\"".to_owned(),
+                "The Cursed Alchemist dies!".to_owned()
+            ],
+            GameEvent::Died { name, .. } => vec![format!("{} dies!", name)],
+            GameEvent::ItemPickedUp { macguffin: true, .. } => vec!["You obtained the Philosopher's Stone!".to_owned()],
+            GameEvent::ItemPickedUp { name, .. } => vec![format!("You picked up {}.", name)],
+            GameEvent::ItemDropped { name, .. } => vec![format!("You dropped the {}", name)],
+            GameEvent::ItemUsed { name, quaffed: true, .. } => vec![format!("You drink the {}.", name)],
+            GameEvent::ItemUsed { name, quaffed: false, .. } => vec![format!("You use the {}.", name)],
+            GameEvent::NothingToPickUp => vec!["There's nothing to pick up.".to_owned()],
+            GameEvent::Overburdened => vec!["You are overburdened!".to_owned()],
+            GameEvent::NoWayDown => vec!["There's no way down.".to_owned()],
+            GameEvent::NoWayUp => vec!["There's no way up.".to_owned()],
+            GameEvent::LevelEntered { depth } => {
+                let mut lines = vec!["You descend to a next level, and take a moment to heal.".to_owned()];
+                if *depth == LEVELNUM-1 {
+                    lines.push("You feel foul presence the level below.".to_owned());
+                }
+                lines
+            }
+            GameEvent::LevelRevisited { depth, ascended: true } => vec![format!("You climb back up to level {}.", depth)],
+            GameEvent::LevelRevisited { depth, ascended: false } => vec![format!("You descend to level {} again.", depth)],
+            GameEvent::Healed { target, amount } if *target == player => vec![format!("You heal {} hp.", amount)],
+            #[cfg(debug_assertions)]
+            GameEvent::Harmed { name, .. } => vec![format!("{} suffers damage!", name)],
+            GameEvent::Confused { target, .. } if *target == player => vec!["You feel confused.".to_owned()],
+            GameEvent::Confused { name, .. } => vec![format!("{} looks confused.", name)],
+            GameEvent::BecameInvulnerable { target } if *target == player => vec!["You are invulnerable!".to_owned()],
+            GameEvent::BecameStronger { target } if *target == player => vec!["You feel stronger!".to_owned()],
+            #[cfg(debug_assertions)]
+            GameEvent::CaughtInExplosion { name, .. } => vec![format!("{} gets caught in the explosion!", name)],
+            GameEvent::Teleported { target, .. } if *target == player => vec![],
+            GameEvent::Teleported { name, .. } => vec![format!("{} teleports away!", name)],
+            GameEvent::Suffocated { target } if *target == player => vec!["You teleported into a wall and suffocated.".to_owned()],
+            GameEvent::Telefragged { attacker, victim_name, .. } if *attacker == player
+                => vec![format!("You telefragged a poor {victim_name}.")],
+            GameEvent::Telefragged { attacker_name, victim_name, .. }
+                => vec![format!("{attacker_name} telefragged a poor {victim_name}.")],
+            GameEvent::MixFailed => vec!["You cannot mix that.".to_owned()],
+            GameEvent::PotionMixed { names, station, temperature, outcome } => {
+                let result = match outcome {
+                    MixOutcome::Exploded => "The mix violently explodes!".to_owned(),
+                    MixOutcome::Special { name, .. } => format!("You get a potion of {name}!"),
+                    MixOutcome::Even { .. } if names.len() == 1 => "It boils down to a stronger brew.".to_owned(),
+                    MixOutcome::Even { .. } if names.len() == 2 => "Two potions mix evenly.".to_owned(),
+                    MixOutcome::Even { .. } => "The potions mix evenly.".to_owned()
+                };
+                let verb = match temperature {
+                    Temperature::Room => "mix",
+                    Temperature::Heated => "heat",
+                    Temperature::Cooled => "cool"
+                };
+                let place = station.as_ref().map_or(String::new(), |station| format!(" in the {}", station.to_lowercase()));
+                vec![format!("You {} {}{}.", verb, list(names), place), result]
+            }
+            GameEvent::PotionDiluted { name } => vec![format!("You dilute {} with water and pour it into two flasks.", name)],
+            GameEvent::NoWaterHere => vec!["There's no water here to dilute it with.".to_owned()],
+            GameEvent::TooWeakToDilute { name } => vec![format!("{} is too weak to dilute.", name)],
+            GameEvent::PotionIdentified { appearance, kind } => vec![format!("{} flasks hold {}.", capitalize(appearance), kind.name())],
+            GameEvent::NothingToIdentify => vec!["You have no unknown potions to test.".to_owned()],
+            GameEvent::SpillsMixed { exploded: true } => vec!["Spilled potions run together and explode!".to_owned()],
+            GameEvent::SpillsMixed { exploded: false } => vec!["Spilled potions hiss as they run together.".to_owned()],
+            GameEvent::VapourIgnited => vec!["The vapour bursts into flames!".to_owned()],
+            #[cfg(debug_assertions)]
+            GameEvent::Lingering { name, .. } => vec![format!("{} is burning/poisoned!", name)],
+            GameEvent::Ignited { target, .. } if *target == player => vec!["You catch fire!".to_owned()],
+            GameEvent::Ignited { name, .. } => vec![format!("{} catches fire!", name)],
+            GameEvent::Extinguished { target, .. } if *target == player => vec!["The water puts out the flames.".to_owned()],
+            GameEvent::FellIntoChasm { target, .. } if *target == player => vec!["You fall into the chasm!".to_owned()],
+            GameEvent::FellIntoChasm { name, .. } => vec![format!("{} falls into the chasm.", name)],
+            GameEvent::Aiming { name, .. } => vec![format!("{} is aiming with a flask...", name)],
+            GameEvent::FinalDoorOpened => vec!["You hear a rumbling sound; the door to the chamber opens!".to_owned()],
+            GameEvent::AutosaveFailed { message } => vec![format!("Autosave failed. {}", message)],
+            _ => vec![]
+        }
+    }
+}
+
+/// "A", "A and B", "A, B and C"
+fn list(items: &[String]) -> String {
+    match items.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => items.concat()
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+}
+
+type Listener = Box<dyn FnMut(&GameEvent) + Send + Sync>;
+
+/// Шина событий. Системы кладут события через `emit`, подписчики получают
+/// их в `publish_events` в том же порядке.
+#[derive(Default)]
+pub struct GameEvents {
+    pending: Vec<GameEvent>,
+    listeners: Vec<Listener>
+}
+
+impl GameEvents {
+    pub fn new() -> GameEvents {
+        GameEvents::default()
+    }
+
+    pub fn emit(&mut self, event: GameEvent) {
+        self.pending.push(event);
+    }
+
+    /// Calls `listener` for every event from now on.
+    pub fn subscribe<F: FnMut(&GameEvent) + Send + Sync + 'static>(&mut self, listener: F) {
+        self.listeners.push(Box::new(listener));
+    }
+}
+
+/// Hands the pending events to the log and the listeners.
+pub fn publish_events(ecs: &mut World) {
+    let player = *ecs.fetch::<Entity>();
+    let mut events = ecs.write_resource::<GameEvents>();
+    let mut log = ecs.write_resource::<GameLog>();
+
+    let pending = std::mem::take(&mut events.pending);
+    for event in pending.iter() {
+        log.entries.extend(event.describe(player));
+        for listener in events.listeners.iter_mut() {
+            listener(event);
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GameLog {
     pub entries: Vec<String>,
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+use specs::prelude::*;
+
+use crate::{components::{Confusion, Exhales, LingerType, LingeringEffect, Position, Viewshed}, effects::{EffectQueue, EffectSource, EffectType, PotionEffect, Targets}, gameevent::{GameEvent, GameEvents}, map::{self, Gas, GasType, Map, TileType, MAPCOUNT, MAPWIDTH}, particle_system::ParticleBuilder};
+
+/// What a shattered flask of weight 1 lets out; heavier flasks let out more.
+pub const SPLASH_GAS: i32 = 100;
+
+// Газ слабее зелья, зато держится, пока стоишь в облаке
+const GAS_POISON: LingeringEffect = LingeringEffect { etype: LingerType::Poison, duration: 2, dmg: 1 };
+const GAS_CONFUSION: Confusion = Confusion { turns: 2 };
+const VAPOUR_FIRE: LingeringEffect = LingeringEffect { etype: LingerType::Fire, duration: 3, dmg: 2 };
+
+/// Gas clouds: they work on whoever is inside, spread out and thin away.
+///
+/// Gas spreads like spilled potions (see `FluidSystem`): a tile gives each
+/// thinner neighbour a fifth of the difference. Only walls and doors stop it.
+/// Two different gases do not mix, the first to reach an empty tile keeps it.
+/// Every cloud thins by one a turn, clouds are just much thicker than puddles.
+///
+/// Vapour catches fire from lava, burning tiles and puddles, burning creatures
+/// and explosions, and the whole cloud burns up at once, leaving smoke.
+/// The grass under the cloud catches fire with it. Thick
+/// smoke blocks sight, so viewsheds are redone while there is any.
+pub struct GasSystem {}
+
+impl<'a> System<'a> for GasSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = ( WriteExpect<'a, Map>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, Exhales>,
+                        ReadStorage<'a, LingeringEffect>,
+                        WriteStorage<'a, Viewshed>,
+                        WriteExpect<'a, GameEvents>,
+                        WriteExpect<'a, ParticleBuilder>,
+                        WriteExpect<'a, EffectQueue>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, positions, exhales, linger, mut viewsheds, mut events, mut pbuilder, mut effects) = data;
+        let had_smoke = map.gases.iter().flatten().any(|gas| gas.gtype == GasType::Smoke);
+
+        for (pos, exhale) in (&positions, &exhales).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            map.release_gas(idx, Gas { gtype: exhale.gas, density: exhale.density });
+        }
+
+        let mut sparks: Vec<usize> = (0..MAPCOUNT)
+            .filter(|idx| map.tiles[*idx] == TileType::Lava || map.fire[*idx] > 0 || map.fluids[*idx].as_ref().is_some_and(|f| f.burns()))
+            .collect();
+        for (pos, _) in (&positions, &linger).join().filter(|(_, l)| l.etype == LingerType::Fire) {
+            sparks.push(map.xy_idx(pos.x, pos.y));
+        }
+        for idx in sparks {
+            ignite(&mut map, idx, &mut effects, &mut pbuilder, &mut events);
+        }
+
+        for idx in 0..MAPCOUNT {
+            let Some(gas) = map.gases[idx] else { continue; };
+            let effect = match gas.gtype {
+                GasType::Poison => PotionEffect::Linger(GAS_POISON),
+                GasType::Confusion => PotionEffect::Confusion(GAS_CONFUSION),
+                GasType::Vapour | GasType::Smoke => continue
+            };
+            for mob in map.tile_content[idx].iter() {
+                effects.push(EffectType::Potion(effect), EffectSource::Gas, None, Targets::Single(*mob));
+            }
+        }
+
+        let densities: Vec<i32> = map.gases.iter().map(|g| g.map_or(0, |g| g.density)).collect();
+        let mut next = densities.clone();
+        let mut kinds: Vec<Option<GasType>> = map.gases.iter().map(|g| g.map(|g| g.gtype)).collect();
+        for idx in 0..MAPCOUNT {
+            let Some(gas) = map.gases[idx] else { continue; };
+            for n in map::cardinal_neighbours(idx) {
+                if !map::tile_holds_gas(map.tiles[n]) || kinds[n].is_some_and(|kind| kind != gas.gtype) { continue; }
+                let flow = (densities[idx] - densities[n]) / 5;
+                if flow <= 0 { continue; }
+                kinds[n] = Some(gas.gtype);
+                next[idx] -= flow;
+                next[n] += flow;
+            }
+        }
+        for idx in 0..MAPCOUNT {
+            map.gases[idx] = kinds[idx].filter(|_| next[idx] > 1).map(|gtype| Gas { gtype, density: next[idx] - 1 });
+        }
+
+        if had_smoke || map.gases.iter().flatten().any(|gas| gas.gtype == GasType::Smoke) {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
+
+/// The gas a shattered potion lets out: poison and confusion fumes, smoke from fire.
+pub fn fumes(effects: &[PotionEffect]) -> Option<GasType> {
+    let has = |etype: LingerType| effects.iter().any(|e| matches!(e, PotionEffect::Linger(l) if l.etype == etype));
+    if has(LingerType::Poison) {
+        Some(GasType::Poison)
+    } else if effects.iter().any(|e| matches!(e, PotionEffect::Confusion(_))) {
+        Some(GasType::Confusion)
+    } else if has(LingerType::Fire) {
+        Some(GasType::Smoke)
+    } else {
+        None
+    }
+}
+
+/// Sets the vapour on `idx` alight, and the rest of the cloud with it:
+/// whoever is inside catches fire, the vapour turns to smoke and whatever
+/// can burn under it is set alight.
+pub fn ignite(map: &mut Map, idx: usize, effects: &mut EffectQueue, pbuilder: &mut ParticleBuilder, events: &mut GameEvents) {
+    // Сгоревший пар сразу становится дымом, так что дважды клетку не обойдём
+    let burn = |map: &mut Map, idx: usize| match map.gases[idx].as_mut() {
+        Some(gas) if gas.gtype == GasType::Vapour => {
+            gas.gtype = GasType::Smoke;
+            true
+        }
+        _ => false
+    };
+    if !burn(map, idx) { return; }
+
+    let mut cloud = vec![idx];
+    let mut i = 0;
+    while i < cloud.len() {
+        let tile = cloud[i];
+        i += 1;
+        for n in map::cardinal_neighbours(tile) {
+            if burn(map, n) {
+                cloud.push(n);
+            }
+        }
+    }
+
+    for tile in cloud {
+        let (x, y) = ((tile % MAPWIDTH) as i32, (tile / MAPWIDTH) as i32);
+        pbuilder.request(x, y, rltk::RGB::named(rltk::ORANGE), rltk::RGB::named(rltk::BLACK), rltk::to_cp437('▲'), 200.0);
+        map.set_alight(tile);
+        for mob in map.tile_content[tile].iter() {
+            effects.push(EffectType::Potion(PotionEffect::Linger(VAPOUR_FIRE)), EffectSource::Gas, None, Targets::Single(*mob));
+        }
+    }
+    events.emit(GameEvent::VapourIgnited);
+}
@@ -1,7 +1,7 @@
 use rltk::{Point, Rltk, VirtualKeyCode, RGB};
 use specs::prelude::*;
 
-use crate::{components::{CombatStats, InBackpack, Name, Player, Position, Potion, Viewshed, Weight}, gamelog::GameLog, map::{Map, MAPWIDTH}, RunState, State};
+use crate::{alchemy_system::{brew_turns, Flask, FIELD_SLOTS}, components::{AlchemyStation, CombatStats, Diluted, InBackpack, Name, Player, Position, Potion, Temperature, Viewshed, Weight, STANDARD_POTENCY}, effects::{potion_effects, PotionStorages}, gamelog::GameLog, identify_system::PotionAppearances, journal::AlchemyJournal, map::{self, Map, MAPWIDTH}, map_builders::LevelHistory, saveload_system::SaveSlot, simulation::STATION_SLOTS, RunState, State};
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum MainMenuSelection {
@@ -16,6 +16,40 @@ pub enum MainMenuResult {
     Selected { selected: MainMenuSelection }
 }
 
+#[derive(PartialEq, Copy, Clone)]
+pub enum PauseMenuSelection {
+    Continue,
+    SaveAndQuit,
+    Abandon
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum PauseMenuResult {
+    NoSelection { selected: PauseMenuSelection },
+    Selected { selected: PauseMenuSelection },
+    Cancel
+}
+
+/// What picking a slot in the save menu does.
+#[derive(PartialEq, Copy, Clone)]
+pub enum SlotAction {
+    Save,
+    Load
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum SlotMenuResult {
+    NoSelection { selected: SaveSlot },
+    Selected { selected: SaveSlot },
+    Cancel
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MapgenViewerResult {
+    Show { level: usize, step: usize },
+    Done
+}
+
 pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     ctx.draw_box(0, 43, MAPWIDTH-1, 6, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
 
@@ -58,6 +92,18 @@ fn draw_tooltip(ecs: &World, ctx: &mut Rltk) {
             tooltip.push(name.name.to_string());
         }
     }
+    let mouse_idx = map.xy_idx(mouse_pos.0, mouse_pos.1);
+    if map.revealed_tiles[mouse_idx] {
+        if let Some(terrain) = crate::map::tile_name(map.tiles[mouse_idx]) {
+            tooltip.push(terrain.to_owned());
+        }
+    }
+    if let (true, Some(gas)) = (map.visible_tiles[mouse_idx], map.gases[mouse_idx]) {
+        tooltip.push(gas.gtype.name().to_owned());
+    }
+    if map.visible_tiles[mouse_idx] && map.fire[mouse_idx] > 0 {
+        tooltip.push("Fire".to_owned());
+    }
 
     if !tooltip.is_empty() {
         let mut width = 0;
@@ -106,11 +152,11 @@ pub enum ItemMenuResult {
 }
 
 pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let entities = gs.ecs.entities();
-    let weight = gs.ecs.read_storage::<Weight>();
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let names = gs.sim.ecs.read_storage::<Name>();
+    let backpack = gs.sim.ecs.read_storage::<InBackpack>();
+    let entities = gs.sim.ecs.entities();
+    let weight = gs.sim.ecs.read_storage::<Weight>();
 
     let inventory = (&backpack, &names).join().filter(|item| item.0.owner == *player_entity);
     let count = inventory.count() as i32;
@@ -151,11 +197,11 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
 }
 
 pub fn drop_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let entities = gs.ecs.entities();
-    let weight = gs.ecs.read_storage::<Weight>();
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let names = gs.sim.ecs.read_storage::<Name>();
+    let backpack = gs.sim.ecs.read_storage::<InBackpack>();
+    let entities = gs.sim.ecs.entities();
+    let weight = gs.sim.ecs.read_storage::<Weight>();
 
     let inventory = (&backpack, &names).join().filter(|item| item.0.owner == *player_entity);
     let count = inventory.count() as i32;
@@ -196,10 +242,10 @@ pub fn drop_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Enti
 }
 
 pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32, radius: i32) -> (ItemMenuResult, Option<rltk::Point>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let player_pos = gs.ecs.fetch::<Point>();
-    let viewsheds = gs.ecs.read_storage::<Viewshed>();
-    let mut gamelog = gs.ecs.fetch_mut::<GameLog>();
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let player_pos = gs.sim.ecs.fetch::<Point>();
+    let viewsheds = gs.sim.ecs.read_storage::<Viewshed>();
+    let mut gamelog = gs.sim.ecs.fetch_mut::<GameLog>();
 
     let msg = "Select a target...".to_string();
     if gamelog.entries.iter().last().is_some_and(|m| m != &msg) {
@@ -260,11 +306,21 @@ pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32, radius: i32) ->
 }
 
 pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
-    let runstate = gs.ecs.fetch::<RunState>();
-    let game_exists = crate::saveload_system::does_save_exist();
+    let runstate = gs.sim.ecs.fetch::<RunState>();
+    let game_exists = gs.save_slots.iter().any(|s| s.header.is_some());
 
     ctx.print_color_centered(15, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "The Great Work");
 
+    let seed = gs.sim.ecs.fetch::<crate::rng::RunSeed>();
+    ctx.print_color_centered(48, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), format!("Seed: {}", seed.0));
+
+    if let Some(message) = &gs.menu_message {
+        ctx.print_color_centered(40, RGB::named(rltk::ORANGE), RGB::named(rltk::BLACK), message);
+    }
+    if gs.permadeath {
+        ctx.print_color_centered(46, RGB::named(rltk::RED), RGB::named(rltk::BLACK), "Strict permadeath: saves are deleted on load");
+    }
+
     if let RunState::MainMenu { menu_selection: mut selection } = *runstate {
         if !game_exists {
             selection = MainMenuSelection::NewGame;
@@ -336,6 +392,113 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
     MainMenuResult::NoSelection { selected: MainMenuSelection::NewGame }
 }
 
+pub fn pause_menu(ctx: &mut Rltk, selection: PauseMenuSelection) -> PauseMenuResult {
+    let options = [
+        (PauseMenuSelection::Continue, " Continue "),
+        (PauseMenuSelection::SaveAndQuit, " Save & Quit "),
+        (PauseMenuSelection::Abandon, " Abandon run ")
+    ];
+
+    ctx.draw_box(25, 18, 30, 10, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(18, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Paused");
+    for (i, (option, label)) in options.iter().enumerate() {
+        let (fg, bg) = if *option == selection {
+            (RGB::named(rltk::BLACK), RGB::named(rltk::YELLOW))
+        } else {
+            (RGB::named(rltk::WHITE), RGB::named(rltk::BLACK))
+        };
+        ctx.print_color_centered(21 + 2*i, fg, bg, label);
+    }
+
+    let current = options.iter().position(|(o, _)| *o == selection).unwrap_or(0);
+    match ctx.key {
+        None => PauseMenuResult::NoSelection { selected: selection },
+        Some(VirtualKeyCode::Escape) => PauseMenuResult::Cancel,
+        Some(VirtualKeyCode::Up)
+            => PauseMenuResult::NoSelection { selected: options[(current + options.len() - 1) % options.len()].0 },
+        Some(VirtualKeyCode::Down)
+            => PauseMenuResult::NoSelection { selected: options[(current + 1) % options.len()].0 },
+        Some(VirtualKeyCode::Return) => PauseMenuResult::Selected { selected: selection },
+        Some(_) => PauseMenuResult::NoSelection { selected: selection }
+    }
+}
+
+/// Shows one step of building a level. Right/Space go forward, Left back,
+/// Enter skips to the next level and Escape back to the game.
+pub fn mapgen_viewer(ctx: &mut Rltk, levels: &[LevelHistory], level: usize, step: usize) -> MapgenViewerResult {
+    let Some(history) = levels.get(level) else { return MapgenViewerResult::Done; };
+    let Some(snapshot) = history.snapshots.get(step) else { return MapgenViewerResult::Done; };
+
+    map::draw_tiles(&snapshot.to_map(history.depth), ctx, history.depth);
+    for (pos, glyph) in snapshot.spawns.iter() {
+        ctx.set(pos.x, pos.y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), *glyph);
+    }
+
+    ctx.draw_box(0, 43, MAPWIDTH-1, 6, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(2, 44, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK),
+        format!("Depth {}, {}: step {}/{}", history.depth, history.builder, step + 1, history.snapshots.len()));
+    ctx.print_color(2, 45, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), &snapshot.label);
+    ctx.print_color(2, 47, RGB::named(rltk::GREY), RGB::named(rltk::BLACK),
+        "Right/Space: next step  Left: previous  Enter: next level  ESC: play");
+
+    let next_level = MapgenViewerResult::Show { level: level + 1, step: 0 };
+    match ctx.key {
+        Some(VirtualKeyCode::Right) | Some(VirtualKeyCode::Space) if step + 1 < history.snapshots.len()
+            => MapgenViewerResult::Show { level, step: step + 1 },
+        Some(VirtualKeyCode::Right) | Some(VirtualKeyCode::Space) | Some(VirtualKeyCode::Return) => next_level,
+        Some(VirtualKeyCode::Left) => MapgenViewerResult::Show { level, step: step.saturating_sub(1) },
+        Some(VirtualKeyCode::Escape) => MapgenViewerResult::Done,
+        _ => MapgenViewerResult::Show { level, step }
+    }
+}
+
+/// Slot picker for saving and loading. Saving goes to the manual slots only,
+/// loading only offers slots that have something in them.
+pub fn save_slots(gs: &mut State, ctx: &mut Rltk, action: SlotAction, selection: SaveSlot) -> SlotMenuResult {
+    let listings: Vec<_> = gs.save_slots.iter()
+        .filter(|l| match action {
+            SlotAction::Save => l.slot != SaveSlot::Auto,
+            SlotAction::Load => l.header.is_some()
+        })
+        .collect();
+    if listings.is_empty() {
+        return SlotMenuResult::Cancel;
+    }
+
+    let title = if action == SlotAction::Save { "Save to which slot?" } else { "Load which save?" };
+    ctx.draw_box(5, 14, 70, 4 + 2*listings.len(), RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(14, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), title);
+    ctx.print_color(7, 17 + 2*listings.len(), RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to go back");
+
+    for (i, listing) in listings.iter().enumerate() {
+        let y = 16 + 2*i;
+        let (fg, bg) = if listing.slot == selection {
+            (RGB::named(rltk::BLACK), RGB::named(rltk::YELLOW))
+        } else {
+            (RGB::named(rltk::WHITE), RGB::named(rltk::BLACK))
+        };
+        ctx.print_color(7, y, fg, bg, format!(" {:<9}", listing.slot.name()));
+        let (color, text) = match &listing.header {
+            None => (RGB::named(rltk::GREY), "empty".to_owned()),
+            Some(Ok(header)) => (RGB::named(rltk::WHITE), header.describe()),
+            Some(Err(e)) => (RGB::named(rltk::ORANGE), e.to_string())
+        };
+        ctx.print_color(18, y, color, RGB::named(rltk::BLACK), truncate_to(text, 56));
+    }
+
+    let current = listings.iter().position(|l| l.slot == selection).unwrap_or(0);
+    match ctx.key {
+        None => SlotMenuResult::NoSelection { selected: listings[current].slot },
+        Some(VirtualKeyCode::Escape) => SlotMenuResult::Cancel,
+        Some(VirtualKeyCode::Up)
+            => SlotMenuResult::NoSelection { selected: listings[(current + listings.len() - 1) % listings.len()].slot },
+        Some(VirtualKeyCode::Down)
+            => SlotMenuResult::NoSelection { selected: listings[(current + 1) % listings.len()].slot },
+        Some(VirtualKeyCode::Return) => SlotMenuResult::Selected { selected: listings[current].slot },
+        Some(_) => SlotMenuResult::NoSelection { selected: listings[current].slot }
+    }
+}
+
 pub fn keybinds_menu(ctx: &mut Rltk) -> ItemMenuResult {
     macro_rules! formstr {
         ($key:literal, $desc:literal) => {
@@ -343,20 +506,23 @@ pub fn keybinds_menu(ctx: &mut Rltk) -> ItemMenuResult {
         };
     }
 
-    ctx.draw_box(15, 5, 50, 22, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.draw_box(15, 5, 50, 28, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
     ctx.print_color_centered(5, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Keybinds");
-    ctx.print_color(18, 27, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
+    ctx.print_color(18, 33, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
 
     #[cfg(not(target_arch = "wasm32"))]
     let strings: Vec<String> = vec![
         formstr!("←↑↓→", "move"),
         formstr!(".", "descend to next level"),
+        formstr!("<", "climb to previous level"),
         formstr!("g | ,", "pick up an item"),
         formstr!("space", "wait a turn"),
         formstr!("i", "open inventory"),
         formstr!("d", "drop an item"),
         formstr!("t", "throw an item"),
-        formstr!("m", "mix potions"),
+        formstr!("m", "mix potions, brew at a station"),
+        formstr!("w", "dilute a potion with water"),
+        formstr!("j", "alchemy journal"),
         formstr!("esc", "pause"),
         formstr!("/", "help"),
     ];
@@ -365,12 +531,15 @@ pub fn keybinds_menu(ctx: &mut Rltk) -> ItemMenuResult {
     let strings: Vec<String> = vec![
         formstr!("←↑↓→", "move"),
         formstr!(">", "descend to next level"),
+        formstr!("<", "climb to previous level"),
         formstr!("g | ,", "pick up an item"),
         formstr!("space", "wait a turn"),
         formstr!("i", "open inventory"),
         formstr!("d", "drop an item"),
         formstr!("t", "throw an item"),
-        formstr!("m", "mix potions"),
+        formstr!("m", "mix potions, brew at a station"),
+        formstr!("w", "dilute a potion with water"),
+        formstr!("j", "alchemy journal"),
         formstr!("esc", "pause"),
         formstr!("?", "help"),
     ];
@@ -388,11 +557,11 @@ pub fn keybinds_menu(ctx: &mut Rltk) -> ItemMenuResult {
 }
 
 pub fn throw_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let weight = gs.ecs.read_storage::<Weight>();
-    let entities = gs.ecs.entities();
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let names = gs.sim.ecs.read_storage::<Name>();
+    let backpack = gs.sim.ecs.read_storage::<InBackpack>();
+    let weight = gs.sim.ecs.read_storage::<Weight>();
+    let entities = gs.sim.ecs.entities();
 
     let inventory = (&backpack, &names).join().filter(|item| item.0.owner == *player_entity);
     let count = inventory.count() as i32;
@@ -432,32 +601,124 @@ pub fn throw_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Ent
     }
 }
 
-pub fn mix_potions(gs: &mut State, ctx: &mut Rltk, selected: Option<Entity>) -> (ItemMenuResult, Option<Entity>, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let potions = gs.ecs.read_storage::<Potion>();
-    let entities = gs.ecs.entities();
-    let weight = gs.ecs.read_storage::<Weight>();
+pub fn dilute_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let names = gs.sim.ecs.read_storage::<Name>();
+    let backpack = gs.sim.ecs.read_storage::<InBackpack>();
+    let potions = gs.sim.ecs.read_storage::<Potion>();
+    let entities = gs.sim.ecs.entities();
 
     let inventory = (&backpack, &names, &potions).join().filter(|item| item.0.owner == *player_entity);
     let count = inventory.count() as i32;
 
-    let mut y = 25 - (count / 2);
-    ctx.draw_box(15, y-2, 31, count+3, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
-    if selected.is_none() {
-        ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Choose first ingredient...");
-    } else {
-        ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Choose second ingredient...");
-    }
-    ctx.print_color(18, y+count + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
+    let top = 25 - (count / 2);
+    ctx.draw_box(15, top-2, 31, count+3, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(18, top-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Dilute which potion?");
+    ctx.print_color(18, top+count + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
 
-    let mut mixable: Vec<Entity> = vec![];
+    let mut dilutable: Vec<Entity> = vec![];
     let mut items = (&entities, &backpack, &names, &potions).join().filter(|item| item.1.owner == *player_entity).collect::<Vec<_>>();
     items.sort_by(|a, b| a.2.name.cmp(&b.2.name));
     for (j, (entity, _pack, name, _potion)) in items.into_iter().enumerate() {
+        let y = top + j as i32;
+        ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
+        ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
 
-        let (fg, bg, glyph) = if selected.is_some_and(|sel| sel == entity) {
+        ctx.print(21, y, truncate_str(name.name.to_string()));
+        dilutable.push(entity);
+    }
+
+    match ctx.key {
+        None => (ItemMenuResult::NoResponse, None),
+        Some(key) => match key {
+            rltk::VirtualKeyCode::Escape => (ItemMenuResult::Cancel, None),
+            _ => {
+                let selection = rltk::letter_to_option(key);
+                if selection > -1 && selection < count {
+                    return (ItemMenuResult::Selected, Some(dilutable[selection as usize]));
+                }
+                (ItemMenuResult::NoResponse, None)
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum MixMenuResult {
+    Cancel,
+    NoResponse,
+    /// The player put a flask in, took one out or changed the temperature
+    Changed { slots: [Option<Entity>; STATION_SLOTS], temperature: Temperature },
+    Brew
+}
+
+/// Mixing in the field (two flasks) or at a station: slots for the
+/// ingredients, heating and cooling, and what the journal expects to come out.
+pub fn mix_potions(gs: &mut State, ctx: &mut Rltk, station: Option<Entity>, slots: [Option<Entity>; STATION_SLOTS], temperature: Temperature) -> MixMenuResult {
+    let player_entity = gs.sim.ecs.fetch::<Entity>();
+    let names = gs.sim.ecs.read_storage::<Name>();
+    let backpack = gs.sim.ecs.read_storage::<InBackpack>();
+    let potions = gs.sim.ecs.read_storage::<Potion>();
+    let diluted = gs.sim.ecs.read_storage::<Diluted>();
+    let entities = gs.sim.ecs.entities();
+    let weight = gs.sim.ecs.read_storage::<Weight>();
+    let stations = gs.sim.ecs.read_storage::<AlchemyStation>();
+    let journal = gs.sim.ecs.fetch::<AlchemyJournal>();
+    let appearances = gs.sim.ecs.fetch::<PotionAppearances>();
+    let potion_storages = gs.sim.ecs.system_data::<PotionStorages>();
+
+    let at = station.and_then(|s| stations.get(s).copied());
+    let capacity = at.map_or(FIELD_SLOTS, |s| s.slots as usize);
+    let filled: Vec<Entity> = slots.iter().flatten().copied().collect();
+    let flasks_of = |flasks: &[Entity]| -> Vec<Flask> {
+        flasks.iter().map(|f| Flask { effects: potion_effects(&potion_storages, *f), potency: potions.get(*f).map_or(STANDARD_POTENCY, |p| p.potency) }).collect()
+    };
+    let name_of = |item: Entity| names.get(item).map_or("something".to_owned(), |n| n.name.clone());
+
+    let mut items = (&entities, &backpack, &names, &potions).join().filter(|item| item.1.owner == *player_entity).collect::<Vec<_>>();
+    items.sort_by(|a, b| a.2.name.cmp(&b.2.name));
+    let count = items.len() as i32;
+
+    let mut footer: Vec<(RGB, String)> = vec![];
+    let slot_names: Vec<String> = (0..capacity).map(|i| slots[i].map_or("-".to_owned(), |item| truncate_to(name_of(item), 14))).collect();
+    footer.push((RGB::named(rltk::WHITE), format!("Slots: {}", slot_names.join(" | "))));
+    if let Some(at) = at {
+        let temperatures = match temperature {
+            Temperature::Room => "room temperature",
+            Temperature::Heated => "heated",
+            Temperature::Cooled => "cooled"
+        };
+        let color = if at.heat || at.cool { RGB::named(rltk::WHITE) } else { RGB::named(rltk::GREY) };
+        footer.push((color, format!("Tab: heat or cool ({})", temperatures)));
+    }
+    // Одну флягу можно только уварить на огне, и только не разбавленную
+    let distilling = filled.len() == 1 && temperature == Temperature::Heated && !diluted.contains(filled[0]);
+    if filled.len() == 1 && diluted.contains(filled[0]) {
+        footer.push((RGB::named(rltk::GREY), "Watered-down flasks cannot be distilled".to_owned()));
+    }
+    if filled.len() >= 2 {
+        let predicted = journal.predict(&flasks_of(&filled), temperature).map_or("unknown".to_owned(), |result| result.describe(&appearances));
+        footer.push((RGB::named(rltk::WHITE), format!("Result: {}", truncate_to(predicted, 44))));
+    }
+    if filled.len() >= 2 || distilling {
+        let turns = at.map_or(1, |at| brew_turns(at, temperature));
+        let verb = if distilling { "distil" } else { "brew" };
+        footer.push((RGB::named(rltk::YELLOW), format!("Enter: {}, takes {} turn{}", verb, turns, if turns == 1 { "" } else { "s" })));
+    }
+
+    let mut y = 25 - ((count + footer.len() as i32) / 2);
+    ctx.draw_box(15, y-2, 58, count + footer.len() as i32 + 4, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    let title = match station {
+        Some(station) => format!("{}: choose up to {} ingredients...", name_of(station), capacity),
+        None => format!("Choose {} ingredient...", if filled.is_empty() { "first" } else { "second" })
+    };
+    ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), title);
+    ctx.print_color(18, y + count + footer.len() as i32 + 2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
+
+    let mut mixable: Vec<Entity> = vec![];
+    for (j, (entity, _pack, name, _potion)) in items.into_iter().enumerate() {
+        let (fg, bg, glyph) = if filled.contains(&entity) {
             (RGB::named(rltk::BLACK), RGB::named(rltk::YELLOW), 65+j as rltk::FontCharType)
         } else {
             (RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType)
@@ -468,35 +729,99 @@ pub fn mix_potions(gs: &mut State, ctx: &mut Rltk, selected: Option<Entity>) ->
 
         ctx.print(21, y, truncate_str(name.name.to_string()));
         ctx.print(45, y, weight.get(entity).map_or(1, |w| w.0));
+        // Что уже пробовали смешивать, журнал подсказывает справа
+        if !filled.is_empty() && filled.len() < capacity && !filled.contains(&entity) {
+            let with_this = [filled.clone(), vec![entity]].concat();
+            if let Some(result) = journal.predict(&flasks_of(&with_this), temperature) {
+                ctx.print_color(48, y, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), format!("→ {}", truncate_to(result.describe(&appearances), 22)));
+            }
+        }
         mixable.push(entity);
         y += 1;
     }
+    y += 1;
+    for (color, line) in footer {
+        ctx.print_color(18, y, color, RGB::named(rltk::BLACK), line);
+        y += 1;
+    }
 
     match ctx.key {
-        None => (ItemMenuResult::NoResponse, selected, None),
-        Some(key) => match key {
-            VirtualKeyCode::Escape => {
-                (ItemMenuResult::Cancel, None, None)
-            },
-            _ => {
-                let selection = rltk::letter_to_option(key);
-                if selection > -1 && selection < count {
-                    let selection = mixable[selection as usize];
-                    if selected.is_some() {
-                        if selected.unwrap() == selection {
-                            return (ItemMenuResult::Selected, None, None);
-                        }
-                        return (ItemMenuResult::Selected, selected, Some(selection))
-                    } else {
-                        return (ItemMenuResult::Selected, Some(selection), None)
-                    }
-                }
-                (ItemMenuResult::NoResponse, selected, None)
+        None => MixMenuResult::NoResponse,
+        Some(VirtualKeyCode::Escape) => MixMenuResult::Cancel,
+        Some(VirtualKeyCode::Return) if filled.len() >= 2 || distilling => MixMenuResult::Brew,
+        Some(VirtualKeyCode::Tab) => match at {
+            Some(at) => {
+                // Комнатная → нагрев → охлаждение, что станция не умеет, пропускаем
+                let next = [Temperature::Heated, Temperature::Cooled, Temperature::Room].into_iter()
+                    .cycle()
+                    .skip_while(|t| *t != temperature)
+                    .skip(1)
+                    .find(|t| match t {
+                        Temperature::Room => true,
+                        Temperature::Heated => at.heat,
+                        Temperature::Cooled => at.cool
+                    })
+                    .unwrap_or(Temperature::Room);
+                MixMenuResult::Changed { slots, temperature: next }
             }
+            None => MixMenuResult::NoResponse
+        },
+        Some(key) => {
+            let selection = rltk::letter_to_option(key);
+            if selection < 0 || selection >= count {
+                return MixMenuResult::NoResponse;
+            }
+            let item = mixable[selection as usize];
+            let mut chosen: Vec<Entity> = filled.iter().copied().filter(|f| *f != item).collect();
+            if chosen.len() == filled.len() {
+                if filled.len() >= capacity { return MixMenuResult::NoResponse; }
+                chosen.push(item);
+            }
+            let mut slots = [None; STATION_SLOTS];
+            for (slot, flask) in slots.iter_mut().zip(chosen) {
+                *slot = Some(flask);
+            }
+            MixMenuResult::Changed { slots, temperature }
         }
     }
 }
 
+/// The alchemy journal: special recipes found so far and every mix, newest first.
+pub fn show_journal(gs: &mut State, ctx: &mut Rltk) -> ItemMenuResult {
+    let journal = gs.sim.ecs.fetch::<AlchemyJournal>();
+    let appearances = gs.sim.ecs.fetch::<PotionAppearances>();
+
+    ctx.draw_box(5, 3, 70, 40, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(3, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Alchemy journal");
+    ctx.print_color(8, 43, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Press ESC to close");
+
+    let mut lines: Vec<(RGB, String)> = vec![(RGB::named(rltk::YELLOW), "Recipes".to_owned())];
+    if journal.recipes.is_empty() {
+        lines.push((RGB::named(rltk::GREY), "None found yet.".to_owned()));
+    }
+    for recipe in journal.recipes.iter() {
+        lines.push((RGB::named(rltk::GOLD), format!("{} → {}", recipe.describe(&appearances), appearances.label(&recipe.result))));
+    }
+
+    lines.push((RGB::named(rltk::BLACK), String::new()));
+    lines.push((RGB::named(rltk::YELLOW), "Mixes".to_owned()));
+    if journal.entries.is_empty() {
+        lines.push((RGB::named(rltk::GREY), "Nothing mixed yet.".to_owned()));
+    }
+    for entry in journal.entries.iter().rev() {
+        lines.push((RGB::named(rltk::WHITE), format!("Turn {}: {} → {}", entry.turn, entry.describe(&appearances), entry.result.describe(&appearances))));
+    }
+
+    for (i, (color, line)) in lines.into_iter().take(37).enumerate() {
+        ctx.print_color(7, 5 + i as i32, color, RGB::named(rltk::BLACK), truncate_to(line, 66));
+    }
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => ItemMenuResult::Cancel,
+        _ => ItemMenuResult::NoResponse
+    }
+}
+
 pub fn gameover(ctx: &mut Rltk) -> ItemMenuResult {
     ctx.draw_box(35, 20, 10, 3, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
     ctx.print_color_centered(22, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "You died!");
@@ -528,7 +853,11 @@ pub fn winscreen(ctx: &mut Rltk) -> ItemMenuResult {
 }
 
 fn truncate_str(name: String) -> String {
-    match name.char_indices().nth(20) {
+    truncate_to(name, 20)
+}
+
+fn truncate_to(name: String, len: usize) -> String {
+    match name.char_indices().nth(len) {
         None => name,
         Some((idx, _)) => {
             let mut n = name[..idx].to_string();
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rltk::{RandomNumberGenerator, RGB};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use crate::{components::{LingerType, Name, Potion, Renderable, Unidentified}, effects::{potion_effects, PotionEffect, PotionStorages}, gameevent::{GameEvent, GameEvents}};
+
+/// What a potion does, as far as knowing it goes: the effect without its numbers.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PotionKind {
+    Health,
+    Teleport,
+    Confusion,
+    Harm,
+    Fire,
+    Poison,
+    Explosion,
+    Invulnerability,
+    Strength
+}
+
+impl PotionKind {
+    pub const ALL: [PotionKind; 9] = [PotionKind::Health, PotionKind::Teleport, PotionKind::Confusion, PotionKind::Harm,
+        PotionKind::Fire, PotionKind::Poison, PotionKind::Explosion, PotionKind::Invulnerability, PotionKind::Strength];
+
+    pub fn of(effect: &PotionEffect) -> PotionKind {
+        match effect {
+            PotionEffect::Heal(_) => PotionKind::Health,
+            PotionEffect::Teleport(_) => PotionKind::Teleport,
+            PotionEffect::Confusion(_) => PotionKind::Confusion,
+            PotionEffect::Harm(_) => PotionKind::Harm,
+            PotionEffect::Linger(l) if l.etype == LingerType::Fire => PotionKind::Fire,
+            PotionEffect::Linger(_) => PotionKind::Poison,
+            PotionEffect::Explosion(_) => PotionKind::Explosion,
+            PotionEffect::Invulnerability(_) => PotionKind::Invulnerability,
+            PotionEffect::Strength(_) => PotionKind::Strength
+        }
+    }
+
+    /// Every kind in `effects`, sorted, each once.
+    pub fn all_of(effects: &[PotionEffect]) -> Vec<PotionKind> {
+        let mut kinds: Vec<PotionKind> = effects.iter().map(PotionKind::of).collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PotionKind::Health => "Health",
+            PotionKind::Teleport => "Teleport",
+            PotionKind::Confusion => "Confusion",
+            PotionKind::Harm => "Harm",
+            PotionKind::Fire => "Fire",
+            PotionKind::Poison => "Poison",
+            PotionKind::Explosion => "Explosion",
+            PotionKind::Invulnerability => "Invulnerability",
+            PotionKind::Strength => "Strength"
+        }
+    }
+}
+
+// Прилагательных и цветов больше, чем видов зелий, чтобы забеги не повторялись
+const LOOKS: [&str; 12] = ["murky", "cloudy", "fizzing", "bubbling", "oily", "glowing",
+    "smoky", "viscous", "sparkling", "milky", "syrupy", "shimmering"];
+const COLORS: [(&str, (u8, u8, u8)); 12] = [
+    ("teal", (0, 128, 128)), ("amber", (255, 191, 0)), ("crimson", (220, 20, 60)), ("azure", (0, 127, 255)),
+    ("ochre", (204, 119, 34)), ("violet", (143, 0, 255)), ("jade", (0, 168, 107)), ("silver", (192, 192, 192)),
+    ("rose", (255, 102, 153)), ("umber", (99, 81, 71)), ("cobalt", (0, 71, 171)), ("saffron", (244, 196, 48))];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Appearance {
+    /// E.g. "murky teal"
+    pub name: String,
+    pub color: RGB
+}
+
+/// How every kind of potion looks in this run, and which kinds the player
+/// has found out. The looks are rolled from the run seed, like `AlchemySeed`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PotionAppearances {
+    looks: BTreeMap<PotionKind, Appearance>,
+    known: BTreeSet<PotionKind>
+}
+
+impl PotionAppearances {
+    pub fn new(seed: u64) -> PotionAppearances {
+        let mut rng = RandomNumberGenerator::seeded(seed);
+        let mut looks: Vec<&str> = LOOKS.to_vec();
+        let mut colors = COLORS.to_vec();
+        shuffle(&mut looks, &mut rng);
+        shuffle(&mut colors, &mut rng);
+
+        let looks = PotionKind::ALL.iter().zip(looks.iter().zip(colors.iter()))
+            .map(|(kind, (look, (color, (r, g, b))))| (*kind, Appearance { name: format!("{} {}", look, color), color: RGB::from_u8(*r, *g, *b) }))
+            .collect();
+        PotionAppearances { looks, known: BTreeSet::new() }
+    }
+
+    /// Looks for a run where everything is known already, e.g. one from a save
+    /// made before potions had to be identified.
+    pub fn all_known(seed: u64) -> PotionAppearances {
+        PotionAppearances { known: PotionKind::ALL.into_iter().collect(), ..PotionAppearances::new(seed) }
+    }
+
+    pub fn is_known(&self, kind: PotionKind) -> bool {
+        self.known.contains(&kind)
+    }
+
+    /// The kind's name if it is known, its looks otherwise.
+    pub fn kind_name(&self, kind: PotionKind) -> &str {
+        if self.is_known(kind) { kind.name() } else { self.looks[&kind].name.as_str() }
+    }
+
+    /// `kind_name`s joined with " + ", e.g. "Fire + murky teal".
+    pub fn label(&self, kinds: &[PotionKind]) -> String {
+        kinds.iter().map(|kind| self.kind_name(*kind)).collect::<Vec<_>>().join(" + ")
+    }
+
+    /// Learns every kind in `effects`, telling the player about the new ones.
+    pub fn learn(&mut self, effects: &[PotionEffect], events: &mut GameEvents) {
+        for effect in effects {
+            let kind = PotionKind::of(effect);
+            if self.known.insert(kind) {
+                events.emit(GameEvent::PotionIdentified { appearance: self.looks[&kind].name.clone(), kind });
+            }
+        }
+    }
+
+    /// Name and colour of a flask with `effects` as the player sees it:
+    /// known kinds by name, the rest by their looks. `None` if all are known.
+    pub fn describe(&self, effects: &[PotionEffect]) -> Option<(String, RGB)> {
+        let kinds = PotionKind::all_of(effects);
+        let unknown = kinds.iter().find(|kind| !self.is_known(**kind))?;
+        let color = self.looks[unknown].color;
+
+        let mut name = self.label(&kinds);
+        if let Some(first) = name.get_mut(0..1) {
+            first.make_ascii_uppercase();
+        }
+        name.push_str(" flask");
+        Some((name, color))
+    }
+}
+
+fn shuffle<T>(items: &mut [T], rng: &mut RandomNumberGenerator) {
+    for i in (1..items.len()).rev() {
+        let j = rng.roll_dice(1, i as i32 + 1) as usize - 1;
+        items.swap(i, j);
+    }
+}
+
+/// Keeps the names of unidentified flasks in step with what the player knows:
+/// a flask whose every kind is known gets its real name and colour back.
+/// Flasks from a level the player comes back to are caught up here too.
+pub struct IdentifySystem {}
+
+impl<'a> System<'a> for IdentifySystem {
+    type SystemData = ( Entities<'a>,
+                        ReadExpect<'a, PotionAppearances>,
+                        WriteStorage<'a, Unidentified>,
+                        WriteStorage<'a, Name>,
+                        WriteStorage<'a, Renderable>,
+                        ReadStorage<'a, Potion>,
+                        PotionStorages<'a>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, appearances, mut unidentified, mut names, mut renders, potions, potion_storages) = data;
+
+        let mut identified = vec![];
+        for (ent, real) in (&entities, &unidentified).join() {
+            // Смесь получает эффекты только после maintain, до тех пор её не трогаем
+            let effects = potion_effects(&potion_storages, ent);
+            if effects.is_empty() { continue; }
+            let (name, fg) = match appearances.describe(&effects) {
+                Some(look) => look,
+                None => {
+                    identified.push(ent);
+                    (real.name.clone(), real.fg)
+                }
+            };
+            // Крепость видна и у неопознанной фляги
+            let name = match potions.get(ent) {
+                Some(potion) => potion.name(&name),
+                None => name
+            };
+            names.insert(ent, Name { name }).expect("Unable to rename potion");
+            if let Some(render) = renders.get_mut(ent) {
+                render.fg = fg;
+            }
+        }
+        for ent in identified {
+            unidentified.remove(ent);
+        }
+    }
+}
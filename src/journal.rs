@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{alchemy_system::{Brew, Flask}, components::{Potion, Temperature}, identify_system::{PotionAppearances, PotionKind}};
+
+/// What came of a mix, as written down in the journal.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum MixResult {
+    Exploded,
+    Special(Vec<PotionKind>),
+    Even(Vec<PotionKind>)
+}
+
+impl MixResult {
+    /// The result as the player can tell it, e.g. "Fire + murky teal".
+    pub fn describe(&self, appearances: &PotionAppearances) -> String {
+        match self {
+            MixResult::Exploded => "explodes!".to_owned(),
+            MixResult::Special(kinds) | MixResult::Even(kinds) => appearances.label(kinds)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    /// Turn of the run the mix was made on
+    pub turn: u32,
+    /// Kinds in each flask, in the order they went in
+    pub ingredients: Vec<Vec<PotionKind>>,
+    /// Potency of each flask, in the same order
+    pub potencies: Vec<i32>,
+    pub temperature: Temperature,
+    pub result: MixResult
+}
+
+impl JournalEntry {
+    /// The flasks as the player can tell them, e.g. "Fire + Harm, weak murky teal (heated)".
+    pub fn describe(&self, appearances: &PotionAppearances) -> String {
+        let flasks: Vec<String> = self.ingredients.iter().zip(self.potencies.iter())
+            .map(|(kinds, potency)| Potion { potency: *potency }.name(&appearances.label(kinds)))
+            .collect();
+        format!("{}{}", flasks.join(", "), temperature_note(self.temperature))
+    }
+
+    fn same_flasks(&self, flasks: &[(Vec<PotionKind>, i32)]) -> bool {
+        let mut ours: Vec<(Vec<PotionKind>, i32)> = self.ingredients.iter().cloned().zip(self.potencies.iter().copied()).collect();
+        let mut theirs = flasks.to_vec();
+        ours.sort();
+        theirs.sort();
+        ours == theirs
+    }
+}
+
+/// A special combination found by mixing: these kinds together give `result`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Recipe {
+    pub ingredients: Vec<PotionKind>,
+    /// The fewest flasks holding each kind the recipe has worked with, in the same order
+    pub counts: Vec<i32>,
+    pub temperature: Temperature,
+    /// The weakest flask the recipe has worked with so far
+    pub potency: i32,
+    pub result: Vec<PotionKind>
+}
+
+impl Recipe {
+    /// The ingredients as the player can tell them, e.g. "3 × Health (heated)".
+    pub fn describe(&self, appearances: &PotionAppearances) -> String {
+        let kinds: Vec<String> = self.ingredients.iter().zip(self.counts.iter())
+            .map(|(kind, count)| match count {
+                1 => appearances.kind_name(*kind).to_owned(),
+                _ => format!("{} × {}", count, appearances.kind_name(*kind))
+            })
+            .collect();
+        format!("{}{}", Potion { potency: self.potency }.name(&kinds.join(" + ")), temperature_note(self.temperature))
+    }
+}
+
+pub fn temperature_note(temperature: Temperature) -> &'static str {
+    match temperature {
+        Temperature::Room => "",
+        Temperature::Heated => " (heated)",
+        Temperature::Cooled => " (cooled)"
+    }
+}
+
+/// The player's alchemy notes: every mix made this run and the special
+/// recipes found so far. Kept by kinds, not names, so that the journal
+/// reads better as the player identifies potions.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AlchemyJournal {
+    pub entries: Vec<JournalEntry>,
+    pub recipes: Vec<Recipe>
+}
+
+impl AlchemyJournal {
+    pub fn record(&mut self, turn: u32, flasks: &[Flask], temperature: Temperature, brewed: &Brew) {
+        let result = match brewed {
+            Brew::Exploded => MixResult::Exploded,
+            Brew::Special(made) => MixResult::Special(PotionKind::all_of(&made.effects)),
+            Brew::Even(mixed) => MixResult::Even(PotionKind::all_of(&mixed.effects))
+        };
+
+        // Рецепт узнаётся по видам во всех флягах вместе, см. `brew`
+        let each: Vec<Vec<PotionKind>> = flasks.iter().map(|flask| PotionKind::all_of(&flask.effects)).collect();
+        if let MixResult::Special(special) = &result {
+            let ingredients = kinds_together(flasks);
+            let counts = flasks_holding(&ingredients, &each);
+            let weakest = flasks.iter().map(|flask| flask.potency).min().unwrap_or_default();
+            let known = self.recipes.iter_mut()
+                .find(|r| r.ingredients == ingredients && r.temperature == temperature && &r.result == special);
+            match known {
+                Some(recipe) => {
+                    recipe.potency = recipe.potency.min(weakest);
+                    recipe.counts = recipe.counts.iter().zip(counts.iter()).map(|(ours, theirs)| *ours.min(theirs)).collect();
+                }
+                None => self.recipes.push(Recipe { ingredients, counts, temperature, potency: weakest, result: special.clone() })
+            }
+        }
+
+        let ingredients = each;
+        let potencies = flasks.iter().map(|flask| flask.potency).collect();
+        self.entries.push(JournalEntry { turn, ingredients, potencies, temperature, result });
+    }
+
+    /// What mixing these flasks should give: a recipe found before if one
+    /// fits, otherwise what the same flasks gave the last time they were tried.
+    pub fn predict(&self, flasks: &[Flask], temperature: Temperature) -> Option<MixResult> {
+        let kinds = kinds_together(flasks);
+        let each: Vec<Vec<PotionKind>> = flasks.iter().map(|flask| PotionKind::all_of(&flask.effects)).collect();
+        let counts = flasks_holding(&kinds, &each);
+        let weakest = flasks.iter().map(|flask| flask.potency).min()?;
+        // Рецепт сработает и с лишними флягами, но не с меньшим их числом
+        let recipe = self.recipes.iter()
+            .find(|r| {
+                r.ingredients == kinds && r.temperature == temperature && weakest >= r.potency
+                    && r.counts.iter().zip(counts.iter()).all(|(needed, held)| held >= needed)
+            });
+        if let Some(recipe) = recipe {
+            return Some(MixResult::Special(recipe.result.clone()));
+        }
+
+        let flasks: Vec<(Vec<PotionKind>, i32)> = each.into_iter().zip(flasks.iter().map(|flask| flask.potency)).collect();
+        self.entries.iter().rev()
+            .find(|entry| entry.temperature == temperature && entry.same_flasks(&flasks))
+            .map(|entry| entry.result.clone())
+    }
+}
+
+fn kinds_together(flasks: &[Flask]) -> Vec<PotionKind> {
+    PotionKind::all_of(&flasks.iter().flat_map(|flask| flask.effects.iter().copied()).collect::<Vec<_>>())
+}
+
+/// For each of `kinds`, how many of the flasks hold it.
+pub fn flasks_holding(kinds: &[PotionKind], flasks: &[Vec<PotionKind>]) -> Vec<i32> {
+    kinds.iter().map(|kind| flasks.iter().filter(|flask| flask.contains(kind)).count() as i32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Explosion, LingerType, LingeringEffect, ProvidesHealing, MAX_POTENCY, MIN_POTENCY, STANDARD_POTENCY};
+    use crate::effects::PotionEffect;
+
+    fn fire(potency: i32) -> Flask {
+        Flask { effects: vec![PotionEffect::Linger(LingeringEffect { etype: LingerType::Fire, duration: 5, dmg: 4 })], potency }
+    }
+
+    fn health(potency: i32) -> Flask {
+        Flask { effects: vec![PotionEffect::Heal(ProvidesHealing { heal_amount: 6 })], potency }
+    }
+
+    #[test]
+    fn predictions_tell_potencies_apart() {
+        let mut journal = AlchemyJournal::default();
+        let flasks = [fire(STANDARD_POTENCY), health(STANDARD_POTENCY)];
+        let mixed = Flask { effects: flasks.iter().flat_map(|f| f.effects.clone()).collect(), potency: STANDARD_POTENCY };
+        journal.record(1, &flasks, Temperature::Room, &Brew::Even(mixed));
+
+        assert!(journal.predict(&[health(STANDARD_POTENCY), fire(STANDARD_POTENCY)], Temperature::Room).is_some());
+        assert_eq!(journal.predict(&[fire(MIN_POTENCY), health(MIN_POTENCY)], Temperature::Room), None);
+        assert_eq!(journal.predict(&flasks, Temperature::Heated), None);
+    }
+
+    #[test]
+    fn found_recipes_come_before_old_mixes() {
+        let mut journal = AlchemyJournal::default();
+        let blast = PotionEffect::Explosion(Explosion { maxdmg: 10, radius: 3 });
+        let made = Flask { effects: vec![blast], potency: STANDARD_POTENCY };
+        journal.record(1, &[fire(STANDARD_POTENCY), fire(STANDARD_POTENCY)], Temperature::Room, &Brew::Special(made));
+
+        // Такие фляги ещё не смешивали, но рецепт уже известен
+        let expected = Some(MixResult::Special(vec![PotionKind::of(&blast)]));
+        assert_eq!(journal.predict(&[fire(MAX_POTENCY), fire(STANDARD_POTENCY)], Temperature::Room), expected);
+        // Слабее, чем рецепт когда-либо получался, он не подсказывается
+        assert_eq!(journal.predict(&[fire(MIN_POTENCY), fire(STANDARD_POTENCY)], Temperature::Room), None);
+    }
+
+    #[test]
+    fn recipes_need_as_many_flasks_as_they_were_found_with() {
+        let mut journal = AlchemyJournal::default();
+        let elixir = PotionEffect::Heal(ProvidesHealing { heal_amount: 30 });
+        let made = Flask { effects: vec![elixir], potency: STANDARD_POTENCY };
+        let three = [health(STANDARD_POTENCY), health(STANDARD_POTENCY), health(STANDARD_POTENCY)];
+        journal.record(1, &three, Temperature::Heated, &Brew::Special(made));
+        assert_eq!(journal.recipes[0].counts, vec![3]);
+
+        let expected = Some(MixResult::Special(vec![PotionKind::of(&elixir)]));
+        assert_eq!(journal.predict(&three, Temperature::Heated), expected);
+        // Двух фляг для эликсира мало
+        assert_eq!(journal.predict(&three[..2], Temperature::Heated), None);
+    }
+}
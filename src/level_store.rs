@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::map::Map;
+
+/// A level the player has left: its map and everything that was on it,
+/// stored in the same layout as the components of a save.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredLevel {
+    pub map: Map,
+    pub entities: Value
+}
+
+/// Уровни, на которых игрока сейчас нет, по глубине.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LevelStore {
+    pub levels: BTreeMap<i32, StoredLevel>
+}
+
+impl LevelStore {
+    pub fn new() -> LevelStore {
+        LevelStore::default()
+    }
+
+    /// Highest save marker used by the stored entities. Live entities must not
+    /// get these markers, or restoring the level would overwrite them.
+    pub fn max_marker(&self) -> Option<u64> {
+        self.levels.values()
+            .filter_map(|level| level.entities.as_object())
+            .flat_map(|components| components.values())
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(|entry| entry["marker"][0].as_u64())
+            .max()
+    }
+}
@@ -1,28 +1,17 @@
-use alchemy_system::AlchemySystem;
-use damage_system::DamageSystem;
-use gamelog::GameLog;
 use gui::draw_ui;
-use inventory_system::{InventorySystem, ItemDropSystem, ItemThrowSystem, ItemUseSystem};
-use map_indexing_system::MapIndexingSystem;
-use melee_combat_system::MeleeCombatSystem;
-use monster_ai_system::{BossAI, LobberAI, MonsterAI};
-use particle_system::ParticleSpawnSystem;
 // use rand::RngCore;
-use rltk::{GameState, Point, Rltk};
+use rltk::{GameState, Rltk};
 use specs::prelude::*;
-use specs::saveload::{SimpleMarker, SimpleMarkerAllocator};
 
 mod components;
 use components::*;
 mod map;
+mod map_builders;
 use map::*;
 mod player;
 use player::*;
 mod rect;
 mod visibility_system;
-use staineffect_system::StainEffect;
-use trap_system::TrapSystem;
-use visibility_system::VisibilitySystem;
 mod monster_ai_system;
 mod map_indexing_system;
 mod melee_combat_system;
@@ -30,41 +19,102 @@ mod damage_system;
 mod inventory_system;
 mod saveload_system;
 mod staineffect_system;
-mod trap_system;
+mod fluid_system;
+mod gas_system;
+mod fire_system;
+mod terrain_system;
 mod alchemy_system;
+mod identify_system;
+mod journal;
+mod effects;
+mod level_store;
 mod particle_system;
+mod prefab;
+mod prefab_tool;
 mod gui;
 mod gamelog;
+mod gameevent;
 mod spawner;
 mod random_table;
+mod raws;
+mod registry;
+mod replay;
+mod rng;
+mod simulation;
+use replay::{Replay, ReplayOutcome};
+use rng::RunSeed;
+use map_builders::{LevelHistory, MapgenHistory};
+use saveload_system::{SaveSlot, SlotListing};
+use simulation::{PlayerCommand, Simulation};
 
 
 pub struct State {
-    pub ecs: World,
+    pub sim: Simulation,
+    pub replay_path: String,
+    /// Shown in the main menu, e.g. why a save could not be loaded
+    pub menu_message: Option<String>,
+    /// Strict permadeath: a save is deleted as soon as it is loaded
+    pub permadeath: bool,
+    /// Slot the current run was loaded from or last saved to
+    pub slot: Option<SaveSlot>,
+    /// What the save menu shows; reread whenever the saves change
+    pub save_slots: Vec<SlotListing>,
+    /// `--mapgen`: step through how every new level was built before playing it
+    pub mapgen_viewer: bool,
+    /// Levels the viewer is showing, taken from the `MapgenHistory`
+    pub mapgen_levels: Vec<LevelHistory>,
 }
 
-impl GameState for State {
-    fn tick(&mut self, ctx : &mut Rltk) {
-        let mut newrunstate;
-        {
-            let runstate = self.ecs.fetch::<RunState>();
-            newrunstate = *runstate;
+impl State {
+    fn new_run(&mut self, seed: RunSeed) {
+        let raws = raws::Raws::clone(&self.sim.ecs.fetch::<raws::Raws>());
+        self.sim = Simulation::new(seed, raws);
+        self.sim.autosave = true;
+        self.slot = None;
+    }
+
+    /// Leaves the current run; the next "New Game" starts a fresh one.
+    /// The autosave goes too, so the run cannot be picked up from there.
+    fn end_run(&mut self) {
+        if let Err(e) = saveload_system::delete_save(SaveSlot::Auto) {
+            self.menu_message = Some(e.to_string());
         }
+        self.save_replay();
+        // В браузере сейвов нет: брошенный забег "Continue" в меню продолжает, законченный нет
+        let over = matches!(self.sim.runstate(), RunState::GameOver | RunState::Win);
+        if over || cfg!(not(target_arch = "wasm32")) {
+            self.new_run(rng::random_seed());
+        }
+        self.save_slots = saveload_system::list_slots();
+    }
 
-        if *self.ecs.fetch::<bool>() {
-            newrunstate = RunState::Win;
+    #[cfg(target_arch = "wasm32")]
+    fn save_replay(&self) {}
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_replay(&self) {
+        if let Some(recording) = &self.sim.recording {
+            if let Err(e) = recording.save(&self.replay_path) {
+                eprintln!("{}", e);
+            }
         }
+    }
+}
+
+impl GameState for State {
+    fn tick(&mut self, ctx : &mut Rltk) {
+        let mut newrunstate = self.sim.runstate();
 
         ctx.cls();
-        particle_system::cull_dead_particles(&mut self.ecs, ctx);
+        particle_system::cull_dead_particles(&mut self.sim.ecs, ctx.frame_time_ms);
 
         match newrunstate {
-            RunState::MainMenu {..} => {},
+            RunState::MainMenu {..} | RunState::SaveSlots { action: gui::SlotAction::Load, .. } | RunState::MapGeneration {..} => {},
             _ => {
-                    let positions = self.ecs.read_storage::<Position>();
-                    let renderables = self.ecs.read_storage::<Renderable>();
-                    let map = self.ecs.fetch::<Map>();
-                    draw_map(&self.ecs, ctx, map.depth);
+                    let positions = self.sim.ecs.read_storage::<Position>();
+                    let renderables = self.sim.ecs.read_storage::<Renderable>();
+                    let map = self.sim.ecs.fetch::<Map>();
+                    draw_map(&self.sim.ecs, ctx, map.depth);
                     // let player_pos = self.ecs.fetch::<Position>();
 
                     let mut data = (&positions, &renderables).join().collect::<Vec<_>>();
@@ -74,28 +124,29 @@ impl GameState for State {
                         if map.visible_tiles[idx] { ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph) }
                     }
 
-                    draw_ui(&self.ecs, ctx);
+                    draw_ui(&self.sim.ecs, ctx);
             }
         }
 
         match newrunstate {
-            RunState::PreRun => {
-                self.run_systems();
-                self.ecs.maintain();
-                newrunstate = RunState::AwaitingInput;
+            RunState::PreRun if self.mapgen_viewer && !self.sim.ecs.fetch::<MapgenHistory>().levels.is_empty() => {
+                self.mapgen_levels = std::mem::take(&mut self.sim.ecs.write_resource::<MapgenHistory>().levels);
+                newrunstate = RunState::MapGeneration { level: 0, step: 0 };
             }
-            RunState::AwaitingInput => {
-                newrunstate = player_input(self, ctx);
+            RunState::MapGeneration { level, step } => {
+                newrunstate = match gui::mapgen_viewer(ctx, &self.mapgen_levels, level, step) {
+                    gui::MapgenViewerResult::Show { level, step } => RunState::MapGeneration { level, step },
+                    gui::MapgenViewerResult::Done => {
+                        self.mapgen_levels.clear();
+                        RunState::PreRun
+                    }
+                };
             }
-            RunState::PlayerTurn => {
-                self.run_systems();
-                self.ecs.maintain();
-                newrunstate = RunState::MonsterTurn;
+            RunState::PreRun | RunState::PlayerTurn | RunState::MonsterTurn | RunState::NextLevel | RunState::PreviousLevel => {
+                newrunstate = self.sim.advance(newrunstate);
             }
-            RunState::MonsterTurn => {
-                self.run_systems();
-                self.ecs.maintain();
-                newrunstate = RunState::PreRun;
+            RunState::AwaitingInput => {
+                newrunstate = player_input(self, ctx);
             }
             RunState::ShowHelp => {
                 let result = gui::keybinds_menu(ctx);
@@ -110,13 +161,11 @@ impl GameState for State {
                     gui::ItemMenuResult::Selected
                         => {
                             let item = result.1.unwrap();
-                            let is_ranged = self.ecs.read_storage::<Ranged>();
-                            if let Some(item_ranged) = is_ranged.get(item) {
-                                newrunstate = RunState::ShowTargeting { range: item_ranged.range, item, targettype: TargetType::Use }
+                            let range = self.sim.ecs.read_storage::<Ranged>().get(item).map(|r| r.range);
+                            if let Some(range) = range {
+                                newrunstate = RunState::ShowTargeting { range, item, targettype: TargetType::Use }
                             } else {
-                                let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                                intent.insert(*self.ecs.fetch::<Entity>(), WantsToUseItem { item, target: None }).expect("Unable to insert want to use");
-                                newrunstate = RunState::PlayerTurn;
+                                newrunstate = self.sim.apply_command(PlayerCommand::Use { item, target: None });
                             }
                         }
                 }
@@ -124,12 +173,7 @@ impl GameState for State {
             RunState::ShowDropItem => {
                 match gui::drop_menu(self, ctx) {
                     (gui::ItemMenuResult::Selected, item) 
-                        => {
-                            let item = item.unwrap();
-                            let mut intent = self.ecs.write_storage::<WantsToDropItem>();
-                            intent.insert(*self.ecs.fetch::<Entity>(), WantsToDropItem { item }).expect("Unable to insert drop intent");
-                            newrunstate = RunState::PlayerTurn;
-                        }
+                        => newrunstate = self.sim.apply_command(PlayerCommand::Drop { item: item.unwrap() }),
                     (gui::ItemMenuResult::Cancel, _)
                         => newrunstate = RunState::AwaitingInput,
                     (gui::ItemMenuResult::NoResponse, _) => {}
@@ -139,7 +183,7 @@ impl GameState for State {
                 => {
                     let mut radius = 0;
                     {
-                        let aoe = self.ecs.read_storage::<AreaOfEffect>();
+                        let aoe = self.sim.ecs.read_storage::<AreaOfEffect>();
                         if let Some(r) = aoe.get(item) {
                             radius = r.radius + 1;
                         }
@@ -149,16 +193,12 @@ impl GameState for State {
                         gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
                         gui::ItemMenuResult::NoResponse => {},
                         gui::ItemMenuResult::Selected => {
-                            if targettype == TargetType::Use {
-                                let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                                intent.insert(*self.ecs.fetch::<Entity>(), WantsToUseItem { item, target: target.1 }).expect("Unable to insert use intent");
+                            let command = if targettype == TargetType::Use {
+                                PlayerCommand::Use { item, target: target.1 }
                             } else {
-                                {
-                                    let mut intent = self.ecs.write_storage::<WantsToThrowItem>();
-                                    intent.insert(*self.ecs.fetch::<Entity>(), WantsToThrowItem { item, target: target.1.unwrap() }).expect("Unable to insert throw intent");
-                                }
-                            }
-                            newrunstate = RunState::PlayerTurn;
+                                PlayerCommand::Throw { item, target: target.1.unwrap() }
+                            };
+                            newrunstate = self.sim.apply_command(command);
                         }
                     }
                 },
@@ -175,33 +215,106 @@ impl GameState for State {
                                         => newrunstate = RunState::PreRun,
                                     gui::MainMenuSelection::LoadGame
                                         => {
-                                            saveload_system::load_game(&mut self.ecs);
-                                            newrunstate = RunState::AwaitingInput;
-                                            saveload_system::delete_save();
+                                            self.save_slots = saveload_system::list_slots();
+                                            let selection = self.save_slots.iter()
+                                                .find(|l| l.header.is_some())
+                                                .map_or(SaveSlot::Auto, |l| l.slot);
+                                            newrunstate = RunState::SaveSlots { action: gui::SlotAction::Load, selection };
                                         },
                                     gui::MainMenuSelection::Quit
-                                        => {
-                                            // TODO save before quit + add "continue" in addition to "new game" during playthrough
-                                            ::std::process::exit(0);
-                                        }
+                                        => ::std::process::exit(0)
                                 }
                             }
                     }
             },
-            RunState::SaveGame => {
-                saveload_system::save_game(&mut self.ecs);
-                newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::LoadGame }
+            RunState::PauseMenu { menu_selection } => {
+                match gui::pause_menu(ctx, menu_selection) {
+                    gui::PauseMenuResult::NoSelection { selected }
+                        => newrunstate = RunState::PauseMenu { menu_selection: selected },
+                    gui::PauseMenuResult::Cancel
+                    | gui::PauseMenuResult::Selected { selected: gui::PauseMenuSelection::Continue }
+                        => newrunstate = RunState::AwaitingInput,
+                    gui::PauseMenuResult::Selected { selected: gui::PauseMenuSelection::SaveAndQuit }
+                        => {
+                            self.save_slots = saveload_system::list_slots();
+                            let selection = match self.slot {
+                                Some(SaveSlot::Manual(n)) => SaveSlot::Manual(n),
+                                _ => SaveSlot::Manual(1)
+                            };
+                            newrunstate = RunState::SaveSlots { action: gui::SlotAction::Save, selection };
+                        }
+                    gui::PauseMenuResult::Selected { selected: gui::PauseMenuSelection::Abandon }
+                        => {
+                            self.menu_message = None;
+                            self.end_run();
+                            newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::NewGame };
+                        }
+                }
             },
-            RunState::NextLevel => {
-                self.goto_next_level();
-                newrunstate = RunState::PreRun;
+            RunState::SaveSlots { action, selection } => {
+                match gui::save_slots(self, ctx, action, selection) {
+                    gui::SlotMenuResult::NoSelection { selected }
+                        => newrunstate = RunState::SaveSlots { action, selection: selected },
+                    gui::SlotMenuResult::Cancel => {
+                        newrunstate = match action {
+                            gui::SlotAction::Save => RunState::PauseMenu { menu_selection: gui::PauseMenuSelection::SaveAndQuit },
+                            gui::SlotAction::Load => RunState::MainMenu { menu_selection: gui::MainMenuSelection::LoadGame }
+                        };
+                    }
+                    gui::SlotMenuResult::Selected { selected } if action == gui::SlotAction::Save => {
+                        match saveload_system::save_game(&mut self.sim.ecs, selected) {
+                            Ok(()) => {
+                                self.menu_message = None;
+                                self.end_run();
+                                newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::LoadGame }
+                            }
+                            Err(e) => {
+                                // Забег не бросаем: можно попробовать другой слот
+                                self.sim.ecs.write_resource::<gamelog::GameLog>().entries.push(e.to_string());
+                                newrunstate = RunState::AwaitingInput;
+                            }
+                        }
+                    }
+                    gui::SlotMenuResult::Selected { selected } => {
+                        // С пермасмертью сейв стирается сразу: не вышло стереть, не загружаем
+                        let loaded = saveload_system::load_game(&mut self.sim.ecs, selected).and_then(|()| {
+                            if self.permadeath {
+                                saveload_system::delete_save(selected)
+                            } else {
+                                // Лишняя копия ничему не мешает: при целом сейве она не читается
+                                saveload_system::delete_backup(selected).ok();
+                                Ok(())
+                            }
+                        });
+                        match loaded {
+                            Ok(()) => {
+                                // Загруженный мир нельзя воспроизвести из сида
+                                self.sim.recording = None;
+                                self.sim.autosave = true;
+                                // История осталась от забега, который был до загрузки
+                                self.sim.ecs.write_resource::<MapgenHistory>().levels.clear();
+                                self.menu_message = None;
+                                self.slot = Some(selected);
+                                newrunstate = *self.sim.ecs.fetch::<RunState>();
+                            }
+                            Err(e) => {
+                                // Мир мог загрузиться наполовину, начинаем с чистого
+                                let seed = *self.sim.ecs.fetch::<RunSeed>();
+                                self.new_run(seed);
+                                self.menu_message = Some(format!("{}: {}", selected.name(), e));
+                                newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::LoadGame };
+                            }
+                        }
+                        self.save_slots = saveload_system::list_slots();
+                    }
+                }
             },
             RunState::ShowThrowItem => {
                 let result = gui::throw_menu(self, ctx);
                 match result.0 {
                     gui::ItemMenuResult::Selected => {
                         let item = result.1.unwrap();
-                        let ws = self.ecs.read_storage::<Weight>();
+                        let ws = self.sim.ecs.read_storage::<Weight>();
                         let weight = ws.get(item).map_or(1, |w| w.0);
                         newrunstate = RunState::ShowTargeting { range: 6-weight, item, targettype: TargetType::Throw };
                     },
@@ -211,211 +324,235 @@ impl GameState for State {
                         => {},
                 }
             },
-            RunState::ShowMix(first) => {
-                let result = gui::mix_potions(self, ctx, first);
-                match result.0 {
-                    gui::ItemMenuResult::Selected   
-                        => {
-                            if result.1.is_some() && result.2.is_some() {
-                                let player = self.ecs.fetch::<Entity>();
-                                let mut intent = self.ecs.write_storage::<WantsToMixPotions>();
-                                intent.insert(*player, WantsToMixPotions { 
-                                    first: result.1.unwrap(), 
-                                    second: result.2.unwrap() 
-                                }).expect("Unable to insert intent to mix");
-                                newrunstate = RunState::PlayerTurn;
-                            } else if result.1.is_some() {
-                                newrunstate = RunState::ShowMix(result.1);
-                            } else {
-                                newrunstate = RunState::ShowMix(None);
-                            }
-                        },
-                    gui::ItemMenuResult::Cancel
-                        => newrunstate = RunState::AwaitingInput, 
-                    gui::ItemMenuResult::NoResponse 
+            RunState::ShowMix { station, slots, temperature } => {
+                let result = gui::mix_potions(self, ctx, station, slots, temperature);
+                match (result, station) {
+                    // В поле смешивают сразу, как выбрана вторая фляга
+                    (gui::MixMenuResult::Changed { slots: [Some(first), Some(second), _], .. }, None)
+                        => newrunstate = self.sim.apply_command(PlayerCommand::Mix { first, second }),
+                    (gui::MixMenuResult::Changed { slots, temperature }, _)
+                        => newrunstate = RunState::ShowMix { station, slots, temperature },
+                    (gui::MixMenuResult::Brew, Some(station))
+                        => newrunstate = self.sim.apply_command(PlayerCommand::Brew { station, ingredients: slots, temperature }),
+                    (gui::MixMenuResult::Brew, None) => {
+                        if let [Some(first), Some(second), _] = slots {
+                            newrunstate = self.sim.apply_command(PlayerCommand::Mix { first, second });
+                        }
+                    }
+                    (gui::MixMenuResult::Cancel, _)
+                        => newrunstate = RunState::AwaitingInput,
+                    (gui::MixMenuResult::NoResponse, _)
                         => {},
                 }
             },
+            RunState::ShowDilute => {
+                let result = gui::dilute_menu(self, ctx);
+                match result {
+                    (gui::ItemMenuResult::Selected, Some(item))
+                        => newrunstate = self.sim.apply_command(PlayerCommand::Dilute { item }),
+                    (gui::ItemMenuResult::Cancel, _)
+                        => newrunstate = RunState::AwaitingInput,
+                    _ => {}
+                }
+            },
+            RunState::ShowJournal => {
+                if gui::show_journal(self, ctx) == gui::ItemMenuResult::Cancel { newrunstate = RunState::AwaitingInput; }
+            }
             RunState::GameOver
                 => {
-                    self.run_systems();
-                    self.ecs.maintain();
+                    newrunstate = self.sim.advance(newrunstate);
                     if gui::gameover(ctx) == gui::ItemMenuResult::Cancel {
-                        ::std::process::exit(0);
+                        self.menu_message = None;
+                        self.end_run();
+                        newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::NewGame };
                     }
             },
             RunState::Win => {
                 if gui::winscreen(ctx) == gui::ItemMenuResult::Cancel {
-                    ::std::process::exit(0);
+                    self.menu_message = None;
+                    self.end_run();
+                    newrunstate = RunState::MainMenu { menu_selection: gui::MainMenuSelection::NewGame };
                 }
             }
         }
 
-        newrunstate = damage_system::clean_up_dead(&mut self.ecs, newrunstate);
-        {
-            let mut runwriter = self.ecs.fetch_mut::<RunState>();
-            *runwriter = newrunstate;
-        }
-
+        self.sim.commit(newrunstate);
     }
 }
 
-impl State {
-    fn run_systems(&mut self) {
-        let mut vis = VisibilitySystem {};
-        vis.run_now(&self.ecs);
-        let mut mob = MonsterAI {};
-        mob.run_now(&self.ecs);
-        let mut mapindex = MapIndexingSystem {};
-        mapindex.run_now(&self.ecs);
-        let mut melee = MeleeCombatSystem {};
-        melee.run_now(&self.ecs);
-        let mut damage = DamageSystem {};
-        damage.run_now(&self.ecs);
-        let mut inventory = InventorySystem {};
-        inventory.run_now(&self.ecs);
-        let mut itemuse = ItemUseSystem {};
-        itemuse.run_now(&self.ecs);
-        let mut drop = ItemDropSystem {};
-        drop.run_now(&self.ecs);
-        let mut throw = ItemThrowSystem {};
-        throw.run_now(&self.ecs);
-        let mut boss = BossAI {};
-        boss.run_now(&self.ecs);
-        let mut alchemy = AlchemySystem {};
-        alchemy.run_now(&self.ecs);
-
-        let runstate;
-        {
-            let runstwriter = self.ecs.fetch::<RunState>();
-            runstate = *runstwriter;
-        }
-        if runstate == RunState::PlayerTurn {
-            let mut lobber = LobberAI {};
-            lobber.run_now(&self.ecs);
-            let mut trap = TrapSystem {};
-            trap.run_now(&self.ecs);
-            let mut stain = StainEffect {};
-            stain.run_now(&self.ecs);
-        }
-
-        let mut particles = ParticleSpawnSystem {};
-        particles.run_now(&self.ecs);
+#[derive(PartialEq, Clone, Copy)]
+pub enum RunState {
+    AwaitingInput,
+    PreRun,
+    PlayerTurn,
+    MonsterTurn,
+    ShowHelp,
+    ShowInventory,
+    ShowDropItem,
+    ShowTargeting{ range: i32, item: Entity, targettype: TargetType },
+    MainMenu{ menu_selection: gui::MainMenuSelection },
+    PauseMenu{ menu_selection: gui::PauseMenuSelection },
+    SaveSlots{ action: gui::SlotAction, selection: SaveSlot },
+    NextLevel,
+    PreviousLevel,
+    MapGeneration{ level: usize, step: usize },
+    ShowThrowItem,
+    ShowMix { station: Option<Entity>, slots: [Option<Entity>; simulation::STATION_SLOTS], temperature: Temperature },
+    ShowDilute,
+    ShowJournal,
+    GameOver,
+    Win
+}
 
-        self.ecs.maintain();
-    }
+#[derive(PartialEq, Clone, Copy)]
+pub enum TargetType {
+    Use,
+    Throw
+}
 
-    fn entities_to_remove_on_level_change(&mut self) -> Vec<Entity> {
-        let entities = self.ecs.entities();
-        let player_entity = self.ecs.fetch::<Entity>();
-        let player = self.ecs.read_storage::<Player>();
-        let inbackpack = self.ecs.read_storage::<InBackpack>();
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+pub struct AlchemySeed(u64);
 
-        let mut to_delete: Vec<Entity> = vec![];
-        for entity in entities.join() {
-            let mut should_delete = true;
+struct HeadlessOptions<'a> {
+    turns: u32,
+    record: Option<&'a String>,
+    /// `--events`: print every game event as it happens instead of the log
+    print_events: bool
+}
 
-            if let Some(_player) = player.get(entity) {
-                should_delete = false;
-            }
+/// `--headless <turns>`: plays the given number of waiting turns without a window
+/// and prints the game log. Useful for scripts and machines without a display.
+fn run_headless(seed: RunSeed, raws: raws::Raws, options: HeadlessOptions) -> i32 {
+    let HeadlessOptions { turns, record, print_events } = options;
+    let mut sim = Simulation::new(seed, raws);
+    if print_events {
+        sim.ecs.write_resource::<gameevent::GameEvents>().subscribe(|event| println!("{:?}", event));
+    }
+    sim.run_until_input();
 
-            if let Some(backpack_item) = inbackpack.get(entity) {
-                if backpack_item.owner == *player_entity {
-                    should_delete = false;
-                }
-            }
+    for _ in 0..turns {
+        if sim.runstate() != RunState::AwaitingInput { break; }
+        sim.play(PlayerCommand::Wait);
+    }
 
-            if should_delete {
-                to_delete.push(entity);
-            }
+    if !print_events {
+        for entry in sim.ecs.fetch::<gamelog::GameLog>().entries.iter() {
+            println!("{}", entry);
         }
-
-        to_delete
     }
 
-    fn goto_next_level(&mut self) {
-        let to_delete = self.entities_to_remove_on_level_change();
-        self.ecs.delete_entities(&to_delete).expect("Unable to delete entities");
-
-        let new_depth;
-        let mut worldmap;
-        {
-            let mut worldmap_res = self.ecs.write_resource::<Map>();
-            new_depth = worldmap_res.depth + 1;
-            *worldmap_res = Map::new_map_rooms_and_corridors(new_depth);
-            worldmap = worldmap_res.clone();
+    if let (Some(path), Some(recording)) = (record, &sim.recording) {
+        if let Err(e) = recording.save(path) {
+            eprintln!("{}", e);
         }
+    }
 
-        for room in worldmap.rooms.clone().iter() {
-            spawner::spawn_room(&mut self.ecs, room, &mut worldmap, new_depth);
-        }
+    0
+}
 
-        {   // Костыли мои костыли
-            let mut worldmap_res = self.ecs.write_resource::<Map>();
-            *worldmap_res = worldmap.clone();
+/// `--mapgen-export <file>`: writes every step of building this seed's levels,
+/// down to `--mapgen-depth <n>` (only the first one by default), as text frames.
+fn run_mapgen_export(seed: RunSeed, raws: raws::Raws, path: &str, depth: i32) -> i32 {
+    let mut sim = Simulation::new(seed, raws);
+    sim.descend_to(depth.clamp(1, LEVELNUM));
+
+    let history = sim.ecs.fetch::<MapgenHistory>();
+    let steps: usize = history.levels.iter().map(|level| level.snapshots.len()).sum();
+    match std::fs::write(path, history.to_ascii()) {
+        Ok(()) => {
+            println!("Wrote {} mapgen steps of {} levels to {}.", steps, history.levels.len(), path);
+            0
         }
-
-        let (player_x, player_y) = worldmap.rooms[0].center();
-        let mut player_pos = self.ecs.write_resource::<Point>();
-        *player_pos = Point::new(player_x, player_y);
-        let mut pos_components = self.ecs.write_storage::<Position>();
-        let player_entity = self.ecs.fetch::<Entity>();
-        
-        if let Some(player_pos_comp) = pos_components.get_mut(*player_entity) {
-            player_pos_comp.x = player_x;
-            player_pos_comp.y = player_y;
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", path, e);
+            2
         }
+    }
+}
 
-        let mut viewsheds = self.ecs.write_storage::<Viewshed>();
+/// `--replay <file>`: plays a recorded run without a window and reports
+/// the first turn where the world differs from the recording.
+fn run_replay(path: &str, raws: &raws::Raws) -> i32 {
+    let replay = match Replay::load(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
 
-        if let Some(player_vs) = viewsheds.get_mut(*player_entity) {
-            player_vs.dirty = true;
+    match replay.verify(raws) {
+        ReplayOutcome::Matched { turns } => {
+            println!("Replay matches the recording ({} turns, seed {}).", turns, replay.seed);
+            0
+        }
+        ReplayOutcome::Diverged { turn, expected, actual } => {
+            println!("Replay diverged at turn {}: checksum {:016x}, recorded {:016x}.", turn + 1, actual, expected);
+            1
         }
+        ReplayOutcome::EndedEarly { turn } => {
+            println!("Run ended at turn {}, but the recording has {} turns.", turn, replay.turns.len());
+            1
+        }
+    }
+}
 
-        let mut gamelog = self.ecs.write_resource::<GameLog>();
-        gamelog.entries.push("You descend to a next level, and take a moment to heal.".to_owned());
+fn main() -> rltk::BError {
+    let args: Vec<String> = std::env::args().collect();
 
-        if new_depth == LEVELNUM-1 {
-            gamelog.entries.push("You feel foul presence the level below.".to_owned());
+    let raws = match raws::load() {
+        Ok(raws) => raws,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
+    };
 
-        let mut stats = self.ecs.write_storage::<CombatStats>();
+    if let Some(pos) = args.iter().position(|a| a == "--prefab") {
+        std::process::exit(prefab_tool::run(&args[pos + 1..], &raws));
+    }
 
-        if let Some(player_stats) = stats.get_mut(*player_entity) {
-            player_stats.hp = i32::max(player_stats.hp, player_stats.max_hp / 2);
+    // `--seed <n>` повторяет забег; без него сид случайный
+    let seed = match args.iter().position(|a| a == "--seed") {
+        Some(pos) => {
+            let value = args.get(pos + 1).and_then(|s| s.parse().ok());
+            match value {
+                Some(value) => RunSeed(value),
+                None => {
+                    eprintln!("--seed expects a number");
+                    std::process::exit(2);
+                }
+            }
         }
+        None => rng::random_seed()
+    };
 
+    if let Some(pos) = args.iter().position(|a| a == "--replay") {
+        let path = args.get(pos + 1).map_or("replay.json", |p| p.as_str());
+        std::process::exit(run_replay(path, &raws));
     }
-}
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum RunState {
-    AwaitingInput,
-    PreRun,
-    PlayerTurn,
-    MonsterTurn,
-    ShowHelp,
-    ShowInventory,
-    ShowDropItem,
-    ShowTargeting{ range: i32, item: Entity, targettype: TargetType },
-    MainMenu{ menu_selection: gui::MainMenuSelection },
-    SaveGame,
-    NextLevel,
-    ShowThrowItem,
-    ShowMix(Option<Entity>),
-    GameOver,
-    Win
-}
+    if let Some(pos) = args.iter().position(|a| a == "--mapgen-export") {
+        let path = args.get(pos + 1).map_or("mapgen.txt", |p| p.as_str());
+        let depth = args.iter().position(|a| a == "--mapgen-depth")
+            .and_then(|pos| args.get(pos + 1))
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(1);
+        std::process::exit(run_mapgen_export(seed, raws, path, depth));
+    }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum TargetType {
-    Use,
-    Throw
-}
+    let record = args.iter().position(|a| a == "--record").and_then(|pos| args.get(pos + 1));
 
-pub struct AlchemySeed(u64);
+    if let Some(pos) = args.iter().position(|a| a == "--headless") {
+        let turns = args.get(pos + 1).and_then(|t| t.parse().ok()).unwrap_or(100);
+        let options = HeadlessOptions {
+            turns,
+            record,
+            print_events: args.iter().any(|a| a == "--events")
+        };
+        std::process::exit(run_headless(seed, raws, options));
+    }
 
-fn main() -> rltk::BError {
     use rltk::RltkBuilder;
     let mut context = RltkBuilder::simple80x50()
         .with_title("The Great Work")
@@ -423,74 +560,23 @@ fn main() -> rltk::BError {
 
     context.with_post_scanlines(false);
 
+    #[cfg(not(target_arch = "wasm32"))]
+    let menu_message = saveload_system::adopt_legacy_save().err().map(|e| e.to_string());
+    #[cfg(target_arch = "wasm32")]
+    let menu_message = None;
+
     let mut gs = State {
-        ecs: World::new(),
+        sim: Simulation::new(seed, raws),
+        replay_path: record.cloned().unwrap_or_else(|| "replay.json".to_owned()),
+        menu_message,
+        permadeath: args.iter().any(|a| a == "--permadeath"),
+        slot: None,
+        save_slots: saveload_system::list_slots(),
+        mapgen_viewer: args.iter().any(|a| a == "--mapgen"),
+        mapgen_levels: vec![],
     };
-
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<Monster>();
-    gs.ecs.register::<Name>();
-    gs.ecs.register::<BlocksTile>();
-    gs.ecs.register::<CombatStats>();
-    gs.ecs.register::<SufferDamage>();
-    gs.ecs.register::<Item>();
-    gs.ecs.register::<ProvidesHealing>();
-    gs.ecs.register::<Consumable>();
-    gs.ecs.register::<InBackpack>();
-    gs.ecs.register::<WantsToMelee>();
-    gs.ecs.register::<WantsToPickupItem>();
-    gs.ecs.register::<WantsToUseItem>();
-    gs.ecs.register::<WantsToDropItem>();
-    gs.ecs.register::<WantsToThrowItem>();
-    gs.ecs.register::<WantsToMixPotions>();
-    gs.ecs.register::<Ranged>();
-    gs.ecs.register::<InflictsDamage>();
-    gs.ecs.register::<AreaOfEffect>();
-    gs.ecs.register::<Confusion>();
-    gs.ecs.register::<Agitated>();
-    gs.ecs.register::<Teleport>();
-    gs.ecs.register::<Weight>();
-    gs.ecs.register::<ParticleLifetime>();
-    gs.ecs.register::<Puddle>();
-    gs.ecs.register::<Potion>();
-    gs.ecs.register::<LingeringEffect>();
-    gs.ecs.register::<InstantHarm>();
-    gs.ecs.register::<Explosion>();
-    gs.ecs.register::<Invulnerability>();
-    gs.ecs.register::<Strength>();
-    gs.ecs.register::<Bomber>();
-    gs.ecs.register::<Lobber>();
-    gs.ecs.register::<Boss>();
-    gs.ecs.register::<MacGuffin>();
-    gs.ecs.register::<SimpleMarker<SerializeMe>>();
-    gs.ecs.register::<SerializationHelper>();
-
-    gs.ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
-
-    let mut map = Map::new_map_rooms_and_corridors(1);
-    let (player_x, player_y) = map.rooms[0].center();
-    
-    let mut rng = rltk::RandomNumberGenerator::new();
-    gs.ecs.insert(AlchemySeed(rng.next_u64()));
-    gs.ecs.insert(rng);
-
-    gs.ecs.insert(particle_system::ParticleBuilder::new());
-
-    gs.ecs.insert(Point::new(player_x, player_y));
-    let player_entity = spawner::player(&mut gs.ecs, player_x, player_y);
-    gs.ecs.insert(player_entity);
-
-    for room in map.rooms.clone().iter().skip(1) {
-        spawner::spawn_room(&mut gs.ecs, room, &mut map, 1);
-    }
-
-    gs.ecs.insert(map);
-    gs.ecs.insert(gamelog::GameLog { entries: vec!["Welcome to the dungeon of doom!".to_string()] });
-    gs.ecs.insert(RunState::MainMenu { menu_selection: gui::MainMenuSelection::NewGame });
-    gs.ecs.insert(false);
+    gs.sim.autosave = true;
+    gs.sim.set_runstate(RunState::MainMenu { menu_selection: gui::MainMenuSelection::NewGame });
 
     rltk::main_loop(context, gs)
 
@@ -1,7 +1,10 @@
-use rltk::{to_cp437, Algorithm2D, BaseMap, FontCharType, Point, RandomNumberGenerator, Rltk, RGB};
+use rltk::{to_cp437, Algorithm2D, BaseMap, FontCharType, Point, Rltk, RGB};
 use serde::{Deserialize, Serialize};
 use specs::{Entity, World};
 
+use crate::components::{LingerType, LingeringEffect};
+use crate::effects::PotionEffect;
+
 use super::rect::*;
 use std::cmp::{min, max};
 
@@ -12,15 +15,85 @@ pub const MAPCOUNT: usize = MAPHEIGHT * MAPWIDTH;
 pub const LEVELNUM: i32 = 8;
 #[cfg(debug_assertions)]
 pub const LEVELNUM: i32 = 8;
+/// How far a burning tile lights up the floor around it.
+pub const FIRE_LIGHT: i32 = 2;
 
 #[derive(PartialEq, Clone, Copy, Serialize, Deserialize, Debug)]
 pub enum TileType {
     Wall,
     Floor,
     DownStairs,
+    UpStairs,
     BossSpawner,
     FinalDoor,
-    MacGuffinSpawner
+    MacGuffinSpawner,
+    ShallowWater,
+    DeepWater,
+    Lava,
+    Grass,
+    Rubble,
+    Chasm,
+    /// What is left of burnt grass
+    Ash
+}
+
+/// Разлитое зелье на клетке: что в нём и сколько.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Fluid {
+    pub effects: Vec<PotionEffect>,
+    pub color: RGB,
+    pub volume: i32
+}
+
+impl Fluid {
+    /// Spilled Fire sets grass and vapour alight.
+    pub fn burns(&self) -> bool {
+        self.effects.iter().any(|e| matches!(e, PotionEffect::Linger(LingeringEffect { etype: LingerType::Fire, .. })))
+    }
+}
+
+/// Облака газа, см. `GasSystem`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GasType {
+    Poison,
+    Confusion,
+    /// Burns up when it meets fire
+    Vapour,
+    /// Blocks sight when thick
+    Smoke
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Gas {
+    pub gtype: GasType,
+    pub density: i32
+}
+
+impl Gas {
+    pub fn blocks_sight(&self) -> bool {
+        self.gtype == GasType::Smoke && self.density >= 10
+    }
+}
+
+impl GasType {
+    pub fn name(self) -> &'static str {
+        match self {
+            GasType::Poison => "Poison gas",
+            GasType::Confusion => "Confusing fumes",
+            GasType::Vapour => "Vapour",
+            GasType::Smoke => "Smoke"
+        }
+    }
+
+    pub fn color(self) -> RGB {
+        match self {
+            GasType::Poison => RGB::from_f32(0.3, 0.6, 0.1),
+            GasType::Confusion => RGB::named(rltk::PINK),
+            GasType::Vapour => RGB::from_f32(0.7, 0.7, 0.4),
+            GasType::Smoke => RGB::named(rltk::GREY)
+        }
+    }
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
@@ -32,7 +105,12 @@ pub struct Map {
     pub revealed_tiles: Vec<bool>,
     pub visible_tiles: Vec<bool>,
     pub blocked: Vec<bool>,
-    pub puddles: Vec<i32>,
+    /// Spilled potions, see `FluidSystem`
+    pub fluids: Vec<Option<Fluid>>,
+    /// Gas clouds, see `GasSystem`
+    pub gases: Vec<Option<Gas>>,
+    /// Fuel left on burning tiles, 0 where nothing burns. See `FireSystem`
+    pub fire: Vec<i32>,
     pub depth: i32,
 
     #[serde(skip_serializing)]
@@ -40,13 +118,81 @@ pub struct Map {
     pub tile_content: Vec<Vec<Entity>>
 }
 
+/// Everything but walls and the sealed door of the lab.
+pub fn tile_walkable(tile: TileType) -> bool {
+    tile != TileType::Wall && tile != TileType::FinalDoor
+}
+
+/// Tall grass hides what is behind it, like a wall.
+pub fn tile_opaque(tile: TileType) -> bool {
+    matches!(tile, TileType::Wall | TileType::FinalDoor | TileType::Grass)
+}
+
+/// Walkable, but nobody should end up there: lava burns, a chasm drops you a level.
+pub fn tile_hazard(tile: TileType) -> bool {
+    matches!(tile, TileType::Lava | TileType::Chasm)
+}
+
+/// Deep water, lava and chasms swallow whatever is spilled into them.
+pub fn tile_holds_fluid(tile: TileType) -> bool {
+    tile_walkable(tile) && !matches!(tile, TileType::DeepWater | TileType::Lava | TileType::Chasm)
+}
+
+/// Gas goes wherever one can walk, and over water, lava and chasms too.
+pub fn tile_holds_gas(tile: TileType) -> bool {
+    tile_walkable(tile)
+}
+
+/// Neighbours of the tile across its sides, within the map. Liquids and gas
+/// spread only this way, so nothing leaks through the corners of walls.
+pub fn cardinal_neighbours(idx: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = (idx % MAPWIDTH, idx / MAPWIDTH);
+    [(x > 0, idx.wrapping_sub(1)), (x + 1 < MAPWIDTH, idx + 1), (y > 0, idx.wrapping_sub(MAPWIDTH)), (y + 1 < MAPHEIGHT, idx + MAPWIDTH)]
+        .into_iter()
+        .filter_map(|(inside, n)| inside.then_some(n))
+}
+
+/// How many turns the tile burns once set alight; 0 if it does not burn.
+pub fn tile_fuel(tile: TileType) -> i32 {
+    match tile {
+        TileType::Grass => 6,
+        _ => 0
+    }
+}
+
+/// Cost of a step onto the tile for monsters looking for a path.
+pub fn tile_cost(tile: TileType) -> f32 {
+    match tile {
+        TileType::ShallowWater => 1.5,
+        TileType::Rubble => 2.0,
+        TileType::DeepWater => 3.0,
+        TileType::Lava => 10.0,
+        _ => 1.0
+    }
+}
+
+/// What the tooltip says about the tile; nothing for plain floor and walls.
+pub fn tile_name(tile: TileType) -> Option<&'static str> {
+    match tile {
+        TileType::ShallowWater => Some("Shallow water"),
+        TileType::DeepWater => Some("Deep water"),
+        TileType::Lava => Some("Lava"),
+        TileType::Grass => Some("Tall grass"),
+        TileType::Rubble => Some("Rubble"),
+        TileType::Chasm => Some("Chasm"),
+        TileType::Ash => Some("Ash"),
+        _ => None
+    }
+}
+
 impl Map {
     pub fn xy_idx(&self, x: i32, y: i32) -> usize {
         (y as usize * MAPWIDTH) + x as usize
     }
 
-    pub fn new_map_rooms_and_corridors(new_depth: i32) -> Map {
-        let mut map = Map {
+    /// A map of solid wall, for the builders to carve.
+    pub fn new(depth: i32) -> Map {
+        Map {
             tiles: vec![TileType::Wall; MAPCOUNT],
             rooms: vec![],
             width: MAPWIDTH as i32,
@@ -55,73 +201,11 @@ impl Map {
             visible_tiles: vec![false; MAPCOUNT],
             blocked: vec![false; MAPCOUNT],
             tile_content: vec![vec![]; MAPCOUNT],
-            puddles: vec![0; MAPCOUNT],
-            depth: new_depth
-        };
-
-        const MAX_ROOMS: i32 = 30;
-        const MIN_SIZE: i32 = 7;
-        const MAX_SIZE: i32 = 10;
-
-        let mut rng = RandomNumberGenerator::new();
-
-        if new_depth == LEVELNUM {
-            map.final_level(&mut rng);
-
-            return map;
-        }
-
-        for _ in 0..MAX_ROOMS {
-            let w = rng.range(MIN_SIZE, MAX_SIZE);
-            let h = rng.range(MIN_SIZE, MAX_SIZE);
-            let x = rng.roll_dice(1, MAPWIDTH as i32 - w - 1) - 1;
-            let y = rng.roll_dice(1, MAPHEIGHT as i32 - h - 1) - 1;
-
-            let new_room = Rect::new(x, y, w, h);
-
-            let mut room_ok = true;
-            for other_room in map.rooms.iter() {
-                room_ok &= !other_room.intersect(&new_room);
-
-            }
-
-            if room_ok {
-                if !map.rooms.is_empty() {
-                    let (new_x, new_y) = new_room.center();
-                    let (prev_x, prev_y) = map.rooms[map.rooms.len()-1].center();
-
-                    if rng.range(0, 2) == 1 {
-                        map.apply_horizontal_tunnel(prev_x, new_x, prev_y);
-                        map.apply_vertical_tunnel(prev_y, new_y, new_x);
-                    } else {
-                        map.apply_vertical_tunnel(prev_y, new_y, prev_x);
-                        map.apply_horizontal_tunnel(prev_x, new_x, new_y);
-                    }
-                }
-
-                map.apply_room_to_map(&new_room);
-                map.rooms.push(new_room);
-            }
+            fluids: vec![None; MAPCOUNT],
+            gases: vec![None; MAPCOUNT],
+            fire: vec![0; MAPCOUNT],
+            depth
         }
-
-        let (stair_x, stair_y) = map.rooms.iter()
-                                          .last()
-                                          .expect("No rooms were generated?")
-                                          .center();
-
-        let idx = map.xy_idx(stair_x, stair_y);
-        map.tiles[idx] = TileType::DownStairs;
-
-        map
-    }
-
-    pub fn final_level(&mut self, rng: &mut RandomNumberGenerator) {
-        let x = rng.roll_dice(1, (MAPWIDTH-33) as i32);
-        let y = rng.roll_dice(1, (MAPHEIGHT-16) as i32);
-
-        self.apply_final_lab(x, y);
-
-        self.rooms.push(Rect { x1: x, x2: x+15, y1: y, y2: y+15 });
     }
 
     pub fn apply_room_to_map(&mut self, room: &Rect) {
@@ -133,20 +217,6 @@ impl Map {
         }
     }
 
-    pub fn apply_final_lab(&mut self, x: i32, y: i32) {
-        let room_bytes = include_bytes!("../room.bin");
-        for (i, b) in room_bytes.iter().enumerate() {
-            let idx = self.xy_idx(x + (i % 32) as i32, y + (i / 32) as i32);
-            self.tiles[idx] = match b {
-                1 => TileType::Wall,
-                2 => TileType::BossSpawner,
-                3 => TileType::FinalDoor,
-                4 => TileType::MacGuffinSpawner,
-                _ => TileType::Floor,
-            };
-        }
-    }
-
     pub fn apply_horizontal_tunnel(&mut self, x1: i32, x2: i32, y: i32) {
         for x in min(x1, x2)..=max(x1, x2) {
             let idx = self.xy_idx(x, y);
@@ -172,15 +242,71 @@ impl Map {
         }
         let idx = self.xy_idx(x, y);
 
-        !self.blocked[idx]
+        // В пропасть монстры сами не прыгают
+        !self.blocked[idx] && self.tiles[idx] != TileType::Chasm
+    }
+
+    fn exit_cost(&self, idx: usize) -> f32 {
+        tile_cost(self.tiles[idx])
+    }
+
+    /// Coordinates of the first tile of the given type, e.g. the stairs.
+    pub fn find_tile(&self, tile: TileType) -> Option<(i32, i32)> {
+        self.tiles.iter()
+            .position(|t| *t == tile)
+            .map(|idx| (idx as i32 % self.width, idx as i32 / self.width))
     }
 
     pub fn populate_blocked(&mut self) {
         for (i, tile) in self.tiles.iter().enumerate() {
-            self.blocked[i] = *tile == TileType::Wall || *tile == TileType::FinalDoor;
+            self.blocked[i] = !tile_walkable(*tile);
+        }
+    }
+
+    /// Spills `fluid` onto the tile. The same potion adds up, a different one
+    /// is washed away by it. Lost if the tile cannot hold liquid or there is none to spill.
+    pub fn pour(&mut self, idx: usize, fluid: Fluid) {
+        if fluid.volume <= 0 || !tile_holds_fluid(self.tiles[idx]) {
+            return;
+        }
+        match &mut self.fluids[idx] {
+            Some(existing) if existing.effects == fluid.effects => existing.volume += fluid.volume,
+            slot => *slot = Some(fluid)
         }
     }
 
+    /// Lets `gas` out on the tile. The same gas adds up, a different one
+    /// pushes the old cloud out.
+    pub fn release_gas(&mut self, idx: usize, gas: Gas) {
+        if !tile_holds_gas(self.tiles[idx]) || gas.density <= 0 {
+            return;
+        }
+        match &mut self.gases[idx] {
+            Some(existing) if existing.gtype == gas.gtype => existing.density += gas.density,
+            slot => *slot = Some(gas)
+        }
+    }
+
+    /// Sets the tile alight if there is anything on it to burn and it
+    /// is not burning already. Returns whether it caught fire.
+    pub fn set_alight(&mut self, idx: usize) -> bool {
+        let fuel = tile_fuel(self.tiles[idx]);
+        if self.fire[idx] > 0 || fuel <= 0 {
+            return false;
+        }
+        self.fire[idx] = fuel;
+        true
+    }
+
+    /// Whether a fire burns close enough to light the tile up.
+    pub fn is_lit(&self, idx: usize) -> bool {
+        let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+        (-FIRE_LIGHT..=FIRE_LIGHT).any(|dy| (-FIRE_LIGHT..=FIRE_LIGHT).any(|dx| {
+            let (nx, ny) = (x + dx, y + dy);
+            nx >= 0 && nx < self.width && ny >= 0 && ny < self.height && self.fire[self.xy_idx(nx, ny)] > 0
+        }))
+    }
+
     pub fn clear_content_index(&mut self) {
         for tile in self.tile_content.iter_mut() {
             tile.clear();
@@ -189,8 +315,11 @@ impl Map {
 }
 
 pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
-    let map = ecs.fetch::<Map>();
+    draw_tiles(&ecs.fetch::<Map>(), ctx, map_depth);
+}
 
+/// Draws the revealed part of `map`; the mapgen viewer uses it for snapshots.
+pub fn draw_tiles(map: &Map, ctx: &mut Rltk, map_depth: i32) {
     let mut x = 0;
     let mut y = 0;
 
@@ -202,7 +331,7 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
 
     for (idx, tile) in map.tiles.iter().enumerate() {
         if map.revealed_tiles[idx] {
-            let glyph: FontCharType;
+            let mut glyph: FontCharType;
             let mut fg: RGB;
             match tile {
                 TileType::Floor => {
@@ -211,7 +340,7 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
                 }
                 TileType::Wall => {
                     // glyph = rltk::to_cp437('#');
-                    glyph = wall_glyph(&*map, x, y);
+                    glyph = wall_glyph(map, x, y);
                     fg = RGB::from_f32(0.85+tint.0, 0.85+tint.1, 0.85+tint.2);
                 }
                 TileType::DownStairs => {
@@ -222,6 +351,10 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
                         fg = RGB::from_f32(0.8, 0.8, 0.95);
                     }
                 },
+                TileType::UpStairs => {
+                    glyph = rltk::to_cp437('<');
+                    fg = RGB::from_f32(0.8, 0.8, 0.95);
+                },
                 TileType::BossSpawner => {
                     glyph = rltk::to_cp437('A');
                     fg = RGB::from_f32(1.0, 1.0, 1.0);
@@ -234,6 +367,34 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
                     glyph = rltk::to_cp437('☼');
                     fg = RGB::named(rltk::GOLD);
                 }
+                TileType::ShallowWater => {
+                    glyph = rltk::to_cp437('~');
+                    fg = RGB::from_f32(0.3, 0.55, 0.9);
+                }
+                TileType::DeepWater => {
+                    glyph = rltk::to_cp437('≈');
+                    fg = RGB::from_f32(0.1, 0.25, 0.85);
+                }
+                TileType::Lava => {
+                    glyph = rltk::to_cp437('≈');
+                    fg = RGB::from_f32(1.0, 0.35, 0.0);
+                }
+                TileType::Grass => {
+                    glyph = rltk::to_cp437('"');
+                    fg = RGB::from_f32(0.2, 0.7, 0.2);
+                }
+                TileType::Rubble => {
+                    glyph = rltk::to_cp437(';');
+                    fg = RGB::from_f32(0.55, 0.5, 0.4);
+                }
+                TileType::Chasm => {
+                    glyph = chasm_glyph(map, x, y);
+                    fg = RGB::from_f32(0.35, 0.3, 0.45);
+                }
+                TileType::Ash => {
+                    glyph = rltk::to_cp437(',');
+                    fg = RGB::from_f32(0.4, 0.38, 0.36);
+                }
             }
             if !map.visible_tiles[idx] { 
                 let mut darkest = f32::min(fg.b, f32::min(fg.g, fg.r));
@@ -244,7 +405,26 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk, map_depth: i32) {
                 fg.g = darkest;
                 fg.r = darkest;
             }
-            ctx.set(x, y, fg, RGB::from_f32(0., 0., 0.), glyph);
+            // Лужи видно, только пока на них смотришь
+            let mut bg = RGB::from_f32(0., 0., 0.);
+            if let (true, Some(fluid)) = (map.visible_tiles[idx], &map.fluids[idx]) {
+                let depth = 0.4 + 0.6 * i32::min(fluid.volume, 6) as f32 / 6.0;
+                bg = RGB::from_f32(fluid.color.r * depth, fluid.color.g * depth, fluid.color.b * depth);
+            }
+            // Газ поверх луж, тем гуще, чем плотнее облако
+            if let (true, Some(gas)) = (map.visible_tiles[idx], &map.gases[idx]) {
+                let thickness = 0.2 + 0.6 * i32::min(gas.density, 30) as f32 / 30.0;
+                bg = bg.lerp(gas.gtype.color(), thickness);
+            }
+            // Пламя рисуем поверх всего, вокруг него светлее
+            if map.visible_tiles[idx] && map.fire[idx] > 0 {
+                glyph = rltk::to_cp437('▲');
+                fg = if map.fire[idx] % 2 == 0 { RGB::named(rltk::ORANGE) } else { RGB::named(rltk::YELLOW) };
+                bg = RGB::from_f32(0.5, 0.1, 0.0);
+            } else if map.visible_tiles[idx] && map.is_lit(idx) {
+                bg = bg.lerp(RGB::from_f32(0.3, 0.15, 0.0), 0.5);
+            }
+            ctx.set(x, y, fg, bg, glyph);
         }
 
         x += 1;
@@ -259,10 +439,10 @@ fn wall_glyph(map: &Map, x: i32, y: i32) -> rltk::FontCharType {
     if x < 1 || x > map.width - 2 || y < 1 || y > map.height - 2 { return to_cp437('#'); }
     let mut mask: u8 = 0;
 
-    if is_revealed_and_wall(map, x, y-1) { mask += 1; }
-    if is_revealed_and_wall(map, x, y+1) { mask += 2; }
-    if is_revealed_and_wall(map, x-1, y) { mask += 4; }
-    if is_revealed_and_wall(map, x+1, y) { mask += 8; }
+    if is_revealed_and(map, x, y-1, TileType::Wall) { mask += 1; }
+    if is_revealed_and(map, x, y+1, TileType::Wall) { mask += 2; }
+    if is_revealed_and(map, x-1, y, TileType::Wall) { mask += 4; }
+    if is_revealed_and(map, x+1, y, TileType::Wall) { mask += 8; }
 
     match mask {
         0 => to_cp437('#'),
@@ -285,8 +465,15 @@ fn wall_glyph(map: &Map, x: i32, y: i32) -> rltk::FontCharType {
     }
 }
 
-fn is_revealed_and_wall(map: &Map, x: i32, y: i32) -> bool {
-    map.revealed_tiles[map.xy_idx(x, y)] && map.tiles[map.xy_idx(x, y)] == TileType::Wall
+// Край пропасти рисуем штриховкой, середину точками
+fn chasm_glyph(map: &Map, x: i32, y: i32) -> rltk::FontCharType {
+    if x < 1 || x > map.width - 2 || y < 1 || y > map.height - 2 { return to_cp437('░'); }
+    let inner = (-1..=1).all(|dy| (-1..=1).all(|dx| map.tiles[map.xy_idx(x + dx, y + dy)] == TileType::Chasm));
+    if inner { to_cp437('·') } else { to_cp437('░') }
+}
+
+fn is_revealed_and(map: &Map, x: i32, y: i32, tile: TileType) -> bool {
+    map.revealed_tiles[map.xy_idx(x, y)] && map.tiles[map.xy_idx(x, y)] == tile
 }
 
 impl Algorithm2D for Map {
@@ -297,7 +484,7 @@ impl Algorithm2D for Map {
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx] == TileType::Wall || self.tiles[idx] == TileType::FinalDoor
+        tile_opaque(self.tiles[idx]) || self.gases[idx].is_some_and(|gas| gas.blocks_sight())
     }
 
     fn get_available_exits(&self, idx: usize) -> rltk::SmallVec<[(usize, f32); 10]> {
@@ -307,14 +494,14 @@ impl BaseMap for Map {
         let w = MAPWIDTH;
 
         // Cardinal directions
-        if self.is_exit_valid(x-1, y) { exits.push((idx-1, 1.0));}      // W
-        if self.is_exit_valid(x+1, y) { exits.push((idx+1, 1.0));}      // E
-        if self.is_exit_valid(x, y-1) { exits.push((idx-w, 1.0));}      // N
-        if self.is_exit_valid(x, y+1) { exits.push((idx+w, 1.0));}      // S
-        if self.is_exit_valid(x-1, y+1) { exits.push((idx-1+w, 1.0));} // SW
-        if self.is_exit_valid(x+1, y-1) { exits.push((idx+1-w, 1.0));} // NE
-        if self.is_exit_valid(x-1, y-1) { exits.push((idx-1-w, 1.0));} // NW
-        if self.is_exit_valid(x+1, y+1) { exits.push((idx+1+w, 1.0));} // SE
+        if self.is_exit_valid(x-1, y) { exits.push((idx-1, self.exit_cost(idx-1)));}      // W
+        if self.is_exit_valid(x+1, y) { exits.push((idx+1, self.exit_cost(idx+1)));}      // E
+        if self.is_exit_valid(x, y-1) { exits.push((idx-w, self.exit_cost(idx-w)));}      // N
+        if self.is_exit_valid(x, y+1) { exits.push((idx+w, self.exit_cost(idx+w)));}      // S
+        if self.is_exit_valid(x-1, y+1) { exits.push((idx-1+w, self.exit_cost(idx-1+w)));} // SW
+        if self.is_exit_valid(x+1, y-1) { exits.push((idx+1-w, self.exit_cost(idx+1-w)));} // NE
+        if self.is_exit_valid(x-1, y-1) { exits.push((idx-1-w, self.exit_cost(idx-1-w)));} // NW
+        if self.is_exit_valid(x+1, y+1) { exits.push((idx+1+w, self.exit_cost(idx+1+w)));} // SE
 
         exits
     }
@@ -0,0 +1,799 @@
+//! Генераторы уровней. Каждый строит карту и сообщает, где появляется игрок,
+//! где лестницы вниз и в каких областях спавнить монстров и предметы.
+//! Какой генератор взять на какой глубине, решают правила из raws.
+
+use std::collections::VecDeque;
+
+use rltk::{FontCharType, Point, RandomNumberGenerator};
+
+use crate::map::{self, Map, TileType, LEVELNUM, MAPCOUNT, MAPHEIGHT, MAPWIDTH};
+use crate::prefab::{Prefab, PrefabSpawn};
+use crate::random_table::RandomTable;
+use crate::raws::{Raws, FINAL_LAB};
+use crate::rect::Rect;
+
+/// Builder ids that the raws can refer to.
+pub const BUILDERS: &[&str] = &["rooms_and_corridors", "bsp", "cellular_automata", "drunkards_walk", "maze"];
+/// Terrain ids that the raws can refer to.
+pub const TERRAIN: &[&str] = &["grass", "water", "lava", "rubble", "chasm"];
+const DEFAULT_BUILDER: &str = "rooms_and_corridors";
+// Не для raws: лаборатория всегда на последнем уровне
+const FINAL_BUILDER: &str = "final_lab";
+const MAX_ATTEMPTS: usize = 10;
+
+/// A generated level before anything is spawned on it.
+pub struct BuiltLevel {
+    pub map: Map,
+    /// Where the player appears
+    pub start: Point,
+    /// Ways down; `build_level` puts the stairs there
+    pub exits: Vec<Point>,
+    /// Groups of tile indices, each gets its own roll on the spawn table
+    pub spawn_regions: Vec<Vec<usize>>,
+    /// What the prefabs on the level want spawned, by tile index
+    pub spawns: Vec<(usize, PrefabSpawn)>
+}
+
+pub trait MapBuilder {
+    /// Builds the level, putting a snapshot into `history` after every notable step.
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel;
+}
+
+/// One step of building a level.
+#[derive(Clone)]
+pub struct MapSnapshot {
+    pub label: String,
+    pub tiles: Vec<TileType>,
+    /// What stands on the map; only the last step of a level has anything here
+    pub spawns: Vec<(Point, FontCharType)>
+}
+
+impl MapSnapshot {
+    /// A fully revealed map with these tiles, so `draw_tiles` can show it.
+    pub fn to_map(&self, depth: i32) -> Map {
+        let mut map = Map::new(depth);
+        map.tiles = self.tiles.clone();
+        map.revealed_tiles = vec![true; MAPCOUNT];
+        map.visible_tiles = vec![true; MAPCOUNT];
+        map
+    }
+
+    pub fn to_ascii(&self) -> String {
+        let mut rows: Vec<Vec<char>> = self.tiles.chunks(MAPWIDTH)
+            .map(|row| row.iter().map(|tile| tile_char(*tile)).collect())
+            .collect();
+        for (pos, glyph) in self.spawns.iter() {
+            rows[pos.y as usize][pos.x as usize] = rltk::to_char(*glyph as u8);
+        }
+        rows.into_iter().map(|row| row.into_iter().collect::<String>() + "\n").collect()
+    }
+}
+
+/// How one level was built, step by step.
+pub struct LevelHistory {
+    pub depth: i32,
+    pub builder: String,
+    pub snapshots: Vec<MapSnapshot>
+}
+
+/// Snapshots of every level generated since the history was last taken.
+/// Used by the `--mapgen` viewer and `--mapgen-export`; not saved.
+#[derive(Default)]
+pub struct MapgenHistory {
+    pub levels: Vec<LevelHistory>
+}
+
+impl MapgenHistory {
+    fn begin(&mut self, depth: i32, builder: &str) {
+        self.levels.push(LevelHistory { depth, builder: builder.to_owned(), snapshots: vec![] });
+    }
+
+    pub fn snapshot(&mut self, label: &str, map: &Map) {
+        self.snapshot_spawns(label, map, vec![]);
+    }
+
+    pub fn snapshot_spawns(&mut self, label: &str, map: &Map, spawns: Vec<(Point, FontCharType)>) {
+        if let Some(level) = self.levels.last_mut() {
+            level.snapshots.push(MapSnapshot { label: label.to_owned(), tiles: map.tiles.clone(), spawns });
+        }
+    }
+
+    /// Every snapshot as a text frame with a header line.
+    pub fn to_ascii(&self) -> String {
+        let mut frames = String::new();
+        for level in self.levels.iter() {
+            for (step, snapshot) in level.snapshots.iter().enumerate() {
+                frames += &format!("== depth {}, {}, step {}/{}: {} ==\n",
+                    level.depth, level.builder, step + 1, level.snapshots.len(), snapshot.label);
+                frames += &snapshot.to_ascii();
+            }
+        }
+        frames
+    }
+}
+
+pub fn tile_char(tile: TileType) -> char {
+    match tile {
+        TileType::Wall => '#',
+        TileType::Floor => '.',
+        TileType::DownStairs => '>',
+        TileType::UpStairs => '<',
+        TileType::BossSpawner => 'A',
+        TileType::FinalDoor => '|',
+        TileType::MacGuffinSpawner => '*',
+        TileType::ShallowWater => '~',
+        TileType::DeepWater => '=',
+        TileType::Lava => '&',
+        TileType::Grass => '"',
+        TileType::Rubble => ',',
+        TileType::Chasm => ':',
+        TileType::Ash => '_'
+    }
+}
+
+/// Picks a builder for `depth` by the raws rules and builds the level with its stairs.
+/// The last level is always the alchemist's lab.
+/// A level that fails `ensure_connected` is thrown away and built again;
+/// after `MAX_ATTEMPTS` the plain default builder is used instead.
+pub fn build_level(depth: i32, raws: &Raws, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+    for _ in 0..MAX_ATTEMPTS {
+        let mut level = build_once(depth, raws, rng, history, false);
+        match ensure_connected(&mut level, history) {
+            Ok(()) => return level,
+            Err(reason) => history.snapshot(&format!("rejected: {}", reason), &level.map)
+        }
+    }
+    // Комнаты с коридорами без хранилищ и рельефа связны всегда
+    let mut level = build_once(depth, raws, rng, history, true);
+    if let Err(reason) = ensure_connected(&mut level, history) {
+        history.snapshot(&format!("kept anyway: {}", reason), &level.map);
+    }
+    level
+}
+
+fn build_once(depth: i32, raws: &Raws, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory, fallback: bool) -> BuiltLevel {
+    let mut level = if depth == LEVELNUM {
+        history.begin(depth, FINAL_BUILDER);
+        FinalLevelBuilder::new(raws).build(depth, rng, history)
+    } else if fallback {
+        history.begin(depth, DEFAULT_BUILDER);
+        builder_by_id(DEFAULT_BUILDER).build(depth, rng, history)
+    } else {
+        let id = choose_builder(depth, raws, rng);
+        history.begin(depth, &id);
+        let mut level = builder_by_id(&id).build(depth, rng, history);
+        place_vaults(&mut level, depth, raws, rng, history);
+        place_terrain(&mut level, depth, raws, rng, history);
+        level
+    };
+
+    for exit in level.exits.iter() {
+        let idx = level.map.xy_idx(exit.x, exit.y);
+        level.map.tiles[idx] = TileType::DownStairs;
+    }
+    if depth > 1 {
+        let idx = level.map.xy_idx(level.start.x, level.start.y);
+        if level.map.tiles[idx] == TileType::Floor {
+            level.map.tiles[idx] = TileType::UpStairs;
+        }
+    }
+    history.snapshot("stairs placed", &level.map);
+
+    level
+}
+
+/// Everything walkable must be reachable from the start, the sealed door
+/// counting as open. A pocket with an exit, a spawner or a prefab spawn in it
+/// gets a tunnel to the nearest reachable tile, an empty one is filled in.
+/// Fails if the start or an exit cannot be used at all.
+fn ensure_connected(level: &mut BuiltLevel, history: &mut MapgenHistory) -> Result<(), String> {
+    let start_idx = level.map.xy_idx(level.start.x, level.start.y);
+    if !map::tile_walkable(level.map.tiles[start_idx]) {
+        return Err("the start is in a wall".to_owned());
+    }
+    if level.exits.is_empty() && level.map.depth != LEVELNUM {
+        return Err("there is no way down".to_owned());
+    }
+    if level.exits.contains(&level.start) {
+        return Err("the way down is where the player starts".to_owned());
+    }
+
+    let passable = |tile: TileType| tile != TileType::Wall;
+    let exits: Vec<usize> = level.exits.iter().map(|e| level.map.xy_idx(e.x, e.y)).collect();
+    loop {
+        let distances = flood(&level.map, start_idx, passable);
+        let Some(pocket_idx) = (0..MAPCOUNT).find(|idx| distances[*idx].is_none() && passable(level.map.tiles[*idx])) else {
+            break;
+        };
+        let pocket: Vec<usize> = flood(&level.map, pocket_idx, passable).iter().enumerate()
+            .filter_map(|(idx, distance)| distance.map(|_| idx))
+            .collect();
+
+        let needed = pocket.iter().any(|idx| {
+            exits.contains(idx)
+                || level.spawns.iter().any(|(spawn_idx, _)| spawn_idx == idx)
+                || matches!(level.map.tiles[*idx], TileType::BossSpawner | TileType::MacGuffinSpawner)
+        });
+        if needed {
+            let (x, y) = ((pocket_idx % MAPWIDTH) as i32, (pocket_idx / MAPWIDTH) as i32);
+            let nearest = (0..MAPCOUNT)
+                .filter(|idx| distances[*idx].is_some())
+                .min_by_key(|idx| {
+                    let (dx, dy) = ((idx % MAPWIDTH) as i32 - x, (idx / MAPWIDTH) as i32 - y);
+                    dx * dx + dy * dy
+                })
+                .expect("The start is always reachable");
+            let (to_x, to_y) = ((nearest % MAPWIDTH) as i32, (nearest / MAPWIDTH) as i32);
+            // Лестницы уже стоят: туннель проходит сквозь них, не стирая
+            let stairs: Vec<(usize, TileType)> = level.map.tiles.iter().enumerate()
+                .filter(|(_, tile)| matches!(tile, TileType::DownStairs | TileType::UpStairs))
+                .map(|(idx, tile)| (idx, *tile))
+                .collect();
+            level.map.apply_horizontal_tunnel(x, to_x, y);
+            level.map.apply_vertical_tunnel(y, to_y, to_x);
+            for (idx, tile) in stairs {
+                level.map.tiles[idx] = tile;
+            }
+            history.snapshot("pocket connected", &level.map);
+        } else {
+            for idx in pocket.iter() {
+                level.map.tiles[*idx] = TileType::Wall;
+            }
+            for region in level.spawn_regions.iter_mut() {
+                region.retain(|idx| !pocket.contains(idx));
+            }
+            history.snapshot("pocket filled", &level.map);
+        }
+    }
+    level.spawn_regions.retain(|region| !region.is_empty());
+
+    Ok(())
+}
+
+fn choose_builder(depth: i32, raws: &Raws, rng: &mut RandomNumberGenerator) -> String {
+    let rule = raws.map_builders.iter()
+        .find(|rule| depth >= rule.min_depth && rule.max_depth.is_none_or(|max| depth <= max));
+    match rule {
+        // Единственный вариант не тратит бросок: сид даёт ту же карту, что и раньше
+        Some(rule) if rule.builders.len() == 1 => rule.builders[0].id.clone(),
+        Some(rule) => rule.builders.iter()
+            .fold(RandomTable::new(), |table, builder| table.add(&builder.id, builder.weight))
+            .roll(rng)
+            .unwrap_or_else(|| DEFAULT_BUILDER.to_owned()),
+        None => DEFAULT_BUILDER.to_owned()
+    }
+}
+
+fn builder_by_id(id: &str) -> Box<dyn MapBuilder> {
+    match id {
+        "bsp" => Box::new(BspBuilder {}),
+        "cellular_automata" => Box::new(CellularAutomataBuilder {}),
+        "drunkards_walk" => Box::new(DrunkardsWalkBuilder {}),
+        "maze" => Box::new(MazeBuilder {}),
+        _ => Box::new(RoomsAndCorridorsBuilder {})
+    }
+}
+
+/// Random rooms joined by L-shaped corridors, each to the previous one.
+pub struct RoomsAndCorridorsBuilder {}
+
+impl MapBuilder for RoomsAndCorridorsBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+
+        const MAX_ROOMS: i32 = 30;
+        const MIN_SIZE: i32 = 7;
+        const MAX_SIZE: i32 = 10;
+
+        for _ in 0..MAX_ROOMS {
+            let w = rng.range(MIN_SIZE, MAX_SIZE);
+            let h = rng.range(MIN_SIZE, MAX_SIZE);
+            let x = rng.roll_dice(1, MAPWIDTH as i32 - w - 1) - 1;
+            let y = rng.roll_dice(1, MAPHEIGHT as i32 - h - 1) - 1;
+
+            let new_room = Rect::new(x, y, w, h);
+
+            let mut room_ok = true;
+            for other_room in map.rooms.iter() {
+                room_ok &= !other_room.intersect(&new_room);
+            }
+
+            if room_ok {
+                if let Some(prev) = map.rooms.last().map(Rect::center) {
+                    connect_rooms(&mut map, prev, new_room.center(), rng);
+                }
+
+                map.apply_room_to_map(&new_room);
+                map.rooms.push(new_room);
+                history.snapshot("room placed", &map);
+            }
+        }
+
+        rooms_level(map)
+    }
+}
+
+/// Binary space partition: the map is split into smaller and smaller
+/// rectangles, and rooms are placed inside them, so they never overlap.
+pub struct BspBuilder {}
+
+impl MapBuilder for BspBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+        let mut rects = vec![];
+        add_subrects(&mut rects, Rect::new(2, 2, MAPWIDTH as i32 - 5, MAPHEIGHT as i32 - 5));
+
+        for _ in 0..240 {
+            let rect = rects[rng.roll_dice(1, rects.len() as i32) as usize - 1].clone();
+            let candidate = random_room_in(&rect, rng);
+            if bsp_room_fits(&map, &candidate) {
+                map.apply_room_to_map(&candidate);
+                map.rooms.push(candidate);
+                add_subrects(&mut rects, rect);
+                history.snapshot("room placed", &map);
+            }
+        }
+
+        // Соседние по горизонтали комнаты соединяем по порядку
+        map.rooms.sort_by_key(|room| room.x1);
+        for i in 1..map.rooms.len() {
+            let (from, to) = (map.rooms[i-1].center(), map.rooms[i].center());
+            connect_rooms(&mut map, from, to, rng);
+            history.snapshot("corridor dug", &map);
+        }
+
+        rooms_level(map)
+    }
+}
+
+fn add_subrects(rects: &mut Vec<Rect>, rect: Rect) {
+    let width = i32::abs(rect.x1 - rect.x2);
+    let height = i32::abs(rect.y1 - rect.y2);
+    let half_width = i32::max(width / 2, 1);
+    let half_height = i32::max(height / 2, 1);
+
+    rects.push(Rect::new(rect.x1, rect.y1, half_width, half_height));
+    rects.push(Rect::new(rect.x1, rect.y1 + half_height, half_width, half_height));
+    rects.push(Rect::new(rect.x1 + half_width, rect.y1, half_width, half_height));
+    rects.push(Rect::new(rect.x1 + half_width, rect.y1 + half_height, half_width, half_height));
+}
+
+fn random_room_in(rect: &Rect, rng: &mut RandomNumberGenerator) -> Rect {
+    let rect_width = i32::abs(rect.x1 - rect.x2);
+    let rect_height = i32::abs(rect.y1 - rect.y2);
+
+    let w = i32::max(3, rng.roll_dice(1, i32::min(rect_width, 10)) - 1) + 1;
+    let h = i32::max(3, rng.roll_dice(1, i32::min(rect_height, 10)) - 1) + 1;
+    let x = rect.x1 + rng.roll_dice(1, 6) - 1;
+    let y = rect.y1 + rng.roll_dice(1, 6) - 1;
+
+    Rect::new(x, y, w, h)
+}
+
+fn bsp_room_fits(map: &Map, room: &Rect) -> bool {
+    // Рамка в две клетки, чтобы комнаты не слипались
+    let expanded = Rect { x1: room.x1 - 2, x2: room.x2 + 2, y1: room.y1 - 2, y2: room.y2 + 2 };
+    expanded.x1 >= 1 && expanded.y1 >= 1
+        && expanded.x2 < MAPWIDTH as i32 - 1 && expanded.y2 < MAPHEIGHT as i32 - 1
+        && !map.rooms.iter().any(|other| other.intersect(&expanded))
+}
+
+/// Caves: random noise smoothed by the "four or five neighbours" rule.
+pub struct CellularAutomataBuilder {}
+
+impl MapBuilder for CellularAutomataBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+
+        for y in 1..MAPHEIGHT as i32 - 1 {
+            for x in 1..MAPWIDTH as i32 - 1 {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = if rng.roll_dice(1, 100) > 55 { TileType::Floor } else { TileType::Wall };
+            }
+        }
+        history.snapshot("noise", &map);
+
+        for _ in 0..15 {
+            let mut next = map.tiles.clone();
+            for y in 1..MAPHEIGHT as i32 - 1 {
+                for x in 1..MAPWIDTH as i32 - 1 {
+                    let walls = neighbour_walls(&map, x, y);
+                    next[map.xy_idx(x, y)] = if walls > 4 || walls == 0 { TileType::Wall } else { TileType::Floor };
+                }
+            }
+            map.tiles = next;
+            history.snapshot("smoothed", &map);
+        }
+
+        open_level(map, history)
+    }
+}
+
+fn neighbour_walls(map: &Map, x: i32, y: i32) -> usize {
+    let mut walls = 0;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx != 0 || dy != 0) && map.tiles[map.xy_idx(x + dx, y + dy)] == TileType::Wall {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+/// Winding tunnels dug by random walkers until enough of the map is open.
+pub struct DrunkardsWalkBuilder {}
+
+impl MapBuilder for DrunkardsWalkBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+        let center = Point::new(MAPWIDTH / 2, MAPHEIGHT / 2);
+        let center_idx = map.xy_idx(center.x, center.y);
+        map.tiles[center_idx] = TileType::Floor;
+
+        const DESIRED_FLOOR: usize = MAPCOUNT * 4 / 10;
+        const WALKER_LIFETIME: i32 = 400;
+
+        let mut floor = 1;
+        let mut walkers = 0;
+        while floor < DESIRED_FLOOR && walkers < 1000 {
+            // Первые копатели идут из центра, остальные с уже открытых клеток
+            let (mut x, mut y) = if walkers < 5 {
+                (center.x, center.y)
+            } else {
+                let open: Vec<usize> = (0..MAPCOUNT).filter(|i| map.tiles[*i] == TileType::Floor).collect();
+                let idx = open[rng.roll_dice(1, open.len() as i32) as usize - 1];
+                ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32)
+            };
+
+            for _ in 0..WALKER_LIFETIME {
+                let idx = map.xy_idx(x, y);
+                if map.tiles[idx] == TileType::Wall {
+                    map.tiles[idx] = TileType::Floor;
+                    floor += 1;
+                }
+                match rng.roll_dice(1, 4) {
+                    1 if x > 2 => x -= 1,
+                    2 if x < MAPWIDTH as i32 - 3 => x += 1,
+                    3 if y > 2 => y -= 1,
+                    4 if y < MAPHEIGHT as i32 - 3 => y += 1,
+                    _ => {}
+                }
+            }
+            walkers += 1;
+            history.snapshot("walker finished", &map);
+        }
+
+        open_level(map, history)
+    }
+}
+
+/// A perfect maze carved by a recursive backtracker; corridors are one tile wide.
+pub struct MazeBuilder {}
+
+impl MapBuilder for MazeBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+
+        // Клетки лабиринта стоят на нечётных координатах, стены между ними
+        let cells_x = (MAPWIDTH as i32 - 1) / 2;
+        let cells_y = (MAPHEIGHT as i32 - 1) / 2;
+        let cell_pos = |cx: i32, cy: i32| (cx * 2 + 1, cy * 2 + 1);
+
+        let mut visited = vec![false; (cells_x * cells_y) as usize];
+        let mut stack = vec![(0, 0)];
+        let mut carved = 0;
+        visited[0] = true;
+        let (sx, sy) = cell_pos(0, 0);
+        let start_idx = map.xy_idx(sx, sy);
+        map.tiles[start_idx] = TileType::Floor;
+
+        while let Some(&(cx, cy)) = stack.last() {
+            let neighbours: Vec<(i32, i32)> = [(0, -1), (0, 1), (-1, 0), (1, 0)].iter()
+                .map(|(dx, dy)| (cx + dx, cy + dy))
+                .filter(|(nx, ny)| *nx >= 0 && *ny >= 0 && *nx < cells_x && *ny < cells_y)
+                .filter(|(nx, ny)| !visited[(ny * cells_x + nx) as usize])
+                .collect();
+
+            if neighbours.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let (nx, ny) = neighbours[rng.roll_dice(1, neighbours.len() as i32) as usize - 1];
+            visited[(ny * cells_x + nx) as usize] = true;
+            let (x1, y1) = cell_pos(cx, cy);
+            let (x2, y2) = cell_pos(nx, ny);
+            for (x, y) in [((x1 + x2) / 2, (y1 + y2) / 2), (x2, y2)] {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = TileType::Floor;
+            }
+            stack.push((nx, ny));
+            carved += 1;
+            if carved % 40 == 0 {
+                history.snapshot("passages carved", &map);
+            }
+        }
+
+        open_level(map, history)
+    }
+}
+
+/// Tries every prefab allowed at `depth` against its chance and stamps those
+/// that come up where the level has open floor for them and a tile around.
+fn place_vaults(level: &mut BuiltLevel, depth: i32, raws: &Raws, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) {
+    for raw in raws.prefabs.iter().filter(|p| p.chance > 0 && p.allowed_at(depth)) {
+        if rng.roll_dice(1, 100) > raw.chance { continue; }
+        let prefab = Prefab::parse(raw).expect("Raws prefabs are checked on load").oriented(rng);
+        let (max_x, max_y) = vault_max_offset(&prefab);
+        if max_x < 1 || max_y < 1 { continue; }
+
+        for _ in 0..30 {
+            let x = rng.roll_dice(1, max_x);
+            let y = rng.roll_dice(1, max_y);
+            if !vault_fits(level, &prefab, x, y) { continue; }
+
+            level.spawns.extend(prefab.apply(&mut level.map, x, y));
+            // В вольте спавнит только он сам
+            let inside = |idx: &usize| {
+                let (ix, iy) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+                ix >= x && ix < x + prefab.width && iy >= y && iy < y + prefab.height
+            };
+            for region in level.spawn_regions.iter_mut() {
+                region.retain(|idx| !inside(idx));
+            }
+            level.spawn_regions.retain(|region| !region.is_empty());
+            history.snapshot(&format!("vault {} placed", prefab.id), &level.map);
+            break;
+        }
+    }
+}
+
+/// Lays the raws terrain over the spawn regions, so corridors and vaults stay
+/// as they are. A patch that would cut anything off, lava and chasms counting
+/// as impassable, is taken back.
+fn place_terrain(level: &mut BuiltLevel, depth: i32, raws: &Raws, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) {
+    let start_idx = level.map.xy_idx(level.start.x, level.start.y);
+    let exits: Vec<usize> = level.exits.iter().map(|e| level.map.xy_idx(e.x, e.y)).collect();
+    let mut open = vec![false; MAPCOUNT];
+    for idx in level.spawn_regions.iter().flatten() {
+        open[*idx] = level.map.tiles[*idx] == TileType::Floor && *idx != start_idx && !exits.contains(idx);
+    }
+    let safe = |tile: TileType| map::tile_walkable(tile) && !map::tile_hazard(tile);
+
+    for rule in raws.terrain.iter().filter(|r| r.allowed_at(depth)) {
+        for _ in 0..rule.count {
+            if rng.roll_dice(1, 100) > rule.chance { continue; }
+            let Some(patch) = grow_patch(&open, rule.size, rng) else { break; };
+
+            let before = level.map.clone();
+            paint_terrain(&mut level.map, &rule.id, &patch, rng);
+
+            let reached = flood(&before, start_idx, safe);
+            let reachable = flood(&level.map, start_idx, safe);
+            let cut_off = (0..MAPCOUNT).any(|idx| reached[idx].is_some() && safe(level.map.tiles[idx]) && reachable[idx].is_none())
+                || exits.iter().any(|idx| reachable[*idx].is_none());
+            if cut_off {
+                level.map = before;
+                continue;
+            }
+            for idx in patch.iter() {
+                open[*idx] = level.map.tiles[*idx] == TileType::Floor;
+            }
+            history.snapshot(&format!("{} added", rule.id), &level.map);
+        }
+    }
+
+    // В лаве, пропасти и на глубине ничего не появляется
+    for region in level.spawn_regions.iter_mut() {
+        region.retain(|idx| safe(level.map.tiles[*idx]) && level.map.tiles[*idx] != TileType::DeepWater);
+    }
+    level.spawn_regions.retain(|region| !region.is_empty());
+}
+
+/// Up to `size` open tiles around a random one, found by a drunken walk.
+/// `None` once nothing is open.
+fn grow_patch(open: &[bool], size: i32, rng: &mut RandomNumberGenerator) -> Option<Vec<usize>> {
+    let candidates: Vec<usize> = (0..MAPCOUNT).filter(|idx| open[*idx]).collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    let start = candidates[rng.roll_dice(1, candidates.len() as i32) as usize - 1];
+    let (mut x, mut y) = ((start % MAPWIDTH) as i32, (start / MAPWIDTH) as i32);
+
+    let mut patch = vec![];
+    for _ in 0..size * 4 {
+        let idx = y as usize * MAPWIDTH + x as usize;
+        if open[idx] && !patch.contains(&idx) {
+            patch.push(idx);
+            if patch.len() as i32 >= size { break; }
+        }
+        match rng.roll_dice(1, 4) {
+            1 if x > 1 => x -= 1,
+            2 if x < MAPWIDTH as i32 - 2 => x += 1,
+            3 if y > 1 => y -= 1,
+            4 if y < MAPHEIGHT as i32 - 2 => y += 1,
+            _ => {}
+        }
+    }
+    Some(patch)
+}
+
+fn paint_terrain(map: &mut Map, id: &str, patch: &[usize], rng: &mut RandomNumberGenerator) {
+    for idx in patch.iter() {
+        map.tiles[*idx] = match id {
+            "grass" => TileType::Grass,
+            "water" => TileType::ShallowWater,
+            "lava" => TileType::Lava,
+            "chasm" => TileType::Chasm,
+            // Завал лежит кучками, а не сплошняком
+            _ if rng.roll_dice(1, 2) == 1 => TileType::Rubble,
+            _ => TileType::Floor
+        };
+    }
+
+    // Посреди лужи глубоко
+    if id == "water" {
+        let is_water = |tile: TileType| matches!(tile, TileType::ShallowWater | TileType::DeepWater);
+        let deep: Vec<usize> = patch.iter().copied()
+            .filter(|idx| {
+                let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+                (-1..=1).all(|dy| (-1..=1).all(|dx| is_water(map.tiles[map.xy_idx(x + dx, y + dy)])))
+            })
+            .collect();
+        for idx in deep {
+            map.tiles[idx] = TileType::DeepWater;
+        }
+    }
+}
+
+/// Largest top left corner `place_vaults` may pick; the smallest is 1, 1.
+/// Leaves room for the ring of floor around the vault.
+pub fn vault_max_offset(prefab: &Prefab) -> (i32, i32) {
+    (MAPWIDTH as i32 - prefab.width - 2, MAPHEIGHT as i32 - prefab.height - 2)
+}
+
+/// Largest top left corner `FinalLevelBuilder` may pick; the smallest is 1, 1.
+pub fn lab_max_offset(lab: &Prefab) -> (i32, i32) {
+    (MAPWIDTH as i32 - lab.width - 1, MAPHEIGHT as i32 - lab.height - 1)
+}
+
+/// The vault and a ring of one tile around it must all be floor, away from the
+/// start and the exits, so nothing that was reachable gets walled off.
+fn vault_fits(level: &BuiltLevel, prefab: &Prefab, x: i32, y: i32) -> bool {
+    let area = Rect::new(x - 1, y - 1, prefab.width + 1, prefab.height + 1);
+    let covers = |p: &Point| p.x >= area.x1 && p.x <= area.x2 && p.y >= area.y1 && p.y <= area.y2;
+    if covers(&level.start) || level.exits.iter().any(covers) {
+        return false;
+    }
+    (area.y1..=area.y2).all(|ty| (area.x1..=area.x2).all(|tx| level.map.tiles[level.map.xy_idx(tx, ty)] == TileType::Floor))
+}
+
+/// The last level: the alchemist's lab prefab somewhere on an empty map.
+pub struct FinalLevelBuilder {
+    lab: Prefab
+}
+
+impl FinalLevelBuilder {
+    pub fn new(raws: &Raws) -> FinalLevelBuilder {
+        let raw = raws.prefab(FINAL_LAB).expect("Raws prefabs are checked on load");
+        FinalLevelBuilder { lab: Prefab::parse(raw).expect("Raws prefabs are checked on load") }
+    }
+}
+
+impl MapBuilder for FinalLevelBuilder {
+    fn build(&mut self, depth: i32, rng: &mut RandomNumberGenerator, history: &mut MapgenHistory) -> BuiltLevel {
+        let mut map = Map::new(depth);
+        let (max_x, max_y) = lab_max_offset(&self.lab);
+        let x = rng.roll_dice(1, max_x);
+        let y = rng.roll_dice(1, max_y);
+
+        let spawns = self.lab.apply(&mut map, x, y);
+        history.snapshot("lab placed", &map);
+        let lab = Rect::new(x, y, self.lab.width - 1, self.lab.height - 1);
+        let (start_x, start_y) = self.lab.start().map_or_else(|| lab.center(), |(sx, sy)| (x + sx, y + sy));
+        map.rooms.push(lab);
+
+        // Спавним только там, куда можно дойти, не открывая дверь к боссу
+        let start_idx = map.xy_idx(start_x, start_y);
+        let region = distances_from(&map, start_idx).iter().enumerate()
+            .filter(|(idx, distance)| distance.is_some() && map.tiles[*idx] == TileType::Floor)
+            .map(|(idx, _)| idx)
+            .collect();
+
+        BuiltLevel { map, start: Point::new(start_x, start_y), exits: vec![], spawn_regions: vec![region], spawns }
+    }
+}
+
+fn connect_rooms(map: &mut Map, (prev_x, prev_y): (i32, i32), (new_x, new_y): (i32, i32), rng: &mut RandomNumberGenerator) {
+    if rng.range(0, 2) == 1 {
+        map.apply_horizontal_tunnel(prev_x, new_x, prev_y);
+        map.apply_vertical_tunnel(prev_y, new_y, new_x);
+    } else {
+        map.apply_vertical_tunnel(prev_y, new_y, prev_x);
+        map.apply_horizontal_tunnel(prev_x, new_x, new_y);
+    }
+}
+
+/// Room maps start in the first room, go down in the last one and spawn per room.
+fn rooms_level(map: Map) -> BuiltLevel {
+    let (start_x, start_y) = map.rooms.first().expect("No rooms were generated?").center();
+    let (exit_x, exit_y) = map.rooms.last().expect("No rooms were generated?").center();
+    let spawn_regions = map.rooms.iter().map(|room| room_region(&map, room)).collect();
+
+    BuiltLevel { map, start: Point::new(start_x, start_y), exits: vec![Point::new(exit_x, exit_y)], spawn_regions, spawns: vec![] }
+}
+
+fn room_region(map: &Map, room: &Rect) -> Vec<usize> {
+    let mut region = vec![];
+    for y in room.y1+1..=room.y2 {
+        for x in room.x1+1..=room.x2 {
+            region.push(map.xy_idx(x, y));
+        }
+    }
+    region
+}
+
+/// Maps without rooms: start next to the middle, fill in what cannot be reached
+/// from there, go down at the farthest point and spawn per chunk of the map.
+fn open_level(mut map: Map, history: &mut MapgenHistory) -> BuiltLevel {
+    let (center_x, center_y) = (MAPWIDTH as i32 / 2, MAPHEIGHT as i32 / 2);
+    let start_idx = (0..MAPCOUNT)
+        .filter(|idx| map.tiles[*idx] == TileType::Floor)
+        .min_by_key(|idx| {
+            let (dx, dy) = ((idx % MAPWIDTH) as i32 - center_x, (idx / MAPWIDTH) as i32 - center_y);
+            dx * dx + dy * dy
+        })
+        .unwrap_or_else(|| map.xy_idx(center_x, center_y));
+    map.tiles[start_idx] = TileType::Floor;
+
+    let distances = distances_from(&map, start_idx);
+    for (idx, distance) in distances.iter().enumerate() {
+        if distance.is_none() {
+            map.tiles[idx] = TileType::Wall;
+        }
+    }
+    history.snapshot("unreachable parts filled", &map);
+
+    let exit_idx = (0..MAPCOUNT).max_by_key(|idx| (distances[*idx], std::cmp::Reverse(*idx))).unwrap_or(start_idx);
+
+    // Куски 10x10 вместо комнат
+    const CHUNK: usize = 10;
+    let chunks_x = MAPWIDTH.div_ceil(CHUNK);
+    let mut spawn_regions = vec![vec![]; chunks_x * MAPHEIGHT.div_ceil(CHUNK)];
+    for idx in (0..MAPCOUNT).filter(|idx| map.tiles[*idx] == TileType::Floor) {
+        spawn_regions[(idx / MAPWIDTH) / CHUNK * chunks_x + (idx % MAPWIDTH) / CHUNK].push(idx);
+    }
+    spawn_regions.retain(|region| !region.is_empty());
+
+    let point = |idx: usize| Point::new(idx % MAPWIDTH, idx / MAPWIDTH);
+    BuiltLevel { map, start: point(start_idx), exits: vec![point(exit_idx)], spawn_regions, spawns: vec![] }
+}
+
+/// Steps from `start` to every walkable tile, `None` where there is no way.
+pub fn distances_from(map: &Map, start: usize) -> Vec<Option<i32>> {
+    flood(map, start, map::tile_walkable)
+}
+
+fn flood(map: &Map, start: usize, passable: impl Fn(TileType) -> bool) -> Vec<Option<i32>> {
+    let mut distances = vec![None; MAPCOUNT];
+    let mut queue = VecDeque::new();
+    distances[start] = Some(0);
+    queue.push_back(start);
+
+    while let Some(idx) = queue.pop_front() {
+        let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+        let distance = distances[idx].unwrap_or(0);
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= MAPWIDTH as i32 || ny >= MAPHEIGHT as i32 { continue; }
+            let next = map.xy_idx(nx, ny);
+            if distances[next].is_none() && passable(map.tiles[next]) {
+                distances[next] = Some(distance + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    distances
+}
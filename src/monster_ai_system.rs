@@ -1,10 +1,11 @@
-use rltk::{Point, RandomNumberGenerator, RGB};
+use rltk::{Point, RGB};
 use specs::{ReadStorage, System};
 use specs::prelude::*;
 
 use crate::components::{Agitated, Bomber, Boss, BossState, Confusion, Explosion, InstantHarm, Item, LingerType, LingeringEffect, Lobber, Monster, Name, Position, Potion, Renderable, SufferDamage, Teleport, Viewshed, WantsToMelee, WantsToThrowItem};
-use crate::gamelog::GameLog;
+use crate::gameevent::{GameEvent, GameEvents};
 use crate::map::Map;
+use crate::rng::RandomStreams;
 use crate::RunState;
 
 pub struct MonsterAI { }
@@ -95,7 +96,7 @@ pub struct LobberAI {}
 impl<'a> System<'a> for LobberAI {
     type SystemData = ( WriteStorage<'a, Lobber>,
                         Entities<'a>,
-                        WriteExpect<'a, GameLog>,
+                        WriteExpect<'a, GameEvents>,
                         ReadStorage<'a, Name>,
                         ReadExpect<'a, Point>,
                         WriteStorage<'a, WantsToThrowItem>,
@@ -107,14 +108,14 @@ impl<'a> System<'a> for LobberAI {
                         WriteStorage<'a, Teleport>,
                         WriteStorage<'a, LingeringEffect>,
                         WriteStorage<'a, Renderable>,
-                        WriteExpect<'a, RandomNumberGenerator>,
+                        WriteExpect<'a, RandomStreams>,
                         WriteStorage<'a, Monster>,
                         WriteStorage<'a, Agitated>,
                         WriteStorage<'a, Viewshed>
                     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut lobbers, entities, mut gamelog, names, playerpos, mut intentthrow, mut potions, mut items, mut explosion, mut harm, mut confusion, mut tp, mut linger, mut renders, mut rng, mut monsters, mut agitated, mut viewshed)= data;
+        let (mut lobbers, entities, mut events, names, playerpos, mut intentthrow, mut potions, mut items, mut explosion, mut harm, mut confusion, mut tp, mut linger, mut renders, mut rng, mut monsters, mut agitated, mut viewshed)= data;
 
         let mut done_lobbing: Vec<Entity> = vec![];
         for (ent, lob, viewshed) in (&entities, &mut lobbers, &mut viewshed).join() {
@@ -137,18 +138,18 @@ impl<'a> System<'a> for LobberAI {
                 match lob.turns as u32 {
                     3..=u32::MAX | 1 => {},
                     2 => {
-                        let name = names.get(ent).map_or("someone", |n| &n.name);
-                        gamelog.entries.push(format!("{} is aiming with a flask...", name));
+                        let name = names.get(ent).map_or("someone".to_owned(), |n| n.name.clone());
+                        events.emit(GameEvent::Aiming { entity: ent, name });
                         lob.targetpos = Some(*playerpos);
                     },
                     0 => {
                         let potion = entities.create();
 
-                        potions.insert(potion, Potion {}).expect("Unable to insert lobber potion");
+                        potions.insert(potion, Potion::standard()).expect("Unable to insert lobber potion");
                         items.insert(potion, Item {}).expect("Unable to insert lobber potion item");
 
                         let color;
-                        match rng.roll_dice(1, 24) {
+                        match rng.ai.roll_dice(1, 24) {
                             1..=4 => {
                                 explosion.insert(potion, Explosion { maxdmg: 8, radius: 4 }).expect("Unable to insert lobber potion explosion");
                                 color = RGB::named(rltk::ORANGE);
@@ -166,7 +167,7 @@ impl<'a> System<'a> for LobberAI {
                                 color = RGB::named(rltk::VIOLET);
                             }
                             _ => {
-                                let etype = match rng.roll_dice(1, 2) {
+                                let etype = match rng.ai.roll_dice(1, 2) {
                                     1 => {
                                         color = RGB::named(rltk::RED);
                                         LingerType::Fire
@@ -222,14 +223,15 @@ impl<'a> System<'a> for BossAI {
                        WriteStorage<'a, Potion>,
                        WriteStorage<'a, Item>,
                        WriteStorage<'a, Renderable>,
-                       WriteExpect<'a, RandomNumberGenerator>,
-                       WriteExpect<'a, GameLog>,
+                       WriteExpect<'a, RandomStreams>,
+                       WriteExpect<'a, GameEvents>,
                        WriteStorage<'a, LingeringEffect>,
                        WriteStorage<'a, InstantHarm>,
-                       WriteStorage<'a, Explosion>);
+                       WriteStorage<'a, Explosion>,
+                       ReadStorage<'a, Name>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut boss, mut map, entities, player_entity, player_pos, runstate, mut viewsheds, mut positions, mut want_melee, mut confused, mut intentthrow, mut potions, mut items, mut renders, mut rng, mut log, mut linger, mut harm, mut explosion) = data;
+        let (mut boss, mut map, entities, player_entity, player_pos, runstate, mut viewsheds, mut positions, mut want_melee, mut confused, mut intentthrow, mut potions, mut items, mut renders, mut rng, mut events, mut linger, mut harm, mut explosion, names) = data;
 
         if *runstate != RunState::MonsterTurn { return; }
 
@@ -250,6 +252,7 @@ impl<'a> System<'a> for BossAI {
                 if distance < 1.5 {
                     want_melee.insert(entity, WantsToMelee { target: *player_entity }).expect("Unable to insert attack on player");
                 } else {
+                    let previous = boss.state;
                     match boss.state {
                         BossState::ClosingIn(_) => {
                             dbg!("closing in");
@@ -277,18 +280,19 @@ impl<'a> System<'a> for BossAI {
                             if viewshed.visible_tiles.contains(&*player_pos) {
                                 match turns % 4 {
                                     3 => {
-                                        log.entries.push("The Cursed Alchemist is aiming with a flask...".to_owned());
+                                        let name = names.get(entity).map_or("The Cursed Alchemist".to_owned(), |n| n.name.clone());
+                                        events.emit(GameEvent::Aiming { entity, name });
                                         boss.targetpos = Some(*player_pos);
                                     }
                                     2 => { }
                                     1 => { 
                                         let potion = entities.create();
 
-                                        potions.insert(potion, Potion {}).expect("Unable to insert boss potion");
+                                        potions.insert(potion, Potion::standard()).expect("Unable to insert boss potion");
                                         items.insert(potion, Item {}).expect("Unable to insert boss potion item");
 
                                         let color;
-                                        match rng.roll_dice(1, 16) {
+                                        match rng.ai.roll_dice(1, 16) {
                                             1..=4 => {
                                                 explosion.insert(potion, Explosion { maxdmg: 10, radius: 4 }).expect("Unable to insert boss potion explosion");
                                                 color = RGB::named(rltk::ORANGE);
@@ -298,7 +302,7 @@ impl<'a> System<'a> for BossAI {
                                                 color = RGB::named(rltk::DARKRED);
                                             }
                                             _ => {
-                                                let etype = match rng.roll_dice(1, 2) {
+                                                let etype = match rng.ai.roll_dice(1, 2) {
                                                     1 => {
                                                         color = RGB::named(rltk::RED);
                                                         LingerType::Fire
@@ -330,6 +334,9 @@ impl<'a> System<'a> for BossAI {
                             boss.state = state_table(boss.state, distance);
                         },
                     }
+                    if std::mem::discriminant(&previous) != std::mem::discriminant(&boss.state) {
+                        events.emit(GameEvent::BossPhaseChanged { boss: entity, from: previous, to: boss.state });
+                    }
 
                     viewshed.dirty = true;
                 }
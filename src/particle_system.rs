@@ -1,7 +1,8 @@
-use rltk::{Rltk, RGB};
+use rltk::RGB;
 use specs::prelude::*;
 
 use crate::components::{ParticleLifetime, Position, Renderable};
+use crate::rng::RandomStreams;
 
 struct ParticleRequest {
     x: i32,
@@ -33,14 +34,17 @@ impl<'a> System<'a> for ParticleSpawnSystem {
                         WriteStorage<'a, Position>,
                         WriteStorage<'a, Renderable>,
                         WriteStorage<'a, ParticleLifetime>,
-                        WriteExpect<'a, ParticleBuilder>
+                        WriteExpect<'a, ParticleBuilder>,
+                        WriteExpect<'a, RandomStreams>
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut positions, mut renders, mut lifetimes, mut pbuilder) = data;
+        let (entities, mut positions, mut renders, mut lifetimes, mut pbuilder, mut rng) = data;
 
         for particle in pbuilder.requests.iter() {
             let ParticleRequest { x, y, fg, bg, glyph, lifetime } = *particle;
+            // Небольшой разброс, чтобы вспышка гасла неровно
+            let lifetime = lifetime * rng.particles.range(80, 121) as f32 / 100.0;
             let p = entities.create();
             positions.insert(p, Position { x, y }).expect("Unable to insert particle position");
             renders.insert(p, Renderable { glyph, fg, bg, render_order: 0 }).expect("Unable to insert particle render");
@@ -51,21 +55,27 @@ impl<'a> System<'a> for ParticleSpawnSystem {
     }
 }
 
-pub fn cull_dead_particles(ecs: &mut World, ctx: &Rltk) {
-    let mut dead_particles: Vec<Entity> = Vec::new();
-    {
-        let mut lifetimes = ecs.write_storage::<ParticleLifetime>();
-        let entities = ecs.entities();
+/// Hides particles whose time is up. The entities themselves are removed by
+/// `remove_all_particles`, so frame timing never changes entity allocation.
+pub fn cull_dead_particles(ecs: &mut World, frame_time_ms: f32) {
+    let mut lifetimes = ecs.write_storage::<ParticleLifetime>();
+    let mut renders = ecs.write_storage::<Renderable>();
+    let entities = ecs.entities();
 
-        for (ent, particle) in (&entities, &mut lifetimes).join() {
-            particle.lifetime_ms -= ctx.frame_time_ms;
-            if particle.lifetime_ms < 0.0 {
-                dead_particles.push(ent);
-            }
+    for (ent, particle) in (&entities, &mut lifetimes).join() {
+        particle.lifetime_ms -= frame_time_ms;
+        if particle.lifetime_ms < 0.0 {
+            renders.remove(ent);
         }
     }
+}
 
-    for particle in dead_particles {
-        ecs.delete_entity(particle).expect("Particle won't die");
-    }
+pub fn remove_all_particles(ecs: &mut World) {
+    let dead_particles: Vec<Entity> = {
+        let lifetimes = ecs.read_storage::<ParticleLifetime>();
+        let entities = ecs.entities();
+        (&entities, &lifetimes).join().map(|(ent, _)| ent).collect()
+    };
+
+    ecs.delete_entities(&dead_particles).expect("Particle won't die");
 }
\ No newline at end of file
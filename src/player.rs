@@ -1,11 +1,11 @@
-use rltk::{Point, RandomNumberGenerator, Rltk, VirtualKeyCode};
+use rltk::{Point, Rltk, VirtualKeyCode};
 use specs::prelude::*;
-use crate::{components::{CombatStats, Confusion, InBackpack, Item, Viewshed, WantsToMelee, WantsToPickupItem, Weight}, gamelog::GameLog, map::TileType, RunState};
+use crate::{components::{AlchemyStation, CombatStats, Confusion, InBackpack, Item, Temperature, Viewshed, WantsToMelee, WantsToPickupItem, Weight}, gameevent::{GameEvent, GameEvents}, map::TileType, rng::RandomStreams, simulation::{PlayerCommand, STATION_SLOTS}, RunState};
 
 use super::{Position, Player, Map, State};
 use std::cmp::{min, max};
 
-fn try_move_player(dx: i32, dy: i32, ecs: &mut World) {
+pub fn try_move_player(dx: i32, dy: i32, ecs: &mut World) {
     let mut positions = ecs.write_storage::<Position>();
     let mut players = ecs.write_storage::<Player>();
     let mut viewsheds = ecs.write_storage::<Viewshed>();
@@ -28,9 +28,9 @@ fn try_move_player(dx: i32, dy: i32, ecs: &mut World) {
                 confusion.remove(entity);
             }
 
-            let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-            dx = rng.roll_dice(1, 3) - 2;
-            dy = rng.roll_dice(1, 3) - 2;
+            let mut rng = ecs.write_resource::<RandomStreams>();
+            dx = rng.combat.roll_dice(1, 3) - 2;
+            dy = rng.combat.roll_dice(1, 3) - 2;
         }
 
         if pos.x + dx < 1 || pos.x + dx > map.width-1 || pos.y + dy < 1 || pos.y + dy > map.height-1 { return; }
@@ -57,57 +57,52 @@ fn try_move_player(dx: i32, dy: i32, ecs: &mut World) {
 }
 
 pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    match ctx.key {
+    let command = match ctx.key {
         None => { return RunState::AwaitingInput }
         Some(key) => match key {
             VirtualKeyCode::Left | VirtualKeyCode::Numpad4 
-                => try_move_player(-1, 0, &mut gs.ecs),
+                => PlayerCommand::Move { dx: -1, dy: 0 },
             VirtualKeyCode::Right | VirtualKeyCode::Numpad6 
-                => try_move_player(1, 0, &mut gs.ecs),
+                => PlayerCommand::Move { dx: 1, dy: 0 },
             VirtualKeyCode::Up | VirtualKeyCode::Numpad8 
-                => try_move_player(0, -1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: 0, dy: -1 },
             VirtualKeyCode::Down | VirtualKeyCode::Numpad2 
-                => try_move_player(0, 1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: 0, dy: 1 },
             VirtualKeyCode::Numpad7 
-                => try_move_player(-1, -1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: -1, dy: -1 },
             VirtualKeyCode::Numpad9 
-                => try_move_player(1, -1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: 1, dy: -1 },
             VirtualKeyCode::Numpad1 
-                => try_move_player(-1, 1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: -1, dy: 1 },
             VirtualKeyCode::Numpad3 
-                => try_move_player(1, 1, &mut gs.ecs),
+                => PlayerCommand::Move { dx: 1, dy: 1 },
 
             #[cfg(not(target_arch = "wasm32"))]
-            VirtualKeyCode::Period => {
-                if try_next_level(&gs.ecs) {
-                    return RunState::NextLevel;
-                } else {
-                    return RunState::AwaitingInput;
-                }
-            }
+            VirtualKeyCode::Period
+                => PlayerCommand::Descend,
             #[cfg(target_arch = "wasm32")]
             VirtualKeyCode::Period => {
                 if ctx.shift {
-                    if try_next_level(&gs.ecs) {
-                        return RunState::NextLevel;
-                    } else {
-                        return RunState::AwaitingInput;
-                    }
+                    PlayerCommand::Descend
+                } else {
+                    PlayerCommand::Wait
                 }
             }
 
             VirtualKeyCode::Numpad5 | VirtualKeyCode::Space
                 // => return skip_turn(&mut gs.ecs),
-                => return RunState::PlayerTurn,
+                => PlayerCommand::Wait,
             
+            VirtualKeyCode::Comma if ctx.shift
+                => PlayerCommand::Ascend,
             VirtualKeyCode::G | VirtualKeyCode::Comma 
-                => get_item(&mut gs.ecs),
+                => PlayerCommand::PickUp,
             VirtualKeyCode::I
                 => return RunState::ShowInventory,
             VirtualKeyCode::D
                 => return RunState::ShowDropItem,
             VirtualKeyCode::Escape
-                => return RunState::SaveGame,
+                => return RunState::PauseMenu { menu_selection: crate::gui::PauseMenuSelection::Continue },
             #[cfg(not(target_arch = "wasm32"))]
             VirtualKeyCode::Slash
                 => return RunState::ShowHelp,
@@ -123,24 +118,43 @@ pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
             VirtualKeyCode::T 
                 => return RunState::ShowThrowItem,
             VirtualKeyCode::M
-                => return RunState::ShowMix(None),
+                => return RunState::ShowMix { station: station_here(&gs.sim.ecs), slots: [None; STATION_SLOTS], temperature: Temperature::Room },
+            VirtualKeyCode::W
+                => return RunState::ShowDilute,
+            VirtualKeyCode::J
+                => return RunState::ShowJournal,
             #[cfg(debug_assertions)]
             VirtualKeyCode::N
-                => return RunState::NextLevel,
+                => {
+                    // Спуск в обход команд реплей не повторит
+                    gs.sim.recording = None;
+                    return RunState::NextLevel
+                },
             _ => return RunState::AwaitingInput 
         }
-    }
+    };
+
+    gs.sim.apply_command(command)
+}
 
-    RunState::PlayerTurn
+/// The alchemy station the player stands on, if any.
+fn station_here(ecs: &World) -> Option<Entity> {
+    let player_pos = ecs.fetch::<Point>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let stations = ecs.read_storage::<AlchemyStation>();
+    (&entities, &positions, &stations).join()
+        .find(|(_, pos, _)| pos.x == player_pos.x && pos.y == player_pos.y)
+        .map(|(station, _, _)| station)
 }
 
-fn get_item(ecs: &mut World) {
+pub fn get_item(ecs: &mut World) {
     let player_pos = ecs.fetch::<Point>();
     let player_entity = ecs.fetch::<Entity>();
     let entities = ecs.entities();
     let items = ecs.read_storage::<Item>();
     let positions = ecs.read_storage::<Position>();
-    let mut gamelog = ecs.fetch_mut::<GameLog>();
+    let mut events = ecs.fetch_mut::<GameEvents>();
 
     let mut target_item: Option<Entity> = None;
     for (item_entity, _item, pos) in (&entities, &items, &positions).join() {
@@ -150,7 +164,7 @@ fn get_item(ecs: &mut World) {
     }
 
     match target_item {
-        None => gamelog.entries.push("There's nothing to pick up.".to_string()),
+        None => events.emit(GameEvent::NothingToPickUp),
         Some(item) => {
             let backpack = ecs.read_storage::<InBackpack>();
             let weight = ecs.read_storage::<Weight>();
@@ -159,7 +173,7 @@ fn get_item(ecs: &mut World) {
                 total_weight += weight.0;
             }
             if total_weight + weight.get(item).map_or(1, |w| w.0) > 12 {
-                gamelog.entries.push("You are overburdened!".to_owned());
+                events.emit(GameEvent::Overburdened);
             } else {
                 let mut pickup = ecs.write_storage::<WantsToPickupItem>();
                 pickup.insert(item, WantsToPickupItem { collected_by: *player_entity, item }).expect("Unable to insert want to pickup");
@@ -202,8 +216,19 @@ pub fn try_next_level(ecs: &World) -> bool {
     if map.tiles[player_idx] == TileType::DownStairs {
         true
     } else {
-        let mut gamelog = ecs.fetch_mut::<GameLog>();
-        gamelog.entries.push("There's no way down.".to_owned());
+        ecs.fetch_mut::<GameEvents>().emit(GameEvent::NoWayDown);
+        false
+    }
+}
+
+pub fn try_previous_level(ecs: &World) -> bool {
+    let Point {x: player_x, y: player_y} = *ecs.fetch::<Point>();
+    let map = ecs.fetch::<Map>();
+    let player_idx = map.xy_idx(player_x, player_y);
+    if map.tiles[player_idx] == TileType::UpStairs {
+        true
+    } else {
+        ecs.fetch_mut::<GameEvents>().emit(GameEvent::NoWayUp);
         false
     }
 }
@@ -0,0 +1,176 @@
+//! Вольты: куски карты, нарисованные символами в raws.
+//!
+//! Legend:
+//! `#` wall, `.` floor, ` ` (space) leaves the level's own tile,
+//! `|` the sealed door that opens when the boss dies,
+//! `A` the boss spawner, `*` the MacGuffin spawner,
+//! `M` a random monster and `!` a random item from the depth's spawn table,
+//! `@` where the player starts, for prefabs that make up a whole level.
+//! A prefab can add characters of its own in `legend`, each standing for an
+//! item, monster or group id on a floor tile.
+
+use rltk::RandomNumberGenerator;
+
+use crate::map::{Map, TileType};
+use crate::raws::RawPrefab;
+
+pub const LEGEND: &[(char, &str)] = &[
+    ('#', "wall"),
+    ('.', "floor"),
+    (' ', "keep the level's tile"),
+    ('|', "sealed door"),
+    ('A', "boss spawner"),
+    ('*', "MacGuffin spawner"),
+    ('M', "random monster"),
+    ('!', "random item"),
+    ('@', "player start")
+];
+
+/// Something a prefab wants spawned on one of its tiles.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PrefabSpawn {
+    Monster,
+    Item,
+    Named(String)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum PrefabTile {
+    Keep,
+    Tile(TileType),
+    Spawn(PrefabSpawn),
+    Start
+}
+
+#[derive(Clone)]
+pub struct Prefab {
+    pub id: String,
+    pub width: i32,
+    pub height: i32,
+    pub tiles: Vec<PrefabTile>,
+    pub rotate: bool,
+    pub mirror: bool
+}
+
+impl Prefab {
+    /// Reads the rows of a raws prefab; the error says what is wrong and where.
+    pub fn parse(raw: &RawPrefab) -> Result<Prefab, String> {
+        if raw.width == 0 || raw.height == 0 {
+            return Err("\"width\" and \"height\" must be positive".to_owned());
+        }
+        if raw.rows.len() != raw.height {
+            return Err(format!("{} rows, but \"height\" is {}", raw.rows.len(), raw.height));
+        }
+        for c in raw.legend.keys() {
+            if LEGEND.iter().any(|(l, _)| l == c) {
+                return Err(format!("\"legend\" redefines the built-in '{}'", c));
+            }
+        }
+
+        let mut tiles = Vec::with_capacity(raw.width * raw.height);
+        for (y, row) in raw.rows.iter().enumerate() {
+            if row.chars().count() != raw.width {
+                return Err(format!("row {} is {} characters wide, but \"width\" is {}", y + 1, row.chars().count(), raw.width));
+            }
+            for c in row.chars() {
+                tiles.push(match c {
+                    '#' => PrefabTile::Tile(TileType::Wall),
+                    '.' => PrefabTile::Tile(TileType::Floor),
+                    ' ' => PrefabTile::Keep,
+                    '|' => PrefabTile::Tile(TileType::FinalDoor),
+                    'A' => PrefabTile::Tile(TileType::BossSpawner),
+                    '*' => PrefabTile::Tile(TileType::MacGuffinSpawner),
+                    'M' => PrefabTile::Spawn(PrefabSpawn::Monster),
+                    '!' => PrefabTile::Spawn(PrefabSpawn::Item),
+                    '@' => PrefabTile::Start,
+                    c => match raw.legend.get(&c) {
+                        Some(id) => PrefabTile::Spawn(PrefabSpawn::Named(id.clone())),
+                        None => return Err(format!("unknown character '{}' in row {}", c, y + 1))
+                    }
+                });
+            }
+        }
+
+        Ok(Prefab {
+            id: raw.id.clone(),
+            width: raw.width as i32,
+            height: raw.height as i32,
+            tiles,
+            rotate: raw.rotate,
+            mirror: raw.mirror
+        })
+    }
+
+    pub fn tile(&self, x: i32, y: i32) -> &PrefabTile {
+        &self.tiles[(y * self.width + x) as usize]
+    }
+
+    pub fn contains(&self, tile: &PrefabTile) -> bool {
+        self.tiles.contains(tile)
+    }
+
+    /// The `@` tile, if there is one.
+    pub fn start(&self) -> Option<(i32, i32)> {
+        self.tiles.iter()
+            .position(|t| *t == PrefabTile::Start)
+            .map(|idx| (idx as i32 % self.width, idx as i32 / self.width))
+    }
+
+    /// Повёрнут на 90° по часовой стрелке.
+    pub fn rotated(&self) -> Prefab {
+        let mut tiles = Vec::with_capacity(self.tiles.len());
+        for y in 0..self.width {
+            for x in 0..self.height {
+                tiles.push(self.tile(y, self.height - 1 - x).clone());
+            }
+        }
+        Prefab { width: self.height, height: self.width, tiles, ..self.clone() }
+    }
+
+    /// Отражён слева направо.
+    pub fn mirrored(&self) -> Prefab {
+        let mut tiles = Vec::with_capacity(self.tiles.len());
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                tiles.push(self.tile(x, y).clone());
+            }
+        }
+        Prefab { tiles, ..self.clone() }
+    }
+
+    /// A random orientation out of those the prefab allows. Rolls nothing
+    /// if it allows none.
+    pub fn oriented(&self, rng: &mut RandomNumberGenerator) -> Prefab {
+        let mut prefab = self.clone();
+        if self.rotate {
+            for _ in 0..rng.roll_dice(1, 4) - 1 {
+                prefab = prefab.rotated();
+            }
+        }
+        if self.mirror && rng.roll_dice(1, 2) == 2 {
+            prefab = prefab.mirrored();
+        }
+        prefab
+    }
+
+    /// Stamps the prefab with its top left corner at `x`, `y`. Returns what
+    /// it wants spawned, by map index.
+    pub fn apply(&self, map: &mut Map, x: i32, y: i32) -> Vec<(usize, PrefabSpawn)> {
+        let mut spawns = vec![];
+        for py in 0..self.height {
+            for px in 0..self.width {
+                let idx = map.xy_idx(x + px, y + py);
+                match self.tile(px, py) {
+                    PrefabTile::Keep => {}
+                    PrefabTile::Tile(tile) => map.tiles[idx] = *tile,
+                    PrefabTile::Start => map.tiles[idx] = TileType::Floor,
+                    PrefabTile::Spawn(spawn) => {
+                        map.tiles[idx] = TileType::Floor;
+                        spawns.push((idx, spawn.clone()));
+                    }
+                }
+            }
+        }
+        spawns
+    }
+}
@@ -0,0 +1,316 @@
+//! `--prefab <command>`: tools for the prefabs in the raws.
+//!
+//! `check [id]` validates all prefabs (or one), `preview <id>` prints one
+//! placed on an empty map, `from-bin <file> [width]` turns an old `room.bin`
+//! style byte grid into a raws prefab and `to-bin <id> <file>` writes one back.
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use rltk::{to_cp437, Point};
+
+use crate::map::{self, Map, TileType, MAPHEIGHT, MAPWIDTH};
+use crate::map_builders::{self, MapSnapshot};
+use crate::prefab::{Prefab, PrefabSpawn, PrefabTile, LEGEND};
+use crate::raws::{Raws, FINAL_LAB};
+
+const USAGE: &str = "Usage: --prefab check [id] | preview <id> | from-bin <file> [width] | to-bin <id> <file>";
+
+// Байты старого room.bin, по порядку
+const BIN_TILES: [TileType; 5] = [TileType::Floor, TileType::Wall, TileType::BossSpawner, TileType::FinalDoor, TileType::MacGuffinSpawner];
+const BIN_WIDTH: usize = 32;
+
+pub fn run(args: &[String], raws: &Raws) -> i32 {
+    let arg = |i: usize| args.get(i).map(String::as_str);
+    match (arg(0), arg(1), arg(2)) {
+        (Some("check"), id, _) => check(raws, id),
+        (Some("preview"), Some(id), _) => preview(raws, id),
+        (Some("from-bin"), Some(path), width) => match width.map(str::parse) {
+            None => from_bin(path, BIN_WIDTH),
+            Some(Ok(width)) if width > 0 => from_bin(path, width),
+            Some(_) => {
+                eprintln!("The width must be a positive number");
+                2
+            }
+        },
+        (Some("to-bin"), Some(id), Some(path)) => to_bin(raws, id, path),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+fn parsed(raws: &Raws, id: &str) -> Option<Prefab> {
+    match raws.prefab(id) {
+        Some(raw) => Some(Prefab::parse(raw).expect("Raws prefabs are checked on load")),
+        None => {
+            eprintln!("No prefab \"{}\" in the raws (known: {})", id,
+                raws.prefabs.iter().map(|p| p.id.as_str()).collect::<Vec<_>>().join(", "));
+            None
+        }
+    }
+}
+
+fn check(raws: &Raws, only: Option<&str>) -> i32 {
+    let prefabs: Vec<Prefab> = match only {
+        Some(id) => match parsed(raws, id) {
+            Some(prefab) => vec![prefab],
+            None => return 2
+        },
+        None => raws.prefabs.iter().map(|raw| Prefab::parse(raw).expect("Raws prefabs are checked on load")).collect()
+    };
+
+    let mut failed = false;
+    for prefab in prefabs.iter() {
+        let problems = if prefab.id == FINAL_LAB { lab_problems(prefab) } else { vault_problems(prefab) };
+        if problems.is_empty() {
+            println!("{}: ok", prefab.id);
+        }
+        for problem in problems.iter() {
+            println!("{}: {}", prefab.id, problem);
+        }
+        failed |= !problems.is_empty();
+    }
+
+    if failed { 1 } else { 0 }
+}
+
+/// The lab must have its spawners and start, the door must be reachable from
+/// the start and the boss chamber from the door, and it must fit wherever
+/// `FinalLevelBuilder` may put it.
+fn lab_problems(lab: &Prefab) -> Vec<String> {
+    let mut problems = vec![];
+
+    let boss = PrefabTile::Tile(TileType::BossSpawner);
+    let macguffin = PrefabTile::Tile(TileType::MacGuffinSpawner);
+    let door = PrefabTile::Tile(TileType::FinalDoor);
+    for (tile, what) in [(&boss, "boss spawner 'A'"), (&macguffin, "MacGuffin spawner '*'")] {
+        if !lab.contains(tile) {
+            problems.push(format!("no {}", what));
+        }
+    }
+
+    let Some(start) = lab.start() else {
+        problems.push("no player start '@'".to_owned());
+        return problems;
+    };
+
+    let closed = reachable(lab, &[start], false);
+    for (x, y) in tiles_of(lab, &door) {
+        if !neighbours(lab, x, y).any(|(nx, ny)| closed[(ny * lab.width + nx) as usize]) {
+            problems.push(format!("the door at {},{} cannot be reached from '@'", x, y));
+        }
+    }
+    let open = reachable(lab, &[start], true);
+    for (tile, what) in [(&boss, "boss spawner"), (&macguffin, "MacGuffin spawner")] {
+        for (x, y) in tiles_of(lab, tile) {
+            if !open[(y * lab.width + x) as usize] {
+                problems.push(format!("the {} at {},{} cannot be reached even with the door open", what, x, y));
+            }
+        }
+    }
+
+    let (max_x, max_y) = map_builders::lab_max_offset(lab);
+    if max_x < 1 || max_y < 1 {
+        problems.push(format!("{}x{} does not fit into a {}x{} map", lab.width, lab.height, MAPWIDTH, MAPHEIGHT));
+        return problems;
+    }
+    // Край карты должен остаться стеной при любом сдвиге; хватит проверить крайние
+    for (x, y) in [(1, 1), (max_x, max_y)] {
+        let mut map = Map::new(1);
+        lab.apply(&mut map, x, y);
+        let open_border = (0..MAPWIDTH as i32).flat_map(|bx| [(bx, 0), (bx, MAPHEIGHT as i32 - 1)])
+            .chain((0..MAPHEIGHT as i32).flat_map(|by| [(0, by), (MAPWIDTH as i32 - 1, by)]))
+            .any(|(bx, by)| map.tiles[map.xy_idx(bx, by)] != TileType::Wall);
+        if open_border {
+            problems.push(format!("placed at {},{} it opens the edge of the map", x, y));
+        }
+    }
+
+    problems
+}
+
+/// Every walkable tile of a vault must be reachable from around it, in every
+/// orientation the vault may get, and every orientation must fit into a level.
+fn vault_problems(vault: &Prefab) -> Vec<String> {
+    let mut problems = vec![];
+
+    let edge: Vec<(i32, i32)> = (0..vault.height)
+        .flat_map(|y| (0..vault.width).map(move |x| (x, y)))
+        .filter(|(x, y)| *x == 0 || *y == 0 || *x == vault.width - 1 || *y == vault.height - 1 || *vault.tile(*x, *y) == PrefabTile::Keep)
+        .filter(|(x, y)| walkable(vault.tile(*x, *y), false))
+        .collect();
+    if edge.is_empty() {
+        problems.push("has no way in".to_owned());
+    }
+    let reached = reachable(vault, &edge, false);
+    for y in 0..vault.height {
+        for x in 0..vault.width {
+            if walkable(vault.tile(x, y), false) && !reached[(y * vault.width + x) as usize] {
+                problems.push(format!("the tile at {},{} cannot be reached from outside", x, y));
+            }
+        }
+    }
+
+    let mut sizes = vec![(vault.width, vault.height)];
+    if vault.rotate {
+        sizes.push((vault.height, vault.width));
+    }
+    for (width, height) in sizes {
+        let (max_x, max_y) = map_builders::vault_max_offset(&Prefab { width, height, ..vault.clone() });
+        if max_x < 1 || max_y < 1 {
+            problems.push(format!("{}x{} with the floor around it does not fit into a {}x{} map", width, height, MAPWIDTH, MAPHEIGHT));
+        }
+    }
+
+    problems
+}
+
+fn walkable(tile: &PrefabTile, door_open: bool) -> bool {
+    match tile {
+        PrefabTile::Tile(TileType::FinalDoor) => door_open,
+        PrefabTile::Tile(tile) => map::tile_walkable(*tile),
+        PrefabTile::Keep | PrefabTile::Spawn(_) | PrefabTile::Start => true
+    }
+}
+
+fn tiles_of(prefab: &Prefab, tile: &PrefabTile) -> Vec<(i32, i32)> {
+    (0..prefab.height)
+        .flat_map(|y| (0..prefab.width).map(move |x| (x, y)))
+        .filter(|(x, y)| prefab.tile(*x, *y) == tile)
+        .collect()
+}
+
+fn neighbours(prefab: &Prefab, x: i32, y: i32) -> impl Iterator<Item = (i32, i32)> + '_ {
+    [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter()
+        .map(move |(dx, dy)| (x + dx, y + dy))
+        .filter(move |(nx, ny)| *nx >= 0 && *ny >= 0 && *nx < prefab.width && *ny < prefab.height)
+}
+
+/// Tiles of the prefab that can be walked to from `from`, the same way
+/// `map_builders::distances_from` walks the map.
+fn reachable(prefab: &Prefab, from: &[(i32, i32)], door_open: bool) -> Vec<bool> {
+    let mut reached = vec![false; prefab.tiles.len()];
+    let mut queue: VecDeque<(i32, i32)> = from.iter().copied().collect();
+    for (x, y) in from.iter() {
+        reached[(y * prefab.width + x) as usize] = true;
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        for (nx, ny) in neighbours(prefab, x, y) {
+            let idx = (ny * prefab.width + nx) as usize;
+            if !reached[idx] && walkable(prefab.tile(nx, ny), door_open) {
+                reached[idx] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    reached
+}
+
+/// The prefab stamped in the middle of a map of walls, as the mapgen export shows maps.
+fn preview(raws: &Raws, id: &str) -> i32 {
+    let Some(prefab) = parsed(raws, id) else { return 2; };
+    let (max_x, max_y) = if prefab.id == FINAL_LAB { map_builders::lab_max_offset(&prefab) } else { map_builders::vault_max_offset(&prefab) };
+    let (x, y) = (i32::max(1, (1 + max_x) / 2), i32::max(1, (1 + max_y) / 2));
+    if x + prefab.width > MAPWIDTH as i32 || y + prefab.height > MAPHEIGHT as i32 {
+        eprintln!("{}x{} does not fit into a {}x{} map", prefab.width, prefab.height, MAPWIDTH, MAPHEIGHT);
+        return 1;
+    }
+
+    let mut map = Map::new(1);
+    let spawns = prefab.apply(&mut map, x, y)
+        .into_iter()
+        .map(|(idx, spawn)| (Point::new(idx % MAPWIDTH, idx / MAPWIDTH), spawn_glyph(raws, &spawn)))
+        .collect();
+    let snapshot = MapSnapshot { label: String::new(), tiles: map.tiles, spawns };
+
+    println!("{} ({}x{}) at {},{}:", prefab.id, prefab.width, prefab.height, x, y);
+    print!("{}", snapshot.to_ascii());
+    println!("Legend: {}", LEGEND.iter().map(|(c, what)| format!("'{}' {}", c, what)).collect::<Vec<_>>().join(", "));
+    0
+}
+
+fn spawn_glyph(raws: &Raws, spawn: &PrefabSpawn) -> rltk::FontCharType {
+    match spawn {
+        PrefabSpawn::Monster => to_cp437('M'),
+        PrefabSpawn::Item => to_cp437('!'),
+        PrefabSpawn::Named(id) => raws.item(id).map(|i| i.glyph)
+            .or_else(|| raws.monster(id).map(|m| m.glyph))
+            .map_or(to_cp437('?'), to_cp437)
+    }
+}
+
+fn from_bin(path: &str, width: usize) -> i32 {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            return 2;
+        }
+    };
+    if bytes.is_empty() || bytes.len() % width != 0 {
+        eprintln!("{} has {} bytes, which is not a whole number of rows {} wide", path, bytes.len(), width);
+        return 1;
+    }
+
+    let mut rows = vec![];
+    for (y, chunk) in bytes.chunks(width).enumerate() {
+        let mut row = String::new();
+        for (x, byte) in chunk.iter().enumerate() {
+            match BIN_TILES.get(*byte as usize) {
+                Some(tile) => row.push(map_builders::tile_char(*tile)),
+                None => {
+                    eprintln!("Unknown byte {} at {},{}", byte, x, y);
+                    return 1;
+                }
+            }
+        }
+        rows.push(row);
+    }
+
+    // Поля в том же порядке и с теми же отступами, что в raws, чтобы вставить как есть
+    let id = Path::new(path).file_stem().map_or("prefab".into(), |s| s.to_string_lossy());
+    let quote = |s: &str| serde_json::to_string(s).expect("Unable to quote a string");
+    let rows: Vec<String> = rows.iter().map(|row| format!("                {}", quote(row))).collect();
+    println!("        {{");
+    println!("            \"id\": {},", quote(&id));
+    println!("            \"width\": {},", width);
+    println!("            \"height\": {},", rows.len());
+    println!("            \"rows\": [\n{}\n            ]", rows.join(",\n"));
+    println!("        }}");
+    0
+}
+
+fn to_bin(raws: &Raws, id: &str, path: &str) -> i32 {
+    let Some(prefab) = parsed(raws, id) else { return 2; };
+
+    let mut bytes = Vec::with_capacity(prefab.tiles.len());
+    for (idx, tile) in prefab.tiles.iter().enumerate() {
+        let tile = match tile {
+            // Старт в старом формате не хранился: он всегда был в одном месте
+            PrefabTile::Start => TileType::Floor,
+            PrefabTile::Tile(tile) => *tile,
+            PrefabTile::Keep | PrefabTile::Spawn(_) => {
+                eprintln!("The tile at {},{} has no byte in the .bin format", idx as i32 % prefab.width, idx as i32 / prefab.width);
+                return 1;
+            }
+        };
+        match BIN_TILES.iter().position(|t| *t == tile) {
+            Some(byte) => bytes.push(byte as u8),
+            None => {
+                eprintln!("The tile at {},{} has no byte in the .bin format", idx as i32 % prefab.width, idx as i32 / prefab.width);
+                return 1;
+            }
+        }
+    }
+
+    if let Err(e) = std::fs::write(path, &bytes) {
+        eprintln!("Unable to write {}: {}", path, e);
+        return 2;
+    }
+    println!("Wrote {} ({}x{}) to {}.", prefab.id, prefab.width, prefab.height, path);
+    0
+}
@@ -1,30 +1,15 @@
 use rltk::RandomNumberGenerator;
 
-#[derive(Clone, Copy)]
-pub enum SpawnEntry {
-    None,
-    Goblin,
-    Ork,
-    Bomber,
-    Lobber,
-    HealingPotion,
-    // FireballScroll,
-    ConfusionPotion,
-    TeleportPotion,
-    // MissileScroll,
-    LingeringPotion,
-    HarmingPotion,
-    ExplosionPotion
-}
+use crate::raws::{RawGroup, RawSpawnTable};
 
 pub struct RandomEntry {
-    name: SpawnEntry,
+    name: String,
     weight: i32,
 }
 
 impl RandomEntry {
-    pub fn new(name: SpawnEntry, weight: i32) -> Self {
-        RandomEntry { name, weight }
+    pub fn new<S: ToString>(name: S, weight: i32) -> Self {
+        RandomEntry { name: name.to_string(), weight }
     }
 }
 
@@ -39,26 +24,41 @@ impl RandomTable {
         RandomTable { entries: vec![], total_weight: 0 }
     }
 
-    pub fn add(mut self, name: SpawnEntry, weight: i32) -> Self {
-        self.total_weight += weight;
-        self.entries.push(RandomEntry::new(name, weight));
+    pub fn add<S: ToString>(mut self, name: S, weight: i32) -> Self {
+        if weight > 0 {
+            self.total_weight += weight;
+            self.entries.push(RandomEntry::new(name, weight));
+        }
         self
     }
 
-    pub fn roll(&self, rng: &mut RandomNumberGenerator) -> SpawnEntry {
-        if self.total_weight == 0 { return SpawnEntry::None }
+    /// Spawn table from the raws with weights for the given depth.
+    pub fn from_raws(table: &RawSpawnTable, map_depth: i32) -> Self {
+        Self::from_raws_where(table, map_depth, |_| true)
+    }
+
+    /// Same, but only with the entries whose id passes `keep`.
+    pub fn from_raws_where(table: &RawSpawnTable, map_depth: i32, keep: impl Fn(&str) -> bool) -> Self {
+        table.entries.iter().filter(|entry| keep(&entry.id)).fold(RandomTable::new(), |rt, entry| {
+            rt.add(&entry.id, entry.weight + map_depth * entry.per_depth / entry.depth_divisor)
+        })
+    }
+
+    pub fn from_group(group: &RawGroup) -> Self {
+        group.members.iter().fold(RandomTable::new(), |rt, member| rt.add(&member.id, member.weight))
+    }
+
+    pub fn roll(&self, rng: &mut RandomNumberGenerator) -> Option<String> {
+        if self.total_weight == 0 { return None }
         let mut roll = rng.roll_dice(1, self.total_weight)-1;
-        let mut index: usize = 0;
 
-        while roll > 0 {
-            if roll < self.entries[index].weight {
-                return self.entries[index].name;
+        for entry in self.entries.iter() {
+            if roll < entry.weight {
+                return Some(entry.name.clone());
             }
-
-            roll -= self.entries[index].weight;
-            index += 1;
+            roll -= entry.weight;
         }
 
-        SpawnEntry::None
+        None
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use rltk::RGB;
+use serde::Deserialize;
+
+use crate::components::{Confusion, Explosion, Temperature, InstantHarm, Invulnerability, LingerType, LingeringEffect, ProvidesHealing, Strength, Teleport, MAX_POTENCY};
+use crate::effects::PotionEffect;
+use crate::identify_system::PotionKind;
+use crate::map::{GasType, TileType};
+use crate::prefab::{Prefab, PrefabTile};
+
+/// Файл с описаниями предметов, монстров и таблиц спавна. Если его нет рядом
+/// с игрой, используется копия, вшитая при сборке.
+pub const RAWS_PATH: &str = "raws/spawns.json";
+const EMBEDDED_RAWS: &str = include_str!("../raws/spawns.json");
+
+/// Spawn tables and entities the game needs by id.
+pub const ROOM_TABLE: &str = "rooms";
+pub const FINAL_LEVEL_TABLE: &str = "final_level";
+pub const BOSS: &str = "the_cursed_alchemist";
+pub const MACGUFFIN: &str = "philosophers_stone";
+pub const FINAL_LAB: &str = "alchemist_lab";
+/// Slots the station menu has room for
+pub const MAX_STATION_SLOTS: i32 = 3;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Raws {
+    pub items: Vec<RawItem>,
+    pub monsters: Vec<RawMonster>,
+    #[serde(default)]
+    pub groups: Vec<RawGroup>,
+    pub spawn_tables: Vec<RawSpawnTable>,
+    /// Which map builder to use on which depth; the first matching rule wins
+    #[serde(default)]
+    pub map_builders: Vec<RawBuilderRule>,
+    #[serde(default)]
+    pub prefabs: Vec<RawPrefab>,
+    /// Patches of water, grass, lava and such laid over generated levels
+    #[serde(default)]
+    pub terrain: Vec<RawTerrain>,
+    /// Special mixes; the first recipe that fits a mix wins
+    #[serde(default)]
+    pub recipes: Vec<RawRecipe>,
+    /// Furniture to brew at, spawned like items and monsters
+    #[serde(default)]
+    pub stations: Vec<RawStation>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawItem {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    pub fg: String,
+    pub weight: Option<i32>,
+    #[serde(default)]
+    pub potion: bool,
+    #[serde(default)]
+    pub consumable: bool,
+    #[serde(default)]
+    pub macguffin: bool,
+    /// Tells what the potions in the backpack are when used
+    #[serde(default)]
+    pub identifies: bool,
+    #[serde(default)]
+    pub effects: Vec<RawEffect>
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RawEffect {
+    Heal { amount: i32 },
+    Teleport { safe: bool },
+    Confusion { turns: i32 },
+    Harm { damage: i32 },
+    Fire { duration: i32, damage: i32 },
+    Poison { duration: i32, damage: i32 },
+    Explosion { damage: i32, radius: i32 },
+    Invulnerability { turns: i32 },
+    Strength { turns: i32 }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RawAi {
+    #[default]
+    Melee,
+    Lobber,
+    Boss
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawMonster {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    /// Может отсутствовать у монстров с `payload`: тогда берётся цвет начинки
+    pub fg: Option<String>,
+    pub hp: i32,
+    pub defence: i32,
+    pub power: i32,
+    #[serde(default = "default_vision")]
+    pub vision: i32,
+    #[serde(default)]
+    pub ai: RawAi,
+    /// Item (or group of items) the monster carries and throws at the player when it explodes
+    pub payload: Option<String>,
+    /// Gas the monster leaves behind every turn
+    pub exhales: Option<RawExhale>
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawExhale {
+    pub gas: GasType,
+    pub density: i32
+}
+
+/// Several ids under one name; spawning the group picks one member by weight.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawGroup {
+    pub id: String,
+    pub members: Vec<RawGroupMember>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawGroupMember {
+    pub id: String,
+    #[serde(default = "default_member_weight")]
+    pub weight: i32
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawSpawnTable {
+    pub id: String,
+    pub entries: Vec<RawSpawnEntry>
+}
+
+/// Weight on a given depth is `weight + depth * per_depth / depth_divisor`, never below zero.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawSpawnEntry {
+    pub id: String,
+    pub weight: i32,
+    #[serde(default)]
+    pub per_depth: i32,
+    #[serde(default = "default_depth_divisor")]
+    pub depth_divisor: i32
+}
+
+/// Depths from `min_depth` to `max_depth` (inclusive, open-ended if missing)
+/// use one of `builders`, picked by weight.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawBuilderRule {
+    #[serde(default = "default_min_depth")]
+    pub min_depth: i32,
+    pub max_depth: Option<i32>,
+    pub builders: Vec<RawGroupMember>
+}
+
+/// A vault drawn in ASCII; the legend is in `prefab.rs`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawPrefab {
+    pub id: String,
+    pub width: usize,
+    pub height: usize,
+    #[serde(default = "default_min_depth")]
+    pub min_depth: i32,
+    pub max_depth: Option<i32>,
+    /// Percent chance to be stamped into a level of a fitting depth.
+    /// Zero for prefabs the game places itself, like the lab.
+    #[serde(default)]
+    pub chance: i32,
+    #[serde(default)]
+    pub rotate: bool,
+    #[serde(default)]
+    pub mirror: bool,
+    /// Extra characters, each standing for an item, monster or group id
+    #[serde(default)]
+    pub legend: BTreeMap<char, String>,
+    pub rows: Vec<String>
+}
+
+impl RawPrefab {
+    pub fn allowed_at(&self, depth: i32) -> bool {
+        depth >= self.min_depth && self.max_depth.is_none_or(|max| depth <= max)
+    }
+}
+
+/// Up to `count` patches of about `size` tiles, each with a `chance` percent,
+/// on depths from `min_depth` to `max_depth`. The last level gets none.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawTerrain {
+    pub id: String,
+    #[serde(default = "default_min_depth")]
+    pub min_depth: i32,
+    pub max_depth: Option<i32>,
+    pub chance: i32,
+    #[serde(default = "default_terrain_count")]
+    pub count: i32,
+    pub size: i32
+}
+
+impl RawTerrain {
+    pub fn allowed_at(&self, depth: i32) -> bool {
+        depth >= self.min_depth && self.max_depth.is_none_or(|max| depth <= max)
+    }
+}
+
+/// See `AlchemyStation`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawStation {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    pub fg: String,
+    pub slots: i32,
+    #[serde(default)]
+    pub heat: bool,
+    #[serde(default)]
+    pub cool: bool,
+    pub turns: i32
+}
+
+/// A special mix: flasks with exactly the ingredients' kinds between them
+/// brew `outputs` instead of mixing evenly. A recipe either lists its
+/// ingredients or has them rolled from the alchemy seed, once per run.
+/// One with a `temperature` only works at a station that can heat or cool.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawRecipe {
+    pub id: String,
+    #[serde(default)]
+    pub ingredients: Vec<RawIngredient>,
+    pub random: Option<RawRandomIngredients>,
+    pub temperature: Option<Temperature>,
+    pub outputs: Vec<RawEffect>
+}
+
+/// At least `count` flasks holding the kind, none of them weaker than `min_potency`.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawIngredient {
+    pub kind: PotionKind,
+    #[serde(default = "default_ingredient_count")]
+    pub count: i32,
+    #[serde(default)]
+    pub min_potency: i32
+}
+
+/// `count` different kinds picked from `from`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RawRandomIngredients {
+    pub from: Vec<PotionKind>,
+    pub count: i32
+}
+
+fn default_vision() -> i32 { 8 }
+fn default_min_depth() -> i32 { 1 }
+fn default_member_weight() -> i32 { 1 }
+fn default_depth_divisor() -> i32 { 1 }
+fn default_terrain_count() -> i32 { 1 }
+fn default_ingredient_count() -> i32 { 1 }
+
+#[derive(Debug)]
+pub enum RawsError {
+    Io { source: String, error: std::io::Error },
+    Parse { source: String, error: serde_json::Error },
+    Invalid { source: String, message: String }
+}
+
+impl fmt::Display for RawsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawsError::Io { source, error } => write!(f, "Unable to read {}: {}", source, error),
+            RawsError::Parse { source, error } => write!(f, "Malformed raws in {}: {}", source, error),
+            RawsError::Invalid { source, message } => write!(f, "Invalid raws in {}: {}", source, message)
+        }
+    }
+}
+
+/// Loads `raws/spawns.json` from the working directory, falling back to the built-in copy.
+pub fn load() -> Result<Raws, RawsError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::path::Path::new(RAWS_PATH).exists() {
+        let data = std::fs::read_to_string(RAWS_PATH)
+            .map_err(|error| RawsError::Io { source: RAWS_PATH.to_owned(), error })?;
+        return parse(RAWS_PATH, &data);
+    }
+
+    parse("built-in raws", EMBEDDED_RAWS)
+}
+
+pub fn parse(source: &str, data: &str) -> Result<Raws, RawsError> {
+    let raws: Raws = serde_json::from_str(data)
+        .map_err(|error| RawsError::Parse { source: source.to_owned(), error })?;
+    raws.validate()
+        .map_err(|message| RawsError::Invalid { source: source.to_owned(), message })?;
+    Ok(raws)
+}
+
+impl Raws {
+    pub fn item(&self, id: &str) -> Option<&RawItem> {
+        self.items.iter().find(|i| i.id == id)
+    }
+
+    pub fn monster(&self, id: &str) -> Option<&RawMonster> {
+        self.monsters.iter().find(|m| m.id == id)
+    }
+
+    pub fn group(&self, id: &str) -> Option<&RawGroup> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+
+    pub fn spawn_table(&self, id: &str) -> Option<&RawSpawnTable> {
+        self.spawn_tables.iter().find(|t| t.id == id)
+    }
+
+    pub fn station(&self, id: &str) -> Option<&RawStation> {
+        self.stations.iter().find(|s| s.id == id)
+    }
+
+    pub fn prefab(&self, id: &str) -> Option<&RawPrefab> {
+        self.prefabs.iter().find(|p| p.id == id)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        let all_ids = self.items.iter().map(|i| &i.id)
+                          .chain(self.monsters.iter().map(|m| &m.id))
+                          .chain(self.groups.iter().map(|g| &g.id))
+                          .chain(self.stations.iter().map(|s| &s.id));
+        for id in all_ids {
+            if !ids.insert(id.as_str()) {
+                return Err(format!("id \"{}\" is defined more than once", id));
+            }
+        }
+
+        for item in self.items.iter() {
+            parse_color(&item.fg).map_err(|e| format!("item \"{}\": {}", item.id, e))?;
+            if !item.effects.is_empty() && !item.potion {
+                return Err(format!("item \"{}\" has effects but is not a potion", item.id));
+            }
+        }
+
+        for monster in self.monsters.iter() {
+            if monster.hp <= 0 {
+                return Err(format!("monster \"{}\" must have positive hp", monster.id));
+            }
+            match (&monster.fg, &monster.payload) {
+                (Some(fg), _) => { parse_color(fg).map_err(|e| format!("monster \"{}\": {}", monster.id, e))?; }
+                (None, Some(_)) => {}
+                (None, None) => return Err(format!("monster \"{}\" needs \"fg\" (or a payload to take its colour from)", monster.id))
+            }
+            if let Some(payload) = &monster.payload {
+                if !self.only_items(payload) {
+                    return Err(format!("payload \"{}\" of monster \"{}\" is not an item or a group of items", payload, monster.id));
+                }
+            }
+            if monster.exhales.is_some_and(|exhale| exhale.density <= 0) {
+                return Err(format!("monster \"{}\" must exhale gas of positive density", monster.id));
+            }
+        }
+
+        for station in self.stations.iter() {
+            parse_color(&station.fg).map_err(|e| format!("station \"{}\": {}", station.id, e))?;
+            if !(2..=MAX_STATION_SLOTS).contains(&station.slots) {
+                return Err(format!("station \"{}\" must have from 2 to {} slots", station.id, MAX_STATION_SLOTS));
+            }
+            if station.turns <= 0 {
+                return Err(format!("station \"{}\" must take a positive number of turns", station.id));
+            }
+        }
+
+        for group in self.groups.iter() {
+            if group.members.is_empty() {
+                return Err(format!("group \"{}\" is empty", group.id));
+            }
+            for member in group.members.iter() {
+                if !ids.contains(member.id.as_str()) {
+                    return Err(format!("group \"{}\" refers to unknown id \"{}\"", group.id, member.id));
+                }
+                if member.weight <= 0 {
+                    return Err(format!("member \"{}\" of group \"{}\" must have positive weight", member.id, group.id));
+                }
+            }
+        }
+        // Рекурсивные группы проверяем отдельно, иначе спавн уйдёт в бесконечный цикл
+        for group in self.groups.iter() {
+            self.check_group_cycle(&group.id, &mut vec![])?;
+        }
+
+        for table in self.spawn_tables.iter() {
+            for entry in table.entries.iter() {
+                if !ids.contains(entry.id.as_str()) {
+                    return Err(format!("spawn table \"{}\" refers to unknown id \"{}\"", table.id, entry.id));
+                }
+                if entry.depth_divisor == 0 {
+                    return Err(format!("spawn table \"{}\": \"depth_divisor\" of \"{}\" cannot be zero", table.id, entry.id));
+                }
+            }
+        }
+
+        for rule in self.map_builders.iter() {
+            if rule.builders.is_empty() {
+                return Err(format!("map builder rule for depth {} has no builders", rule.min_depth));
+            }
+            for builder in rule.builders.iter() {
+                if !crate::map_builders::BUILDERS.contains(&builder.id.as_str()) {
+                    return Err(format!("unknown map builder \"{}\" (known: {})", builder.id, crate::map_builders::BUILDERS.join(", ")));
+                }
+                if builder.weight <= 0 {
+                    return Err(format!("map builder \"{}\" must have positive weight", builder.id));
+                }
+            }
+        }
+
+        for raw in self.prefabs.iter() {
+            Prefab::parse(raw).map_err(|e| format!("prefab \"{}\": {}", raw.id, e))?;
+            for id in raw.legend.values() {
+                if !ids.contains(id.as_str()) {
+                    return Err(format!("prefab \"{}\" refers to unknown id \"{}\"", raw.id, id));
+                }
+            }
+            if !(0..=100).contains(&raw.chance) {
+                return Err(format!("prefab \"{}\": \"chance\" must be between 0 and 100", raw.id));
+            }
+        }
+
+        for rule in self.terrain.iter() {
+            if !crate::map_builders::TERRAIN.contains(&rule.id.as_str()) {
+                return Err(format!("unknown terrain \"{}\" (known: {})", rule.id, crate::map_builders::TERRAIN.join(", ")));
+            }
+            if !(0..=100).contains(&rule.chance) {
+                return Err(format!("terrain \"{}\": \"chance\" must be between 0 and 100", rule.id));
+            }
+            if rule.count <= 0 || rule.size <= 0 {
+                return Err(format!("terrain \"{}\": \"count\" and \"size\" must be positive", rule.id));
+            }
+        }
+
+        let mut recipe_ids = HashSet::new();
+        for recipe in self.recipes.iter() {
+            if !recipe_ids.insert(recipe.id.as_str()) {
+                return Err(format!("recipe \"{}\" is defined more than once", recipe.id));
+            }
+            if recipe.outputs.is_empty() {
+                return Err(format!("recipe \"{}\" has no outputs", recipe.id));
+            }
+            match &recipe.random {
+                None if recipe.ingredients.is_empty() => return Err(format!("recipe \"{}\" needs \"ingredients\" or \"random\"", recipe.id)),
+                Some(_) if !recipe.ingredients.is_empty() => return Err(format!("recipe \"{}\" cannot have both \"ingredients\" and \"random\"", recipe.id)),
+                Some(random) if random.from.is_empty() || random.count <= 0 => return Err(format!("recipe \"{}\" must roll a positive number of ingredients from a non-empty list", recipe.id)),
+                Some(random) if random.from.iter().enumerate().any(|(i, kind)| random.from[..i].contains(kind)) => return Err(format!("recipe \"{}\" lists a kind to roll more than once", recipe.id)),
+                Some(random) if random.count as usize > random.from.len() => return Err(format!("recipe \"{}\" rolls {} different kinds from only {}", recipe.id, random.count, random.from.len())),
+                _ => {}
+            }
+            if recipe.temperature == Some(Temperature::Room) {
+                return Err(format!("recipe \"{}\": leave \"temperature\" out instead of \"room\"", recipe.id));
+            }
+            for (i, ingredient) in recipe.ingredients.iter().enumerate() {
+                if ingredient.count <= 0 {
+                    return Err(format!("recipe \"{}\": \"count\" of {} must be positive", recipe.id, ingredient.kind.name()));
+                }
+                if ingredient.min_potency > MAX_POTENCY {
+                    return Err(format!("recipe \"{}\": \"min_potency\" of {} is above the strongest potency {}", recipe.id, ingredient.kind.name(), MAX_POTENCY));
+                }
+                if recipe.ingredients[..i].iter().any(|other| other.kind == ingredient.kind) {
+                    return Err(format!("recipe \"{}\" lists {} more than once", recipe.id, ingredient.kind.name()));
+                }
+            }
+        }
+
+        for table in [ROOM_TABLE, FINAL_LEVEL_TABLE] {
+            if self.spawn_table(table).is_none() {
+                return Err(format!("spawn table \"{}\" is missing", table));
+            }
+        }
+        if self.monster(BOSS).is_none() {
+            return Err(format!("monster \"{}\" is missing", BOSS));
+        }
+        if self.item(MACGUFFIN).is_none() {
+            return Err(format!("item \"{}\" is missing", MACGUFFIN));
+        }
+        let lab = self.prefab(FINAL_LAB).ok_or_else(|| format!("prefab \"{}\" is missing", FINAL_LAB))?;
+        let lab = Prefab::parse(lab)?;
+        for (tile, what) in [(PrefabTile::Start, "'@'"), (PrefabTile::Tile(TileType::BossSpawner), "'A'"), (PrefabTile::Tile(TileType::MacGuffinSpawner), "'*'")] {
+            if !lab.contains(&tile) {
+                return Err(format!("prefab \"{}\" has no {}", FINAL_LAB, what));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn only_items(&self, id: &str) -> bool {
+        if self.item(id).is_some() {
+            return true;
+        }
+        self.group(id).is_some_and(|g| g.members.iter().all(|m| self.only_items(&m.id)))
+    }
+
+    pub fn only_monsters(&self, id: &str) -> bool {
+        if self.monster(id).is_some() {
+            return true;
+        }
+        self.group(id).is_some_and(|g| g.members.iter().all(|m| self.only_monsters(&m.id)))
+    }
+
+    fn check_group_cycle(&self, id: &str, path: &mut Vec<String>) -> Result<(), String> {
+        if path.iter().any(|p| p == id) {
+            path.push(id.to_owned());
+            return Err(format!("groups refer to each other in a loop: {}", path.join(" -> ")));
+        }
+        if let Some(group) = self.group(id) {
+            path.push(id.to_owned());
+            for member in group.members.iter() {
+                self.check_group_cycle(&member.id, path)?;
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_color(code: &str) -> Result<RGB, String> {
+    RGB::from_hex(code).map_err(|_| format!("\"{}\" is not a colour, expected \"#RRGGBB\"", code))
+}
+
+impl RawEffect {
+    pub fn to_potion_effect(self) -> PotionEffect {
+        match self {
+            RawEffect::Heal { amount } => PotionEffect::Heal(ProvidesHealing { heal_amount: amount }),
+            RawEffect::Teleport { safe } => PotionEffect::Teleport(Teleport { safe }),
+            RawEffect::Confusion { turns } => PotionEffect::Confusion(Confusion { turns }),
+            RawEffect::Harm { damage } => PotionEffect::Harm(InstantHarm { dmg: damage }),
+            RawEffect::Fire { duration, damage }
+                => PotionEffect::Linger(LingeringEffect { etype: LingerType::Fire, duration, dmg: damage }),
+            RawEffect::Poison { duration, damage }
+                => PotionEffect::Linger(LingeringEffect { etype: LingerType::Poison, duration, dmg: damage }),
+            RawEffect::Explosion { damage, radius } => PotionEffect::Explosion(Explosion { maxdmg: damage, radius }),
+            RawEffect::Invulnerability { turns } => PotionEffect::Invulnerability(Invulnerability { turns }),
+            RawEffect::Strength { turns } => PotionEffect::Strength(Strength { turns })
+        }
+    }
+}
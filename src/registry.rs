@@ -0,0 +1,47 @@
+//! Единый список компонентов и ресурсов мира. По нему компоненты
+//! регистрируются в `Simulation::new`, а сейв пишется и читается в
+//! `saveload_system`, так что новый компонент достаточно добавить сюда.
+
+use specs::prelude::*;
+use specs::saveload::SimpleMarker;
+
+use crate::components::*;
+
+/// Calls `$callback!(<args> Position, Renderable, ...)` with every component type.
+macro_rules! all_components {
+    ($callback:ident ! ( $($args:tt)* )) => {
+        $callback!( $($args)*
+            Position, Renderable, Viewshed, Player, Monster, Name, BlocksTile, CombatStats, SufferDamage,
+            Item, ProvidesHealing, Consumable, InBackpack, WantsToMelee, WantsToPickupItem, WantsToUseItem,
+            WantsToDropItem, WantsToThrowItem, WantsToDilute, Ranged, InflictsDamage, AreaOfEffect,
+            Confusion, Agitated, Teleport, Weight, ParticleLifetime, Potion, Diluted, Unidentified, Identifies, LingeringEffect,
+            InstantHarm, Explosion, Invulnerability, Strength, Bomber, Lobber, Exhales, Boss, MacGuffin, AlchemyStation, Busy
+        )
+    };
+}
+pub(crate) use all_components;
+
+/// Resources that belong to the run and go into the save. The rest of the
+/// resources are either rebuilt on load (player entity, tile contents) or
+/// only live for a turn (effect queue, particles, events).
+macro_rules! saved_resources {
+    ($callback:ident ! ( $($args:tt)* )) => {
+        $callback!( $($args)*
+            Map, RunSeed, AlchemySeed, PotionAppearances, AlchemyJournal, RandomStreams, GameLog, TurnCounter, LevelStore
+        )
+    };
+}
+pub(crate) use saved_resources;
+
+macro_rules! register_individually {
+    ($ecs:expr, $( $type:ty ),*) => {
+        $( $ecs.register::<$type>(); )*
+    };
+}
+
+pub fn register_components(ecs: &mut World) {
+    all_components!(register_individually!(ecs,));
+    // Список ингредиентов не сохранить через маркеры, да и живёт намерение один ход
+    ecs.register::<WantsToMixPotions>();
+    ecs.register::<SimpleMarker<SerializeMe>>();
+}
@@ -0,0 +1,213 @@
+use rltk::Point;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use crate::components::{CombatStats, InBackpack, Name, ParticleLifetime, Position, Temperature};
+use crate::map::Map;
+use crate::raws::Raws;
+use crate::rng::{RandomStreams, RunSeed};
+use crate::simulation::{PlayerCommand, Simulation, STATION_SLOTS};
+use crate::RunState;
+
+const REPLAY_VERSION: u32 = 14;
+
+/// `PlayerCommand` with items stored by entity id. With the same seed and the
+/// same commands entities are allocated identically, so the ids stay valid.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum RecordedCommand {
+    Move { dx: i32, dy: i32 },
+    PickUp,
+    Use { item: u32, target: Option<(i32, i32)> },
+    Drop { item: u32 },
+    Throw { item: u32, target: (i32, i32) },
+    Mix { first: u32, second: u32 },
+    Brew { station: u32, ingredients: [Option<u32>; STATION_SLOTS], temperature: Temperature },
+    Dilute { item: u32 },
+    Descend,
+    Ascend,
+    Wait
+}
+
+impl RecordedCommand {
+    pub fn record(command: PlayerCommand) -> RecordedCommand {
+        match command {
+            PlayerCommand::Move { dx, dy } => RecordedCommand::Move { dx, dy },
+            PlayerCommand::PickUp => RecordedCommand::PickUp,
+            PlayerCommand::Use { item, target }
+                => RecordedCommand::Use { item: item.id(), target: target.map(|p| (p.x, p.y)) },
+            PlayerCommand::Drop { item } => RecordedCommand::Drop { item: item.id() },
+            PlayerCommand::Throw { item, target }
+                => RecordedCommand::Throw { item: item.id(), target: (target.x, target.y) },
+            PlayerCommand::Mix { first, second } => RecordedCommand::Mix { first: first.id(), second: second.id() },
+            PlayerCommand::Brew { station, ingredients, temperature }
+                => RecordedCommand::Brew { station: station.id(), ingredients: ingredients.map(|i| i.map(|i| i.id())), temperature },
+            PlayerCommand::Dilute { item } => RecordedCommand::Dilute { item: item.id() },
+            PlayerCommand::Descend => RecordedCommand::Descend,
+            PlayerCommand::Ascend => RecordedCommand::Ascend,
+            PlayerCommand::Wait => RecordedCommand::Wait
+        }
+    }
+
+    pub fn resolve(&self, ecs: &World) -> PlayerCommand {
+        let entities = ecs.entities();
+        match *self {
+            RecordedCommand::Move { dx, dy } => PlayerCommand::Move { dx, dy },
+            RecordedCommand::PickUp => PlayerCommand::PickUp,
+            RecordedCommand::Use { item, target }
+                => PlayerCommand::Use { item: entities.entity(item), target: target.map(|(x, y)| Point::new(x, y)) },
+            RecordedCommand::Drop { item } => PlayerCommand::Drop { item: entities.entity(item) },
+            RecordedCommand::Throw { item, target }
+                => PlayerCommand::Throw { item: entities.entity(item), target: Point::new(target.0, target.1) },
+            RecordedCommand::Mix { first, second }
+                => PlayerCommand::Mix { first: entities.entity(first), second: entities.entity(second) },
+            RecordedCommand::Brew { station, ingredients, temperature }
+                => PlayerCommand::Brew { station: entities.entity(station), ingredients: ingredients.map(|i| i.map(|i| entities.entity(i))), temperature },
+            RecordedCommand::Dilute { item } => PlayerCommand::Dilute { item: entities.entity(item) },
+            RecordedCommand::Descend => PlayerCommand::Descend,
+            RecordedCommand::Ascend => PlayerCommand::Ascend,
+            RecordedCommand::Wait => PlayerCommand::Wait
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedTurn {
+    pub command: RecordedCommand,
+    /// World checksum once the turn has played out; `None` while it is still running
+    pub checksum: Option<u64>
+}
+
+/// Everything needed to play a run again: the seed and the player's commands.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub turns: Vec<RecordedTurn>
+}
+
+pub enum ReplayOutcome {
+    Matched { turns: usize },
+    Diverged { turn: usize, expected: u64, actual: u64 },
+    /// The run ended (death, win) before all recorded turns were played
+    EndedEarly { turn: usize }
+}
+
+impl Replay {
+    pub fn new(seed: RunSeed) -> Replay {
+        Replay { version: REPLAY_VERSION, seed: seed.0, turns: Vec::new() }
+    }
+
+    pub fn record_command(&mut self, command: PlayerCommand) {
+        self.turns.push(RecordedTurn { command: RecordedCommand::record(command), checksum: None });
+    }
+
+    /// Closes the current turn, if there is one waiting for its checksum.
+    pub fn record_checksum(&mut self, ecs: &World) {
+        if let Some(turn) = self.turns.last_mut() {
+            if turn.checksum.is_none() {
+                turn.checksum = Some(world_checksum(ecs));
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let data = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let replay: Replay = serde_json::from_str(&data).map_err(|e| format!("Malformed replay {}: {}", path, e))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!("Replay version {} is not supported (expected {})", replay.version, REPLAY_VERSION));
+        }
+        Ok(replay)
+    }
+
+    /// Plays the recorded commands from a fresh world and compares checksums turn by turn.
+    pub fn verify(&self, raws: &Raws) -> ReplayOutcome {
+        let mut sim = Simulation::new(RunSeed(self.seed), raws.clone());
+        sim.run_until_input();
+
+        for (turn, recorded) in self.turns.iter().enumerate() {
+            if sim.runstate() != RunState::AwaitingInput {
+                return ReplayOutcome::EndedEarly { turn };
+            }
+
+            let command = recorded.command.resolve(&sim.ecs);
+            sim.play(command);
+
+            let actual = world_checksum(&sim.ecs);
+            if let Some(expected) = recorded.checksum {
+                if expected != actual {
+                    return ReplayOutcome::Diverged { turn, expected, actual };
+                }
+            }
+        }
+
+        ReplayOutcome::Matched { turns: self.turns.len() }
+    }
+}
+
+// FNV-1a; байты всегда little-endian, чтобы реплеи переносились между машинами
+struct Checksum(u64);
+
+impl Checksum {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    fn int(&mut self, value: i64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+/// Hash of the gameplay-relevant state: the map, every non-particle entity
+/// and the random streams. Visual-only state is left out, and so is the
+/// log: the frontend writes prompts and save errors into it.
+pub fn world_checksum(ecs: &World) -> u64 {
+    let mut sum = Checksum(0xCBF2_9CE4_8422_2325);
+
+    let map = ecs.fetch::<Map>();
+    sum.int(map.depth as i64);
+    for tile in map.tiles.iter() {
+        sum.int(*tile as i64);
+    }
+
+    let entities = ecs.entities();
+    let particles = ecs.read_storage::<ParticleLifetime>();
+    let positions = ecs.read_storage::<Position>();
+    let stats = ecs.read_storage::<CombatStats>();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let names = ecs.read_storage::<Name>();
+
+    for (entity, _) in (&entities, !&particles).join() {
+        sum.int(entity.id() as i64);
+        sum.int(entity.gen().id() as i64);
+        if let Some(pos) = positions.get(entity) {
+            sum.int(pos.x as i64);
+            sum.int(pos.y as i64);
+        }
+        if let Some(stats) = stats.get(entity) {
+            sum.int(stats.hp as i64);
+            sum.int(stats.max_hp as i64);
+            sum.int(stats.power as i64);
+            sum.int(stats.defence as i64);
+        }
+        if let Some(backpack) = backpacks.get(entity) {
+            sum.int(backpack.owner.id() as i64);
+        }
+        if let Some(name) = names.get(entity) {
+            sum.bytes(name.name.as_bytes());
+        }
+    }
+
+    let streams = ecs.fetch::<RandomStreams>();
+    let streams = serde_json::to_vec(&*streams).expect("Unable to serialize random streams");
+    sum.bytes(&streams);
+
+    sum.0
+}
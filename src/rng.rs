@@ -0,0 +1,63 @@
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
+
+/// Master seed of a run. Every random source in the game is derived from it,
+/// so the same seed and the same inputs always give the same run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RunSeed(pub u64);
+
+/// Independent random streams, one per subsystem. Rolling more dice in one
+/// of them (e.g. an extra AI decision) never shifts the others.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RandomStreams {
+    pub mapgen: RandomNumberGenerator,
+    pub spawns: RandomNumberGenerator,
+    pub combat: RandomNumberGenerator,
+    pub ai: RandomNumberGenerator,
+    /// Чисто визуальные эффекты: не должны влиять на игровые броски
+    pub particles: RandomNumberGenerator,
+}
+
+// Номера потоков; менять нельзя, иначе старые сиды дадут другие забеги
+const STREAM_MAPGEN: u64 = 1;
+const STREAM_SPAWNS: u64 = 2;
+const STREAM_ALCHEMY: u64 = 3;
+const STREAM_COMBAT: u64 = 4;
+const STREAM_AI: u64 = 5;
+const STREAM_PARTICLES: u64 = 6;
+const STREAM_APPEARANCE: u64 = 7;
+
+impl RandomStreams {
+    pub fn new(seed: RunSeed) -> RandomStreams {
+        RandomStreams {
+            mapgen: RandomNumberGenerator::seeded(stream_seed(seed, STREAM_MAPGEN)),
+            spawns: RandomNumberGenerator::seeded(stream_seed(seed, STREAM_SPAWNS)),
+            combat: RandomNumberGenerator::seeded(stream_seed(seed, STREAM_COMBAT)),
+            ai: RandomNumberGenerator::seeded(stream_seed(seed, STREAM_AI)),
+            particles: RandomNumberGenerator::seeded(stream_seed(seed, STREAM_PARTICLES)),
+        }
+    }
+}
+
+/// Seed for the per-run alchemy recipes.
+pub fn alchemy_seed(seed: RunSeed) -> u64 {
+    stream_seed(seed, STREAM_ALCHEMY)
+}
+
+/// Seed for the per-run looks of potions.
+pub fn appearance_seed(seed: RunSeed) -> u64 {
+    stream_seed(seed, STREAM_APPEARANCE)
+}
+
+/// A fresh seed for runs started without one.
+pub fn random_seed() -> RunSeed {
+    RunSeed(RandomNumberGenerator::new().next_u64())
+}
+
+// splitmix64: соседние номера потоков дают несвязанные сиды
+fn stream_seed(seed: RunSeed, stream: u64) -> u64 {
+    let mut z = seed.0.wrapping_add(stream.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
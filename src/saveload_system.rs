@@ -1,122 +1,916 @@
-use std::fs::{self, File};
-use std::path::Path;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use std::convert::Infallible;
-use specs::saveload::SimpleMarkerAllocator;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value};
+use specs::saveload::{Marker, MarkerAllocator, SimpleMarkerAllocator};
 use specs::{Entity, Join};
-use specs::{saveload::{MarkedBuilder, SimpleMarker, SerializeComponents, DeserializeComponents}, Builder, World, WorldExt};
+use specs::{saveload::{SimpleMarker, SerializeComponents, DeserializeComponents}, World, WorldExt};
 
 use crate::components::*;
+use crate::gamelog::GameLog;
+use crate::identify_system::{PotionAppearances, PotionKind};
+use crate::journal::{flasks_holding, AlchemyJournal, JournalEntry, MixResult};
+use crate::level_store::LevelStore;
+use crate::map::Map;
+use crate::registry::{all_components, saved_resources};
+use crate::rng::{self, RandomStreams, RunSeed};
+use crate::simulation::TurnCounter;
+use crate::{AlchemySeed, RunState};
+
+const SAVE_DIR: &str = "./saves";
+/// Where the game kept its only save before there were slots
+const LEGACY_SAVE_PATH: &str = "./savegame.json";
+
+/// Manual slots the player can save into; the autosave has its own file.
+pub const MANUAL_SLOTS: usize = 3;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SaveSlot {
+    /// Written on every level change
+    Auto,
+    /// Numbered from 1
+    Manual(usize)
+}
+
+impl SaveSlot {
+    /// Every slot in the order the menu shows them.
+    pub fn all() -> Vec<SaveSlot> {
+        std::iter::once(SaveSlot::Auto).chain((1..=MANUAL_SLOTS).map(SaveSlot::Manual)).collect()
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            SaveSlot::Auto => "Autosave".to_owned(),
+            SaveSlot::Manual(n) => format!("Slot {}", n)
+        }
+    }
+
+    fn path(self) -> PathBuf {
+        match self {
+            SaveSlot::Auto => Path::new(SAVE_DIR).join("autosave.json"),
+            SaveSlot::Manual(n) => Path::new(SAVE_DIR).join(format!("slot{}.json", n))
+        }
+    }
+
+    /// Копия сейва на время загрузки: удаляется, только когда загрузка прошла успешно
+    fn backup_path(self) -> PathBuf {
+        let mut path = self.path().into_os_string();
+        path.push(".bak");
+        PathBuf::from(path)
+    }
+}
+
+/// Version of the save layout. Bump it when the saved data changes shape and
+/// add a migration from the previous version to `MIGRATIONS`.
+pub const SAVE_FORMAT_VERSION: u32 = 13;
+
+/// `(from_version, migration)`: turns a save of `from_version` into `from_version + 1`.
+type Migration = fn(&mut Value) -> Result<(), String>;
+const MIGRATIONS: &[(u32, Migration)] = &[
+    (1, migrate_v1_to_v2),
+    (2, migrate_v2_to_v3),
+    (3, migrate_v3_to_v4),
+    (4, migrate_v4_to_v5),
+    (5, migrate_v5_to_v6),
+    (6, migrate_v6_to_v7),
+    (7, migrate_v7_to_v8),
+    (8, migrate_v8_to_v9),
+    (9, migrate_v9_to_v10),
+    (10, migrate_v10_to_v11),
+    (11, migrate_v11_to_v12),
+    (12, migrate_v12_to_v13)
+];
+
+/// Component order of the headerless saves (format 1), which wrote one array per component.
+const V1_COMPONENTS: &[&str] = &["Position", "Renderable", "Player", "Viewshed", "Monster",
+    "Name", "BlocksTile", "CombatStats", "SufferDamage", "WantsToMelee", "Item", "Consumable", "Ranged", "InflictsDamage",
+    "AreaOfEffect", "Confusion", "ProvidesHealing", "InBackpack", "ParticleLifetime", "WantsToPickupItem", "WantsToUseItem",
+    "WantsToDropItem", "WantsToThrowItem", "WantsToMixPotions", "Weight", "Teleport", "LingeringEffect", "InstantHarm",
+    "Explosion", "Invulnerability", "Strength", "Potion", "Boss", "Bomber", "Lobber", "MacGuffin", "SerializationHelper"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SaveHeader {
+    pub format_version: u32,
+    /// Version of the game that wrote the save
+    pub game_version: String,
+    /// `None` for saves made before the header existed
+    pub seed: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub timestamp: Option<u64>,
+    /// What the save menu shows about the run; missing in old saves
+    #[serde(default)]
+    pub summary: Option<RunSummary>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RunSummary {
+    pub depth: i32,
+    pub hp: i32,
+    pub max_hp: i32,
+    pub turn: u32
+}
+
+impl SaveHeader {
+    /// One line for the save menu, e.g. `Depth 3, HP 12/30, turn 415, 2025-03-09 18:20`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if let Some(summary) = &self.summary {
+            parts.push(format!("Depth {}", summary.depth));
+            parts.push(format!("HP {}/{}", summary.hp, summary.max_hp));
+            parts.push(format!("turn {}", summary.turn));
+        }
+        if let Some(timestamp) = self.timestamp {
+            parts.push(format_timestamp(timestamp));
+        }
+        if parts.is_empty() {
+            return "old save".to_owned();
+        }
+        parts.join(", ")
+    }
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC.
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let minutes = timestamp % 86400 / 60;
+
+    // Дни от эпохи в григорианскую дату (алгоритм Хиннанта)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// The part of `RunState` worth resuming: menus and targeting are not saved.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+enum SavedRunState {
+    AwaitingInput,
+    PreRun,
+    PlayerTurn,
+    MonsterTurn,
+    NextLevel,
+    PreviousLevel,
+    GameOver
+}
+
+impl SavedRunState {
+    fn from_runstate(runstate: RunState) -> SavedRunState {
+        match runstate {
+            RunState::PreRun => SavedRunState::PreRun,
+            RunState::PlayerTurn => SavedRunState::PlayerTurn,
+            RunState::MonsterTurn => SavedRunState::MonsterTurn,
+            RunState::NextLevel => SavedRunState::NextLevel,
+            RunState::PreviousLevel => SavedRunState::PreviousLevel,
+            RunState::GameOver => SavedRunState::GameOver,
+            _ => SavedRunState::AwaitingInput
+        }
+    }
+
+    fn to_runstate(self) -> RunState {
+        match self {
+            SavedRunState::AwaitingInput => RunState::AwaitingInput,
+            SavedRunState::PreRun => RunState::PreRun,
+            SavedRunState::PlayerTurn => RunState::PlayerTurn,
+            SavedRunState::MonsterTurn => RunState::MonsterTurn,
+            SavedRunState::NextLevel => RunState::NextLevel,
+            SavedRunState::PreviousLevel => RunState::PreviousLevel,
+            SavedRunState::GameOver => RunState::GameOver
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(String),
+    /// The file is not a save, or the world in it does not fit the components
+    Malformed(String),
+    /// Made by a newer build of the game
+    TooNew { version: u32 },
+    Migration { from: u32, message: String }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "Unable to access the save: {}", e),
+            SaveError::Malformed(e) => write!(f, "The save is damaged: {}", e),
+            SaveError::TooNew { version }
+                => write!(f, "The save is from a newer version of the game (format {}, this build reads up to {})", version, SAVE_FORMAT_VERSION),
+            SaveError::Migration { from, message } => write!(f, "Unable to upgrade the save from format {}: {}", from, message)
+        }
+    }
+}
 
 #[allow(deprecated)]
 macro_rules! serialize_individually {
-    ($ecs:expr, $ser:expr, $data:expr, $( $type:ty),*) => {
+    ($ecs:expr, $components:expr, $data:expr, $( $type:ty),*) => {
         $(
-        SerializeComponents::<Infallible, SimpleMarker<SerializeMe>>::serialize(
+        let value = SerializeComponents::<Infallible, SimpleMarker<SerializeMe>>::serialize(
             &( $ecs.read_storage::<$type>(), ),
             &$data.0,
             &$data.1,
-            &mut $ser,
+            serde_json::value::Serializer,
         )
-        .unwrap();
+        .map_err(|e| SaveError::Malformed(format!("{}: {}", stringify!($type), e)))?;
+        $components.insert(stringify!($type).to_owned(), value);
         )*
     };
 }
 
 macro_rules! deserialize_individually {
-    ($ecs:expr, $de:expr, $data:expr, $( $type:ty),*) => {
+    ($ecs:expr, $components:expr, $data:expr, $( $type:ty),*) => {
         $(
+        // Компонента может не быть в старом сейве
+        let value = $components.remove(stringify!($type)).unwrap_or(Value::Array(vec![]));
         DeserializeComponents::<Infallible, _>::deserialize(
             &mut ( &mut $ecs.write_storage::<$type>(), ),
             &mut $data.0, // entities
             &mut $data.1, // marker
             &mut $data.2, // allocater
-            &mut $de,
+            value,
         )
-        .unwrap();
+        .map_err(|e| SaveError::Malformed(format!("{}: {}", stringify!($type), e)))?;
+        )*
+    };
+}
+
+macro_rules! serialize_resources {
+    ($ecs:expr, $resources:expr, $( $type:ty),*) => {
+        $(
+        let value = serde_json::to_value(&*$ecs.fetch::<$type>())
+            .map_err(|e| SaveError::Malformed(format!("{}: {}", stringify!($type), e)))?;
+        $resources.insert(stringify!($type).to_owned(), value);
+        )*
+    };
+}
+
+macro_rules! deserialize_resources {
+    ($ecs:expr, $resources:expr, $( $type:ty),*) => {
+        $(
+        // Чего нет в сейве, остаётся как в новом мире
+        if let Some(value) = $resources.remove(stringify!($type)) {
+            let resource: $type = serde_json::from_value(value)
+                .map_err(|e| SaveError::Malformed(format!("{}: {}", stringify!($type), e)))?;
+            $ecs.insert(resource);
+        }
         )*
     };
 }
 
 #[cfg(target_arch = "wasm32")]
-pub fn save_game(_ecs: &mut World) {}
+pub fn save_game(_ecs: &mut World, _slot: SaveSlot) -> Result<(), SaveError> { Ok(()) }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn save_game(ecs: &mut World) {
-    let mapcopy = ecs.get_mut::<super::map::Map>().unwrap().clone();
-    let savehelper = ecs
-        .create_entity()
-        .with(SerializationHelper { map: mapcopy })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+pub fn save_game(ecs: &mut World, slot: SaveSlot) -> Result<(), SaveError> {
+    let save = world_to_value(ecs)?;
+    let data = serde_json::to_string(&save).map_err(|e| SaveError::Malformed(e.to_string()))?;
+
+    fs::create_dir_all(SAVE_DIR).map_err(|e| SaveError::Io(e.to_string()))?;
+    // Сначала во временный файл: если игра упадёт посреди записи, старый сейв останется целым
+    let mut tmp_path = slot.path().into_os_string();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, data).map_err(|e| SaveError::Io(e.to_string()))?;
+    fs::rename(&tmp_path, slot.path()).map_err(|e| SaveError::Io(e.to_string()))?;
+    // Старая копия от прерванной загрузки больше не нужна
+    delete_backup(slot)
+}
 
+/// The whole run as a save: header, every component in the registry and the saved resources.
+fn world_to_value(ecs: &mut World) -> Result<Value, SaveError> {
+    mark_unmarked(ecs);
+
+    let mut components = JsonMap::new();
     {
         let data = (ecs.entities(), ecs.read_storage::<SimpleMarker<SerializeMe>>());
-
-        let writer = File::create("./savegame.json").unwrap();
-        let mut serializer = serde_json::Serializer::new(writer);
-        serialize_individually!(ecs, serializer, data, Position, Renderable, Player, Viewshed, Monster, 
-            Name, BlocksTile, CombatStats, SufferDamage, WantsToMelee, Item, Consumable, Ranged, InflictsDamage, 
-            AreaOfEffect, Confusion, ProvidesHealing, InBackpack, ParticleLifetime, WantsToPickupItem, WantsToUseItem,
-            WantsToDropItem, WantsToThrowItem, WantsToMixPotions, Weight, Teleport, LingeringEffect, InstantHarm, 
-            Explosion, Invulnerability, Strength, Potion, Boss, Bomber, Lobber, MacGuffin, SerializationHelper);
+        all_components!(serialize_individually!(ecs, components, data,));
     }
 
-    ecs.delete_entity(savehelper).expect("Crash on cleanup");
+    let mut resources = JsonMap::new();
+    saved_resources!(serialize_resources!(ecs, resources,));
+    let runstate = SavedRunState::from_runstate(*ecs.fetch::<RunState>());
+    resources.insert("RunState".to_owned(), serde_json::to_value(runstate).map_err(|e| SaveError::Malformed(e.to_string()))?);
+
+    let header = SaveHeader {
+        format_version: SAVE_FORMAT_VERSION,
+        game_version: env!("CARGO_PKG_VERSION").to_owned(),
+        seed: Some(ecs.fetch::<RunSeed>().0),
+        timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs()),
+        summary: Some(run_summary(ecs))
+    };
+    Ok(serde_json::json!({ "header": header, "components": components, "resources": resources }))
 }
 
-pub fn does_save_exist() -> bool {
-    Path::new("./savegame.json").exists()
+fn run_summary(ecs: &World) -> RunSummary {
+    let player = *ecs.fetch::<Entity>();
+    let stats = ecs.read_storage::<CombatStats>();
+    let (hp, max_hp) = stats.get(player).map_or((0, 0), |s| (s.hp, s.max_hp));
+    RunSummary {
+        depth: ecs.fetch::<Map>().depth,
+        hp,
+        max_hp,
+        turn: ecs.fetch::<TurnCounter>().0
+    }
 }
 
-pub fn load_game(ecs: &mut World) {
+/// Components of `entities` in the save layout. Used to keep a level while the player is elsewhere.
+pub fn entities_to_value(ecs: &mut World, entities: &[Entity]) -> Result<Value, SaveError> {
+    mark_unmarked(ecs);
+
+    let mut components = JsonMap::new();
     {
-        let mut to_delete = vec![];
-        for e in ecs.entities().join() {
-            to_delete.push(e);
-        }
-        for del in to_delete.iter() {
-            ecs.delete_entity(*del).expect("Failed to delete entity");
+        let data = (ecs.entities(), ecs.read_storage::<SimpleMarker<SerializeMe>>());
+        all_components!(serialize_individually!(ecs, components, data,));
+    }
+
+    let markers = ecs.read_storage::<SimpleMarker<SerializeMe>>();
+    let keep: HashSet<u64> = entities.iter().filter_map(|e| markers.get(*e)).map(|m| m.id()).collect();
+    for entries in components.values_mut() {
+        if let Value::Array(entries) = entries {
+            entries.retain(|entry| entry["marker"][0].as_u64().is_some_and(|id| keep.contains(&id)));
         }
     }
+    Ok(Value::Object(components))
+}
 
-    let data = fs::read_to_string("./savegame.json").unwrap();
-    let mut de = serde_json::Deserializer::from_str(&data);
+/// Creates the entities stored by `entities_to_value`. References to entities
+/// that are still in the world (the player, their backpack) are kept.
+pub fn value_to_entities(ecs: &mut World, value: Value) -> Result<(), SaveError> {
+    let mut components = match value {
+        Value::Object(components) => components,
+        _ => return Err(SaveError::Malformed("no components".to_owned()))
+    };
 
-    {
-        let mut d = (&mut ecs.entities(), &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(), &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>());
+    let mut d = (&mut ecs.entities(), &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(), &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>());
+    all_components!(deserialize_individually!(ecs, components, d,));
+    Ok(())
+}
+
+// После загрузки аллокатор знает только маркеры живых сущностей.
+// Маркеры сохранённых уровней заняты: иначе новые сущности получат те же номера.
+fn reserve_stored_markers(ecs: &mut World) {
+    let Some(max) = ecs.try_fetch::<LevelStore>().and_then(|store| store.max_marker()) else { return };
+    let placeholder = ecs.entities().create();
+    ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>().allocate(placeholder, Some(max));
+    ecs.delete_entity(placeholder).expect("Unable to delete placeholder entity");
+}
+
+// Сущности, созданные системами (смеси, лужи, зелья лобберов), маркер не получают.
+// Частицы живут один ход, их не сохраняем.
+fn mark_unmarked(ecs: &mut World) {
+    let entities = ecs.entities();
+    let particles = ecs.read_storage::<ParticleLifetime>();
+    let mut markers = ecs.write_storage::<SimpleMarker<SerializeMe>>();
+    let mut allocator = ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>();
+
+    let unmarked: Vec<Entity> = (&entities, !&markers, !&particles).join().map(|(e, _, _)| e).collect();
+    for entity in unmarked {
+        allocator.mark(entity, &mut markers);
+    }
+}
+
+/// A save is still there if the game stopped while loading it.
+pub fn does_save_exist(slot: SaveSlot) -> bool {
+    slot.path().exists() || slot.backup_path().exists()
+}
+
+fn existing_path(slot: SaveSlot) -> PathBuf {
+    if slot.path().exists() { slot.path() } else { slot.backup_path() }
+}
+
+/// Header of the save in `slot`, or `None` if the slot is empty.
+pub fn read_header(slot: SaveSlot) -> Option<Result<SaveHeader, SaveError>> {
+    if !does_save_exist(slot) {
+        return None;
+    }
+    let header = fs::read_to_string(existing_path(slot))
+        .map_err(|e| SaveError::Io(e.to_string()))
+        .and_then(|data| parse_save(&data))
+        .and_then(|save| match save.get("header") {
+            Some(header) => serde_json::from_value(header.clone()).map_err(|e| SaveError::Malformed(e.to_string())),
+            // Сейвы без заголовка (формат 1)
+            None => Ok(SaveHeader { format_version: 1, game_version: "unknown".to_owned(), seed: None, timestamp: None, summary: None })
+        });
+    Some(header)
+}
+
+/// What is in a slot, as shown in the save menu.
+pub struct SlotListing {
+    pub slot: SaveSlot,
+    /// `None` for an empty slot
+    pub header: Option<Result<SaveHeader, SaveError>>
+}
+
+pub fn list_slots() -> Vec<SlotListing> {
+    SaveSlot::all().into_iter().map(|slot| SlotListing { slot, header: read_header(slot) }).collect()
+}
+
+/// Moves the save from before the slots existed into the first free slot.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn adopt_legacy_save() -> Result<(), SaveError> {
+    let legacy = [LEGACY_SAVE_PATH.to_owned(), format!("{}.bak", LEGACY_SAVE_PATH)]
+        .into_iter()
+        .find(|path| Path::new(path).exists());
+    let Some(legacy) = legacy else { return Ok(()) };
+    let Some(slot) = (1..=MANUAL_SLOTS).map(SaveSlot::Manual).find(|slot| !does_save_exist(*slot)) else { return Ok(()) };
+
+    fs::create_dir_all(SAVE_DIR).map_err(|e| SaveError::Io(e.to_string()))?;
+    fs::rename(&legacy, slot.path()).map_err(|e| SaveError::Io(e.to_string()))?;
+    for path in [LEGACY_SAVE_PATH.to_owned(), format!("{}.bak", LEGACY_SAVE_PATH)] {
+        if Path::new(&path).exists() { fs::remove_file(path).map_err(|e| SaveError::Io(e.to_string()))? }
+    }
+    Ok(())
+}
+
+/// Loads the save in `slot` into `ecs`. Reading, parsing and upgrading the save happen
+/// before the world is touched; if the components themselves do not fit,
+/// the world is left half-loaded and the caller should start a fresh one.
+/// On success the caller decides whether the save stays (see `delete_save`, `delete_backup`).
+pub fn load_game(ecs: &mut World, slot: SaveSlot) -> Result<(), SaveError> {
+    let path = existing_path(slot);
+    let data = fs::read_to_string(&path).map_err(|e| SaveError::Io(e.to_string()))?;
 
-        deserialize_individually!(ecs, de, d, Position, Renderable, Player, Viewshed, Monster, 
-            Name, BlocksTile, CombatStats, SufferDamage, WantsToMelee, Item, Consumable, Ranged, InflictsDamage, 
-            AreaOfEffect, Confusion, ProvidesHealing, InBackpack, ParticleLifetime, WantsToPickupItem, WantsToUseItem,
-            WantsToDropItem, WantsToThrowItem, WantsToMixPotions, Weight, Teleport, LingeringEffect, InstantHarm, 
-            Explosion, Invulnerability, Strength, Potion, Boss, Bomber, Lobber, MacGuffin, SerializationHelper
-        );
+    let mut save = parse_save(&data)?;
+    migrate(&mut save)?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if path == slot.path() {
+        fs::copy(slot.path(), slot.backup_path()).map_err(|e| SaveError::Io(e.to_string()))?;
+    }
+
+    value_to_world(ecs, save)
+}
+
+fn value_to_world(ecs: &mut World, mut save: Value) -> Result<(), SaveError> {
+    let mut components = match save.get_mut("components").map(Value::take) {
+        Some(Value::Object(components)) => components,
+        _ => return Err(SaveError::Malformed("no components".to_owned()))
+    };
+    let mut resources = match save.get_mut("resources").map(Value::take) {
+        Some(Value::Object(resources)) => resources,
+        _ => return Err(SaveError::Malformed("no resources".to_owned()))
+    };
+    if !resources.contains_key("Map") {
+        return Err(SaveError::Malformed("no map".to_owned()));
     }
 
-    let mut deleteme: Option<Entity> = None;
+    ecs.delete_all();
+
     {
+        let mut d = (&mut ecs.entities(), &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(), &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>());
+        all_components!(deserialize_individually!(ecs, components, d,));
+    }
+
+    saved_resources!(deserialize_resources!(ecs, resources,));
+    ecs.write_resource::<Map>().tile_content = vec![vec![]; crate::map::MAPCOUNT];
+    reserve_stored_markers(ecs);
+
+    let runstate = match resources.remove("RunState") {
+        Some(value) => serde_json::from_value::<SavedRunState>(value)
+                                  .map_err(|e| SaveError::Malformed(format!("RunState: {}", e)))?,
+        None => SavedRunState::AwaitingInput
+    };
+    ecs.insert(runstate.to_runstate());
+
+    let player = {
         let entities = ecs.entities();
-        let helper = ecs.read_storage::<SerializationHelper>();
         let player = ecs.read_storage::<Player>();
         let position = ecs.read_storage::<Position>();
-        for (e,h) in (&entities, &helper).join() {
-            let mut worldmap = ecs.write_resource::<crate::map::Map>();
-            *worldmap = h.map.clone();
-            worldmap.tile_content = vec![vec![]; crate::map::MAPCOUNT];
-            deleteme = Some(e);
+        (&entities, &player, &position).join().map(|(e, _p, pos)| (e, rltk::Point::new(pos.x, pos.y))).next()
+    };
+    let (player_entity, player_pos) = player.ok_or_else(|| SaveError::Malformed("no player".to_owned()))?;
+    ecs.insert(player_entity);
+    ecs.insert(player_pos);
+
+    Ok(())
+}
+
+/// Saves with a header are a single JSON object; format 1 is a bare stream of arrays.
+fn parse_save(data: &str) -> Result<Value, SaveError> {
+    if data.trim_start().starts_with('{') {
+        return serde_json::from_str(data).map_err(|e| SaveError::Malformed(e.to_string()));
+    }
+
+    let arrays = serde_json::Deserializer::from_str(data)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<Value>, _>>()
+        .map_err(|e| SaveError::Malformed(e.to_string()))?;
+    Ok(serde_json::json!({ "format_version": 1, "components": arrays }))
+}
+
+fn save_version(save: &Value) -> Result<u32, SaveError> {
+    save.get("header").unwrap_or(save)
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .ok_or_else(|| SaveError::Malformed("no format version".to_owned()))
+}
+
+fn migrate(save: &mut Value) -> Result<(), SaveError> {
+    let mut version = save_version(save)?;
+    if version > SAVE_FORMAT_VERSION {
+        return Err(SaveError::TooNew { version });
+    }
+
+    while version < SAVE_FORMAT_VERSION {
+        let (_, migration) = MIGRATIONS.iter()
+            .find(|(from, _)| *from == version)
+            .ok_or_else(|| SaveError::Migration { from: version, message: "no migration".to_owned() })?;
+        migration(save).map_err(|message| SaveError::Migration { from: version, message })?;
+        version = save_version(save)?;
+    }
+
+    Ok(())
+}
+
+// v1 -> v2: появился заголовок, компоненты хранятся по именам
+fn migrate_v1_to_v2(save: &mut Value) -> Result<(), String> {
+    let arrays = match save.get_mut("components").map(Value::take) {
+        Some(Value::Array(arrays)) => arrays,
+        _ => return Err("expected a list of components".to_owned())
+    };
+    if arrays.len() != V1_COMPONENTS.len() {
+        return Err(format!("expected {} components, found {}", V1_COMPONENTS.len(), arrays.len()));
+    }
+
+    let components: JsonMap<String, Value> = V1_COMPONENTS.iter().map(|name| name.to_string()).zip(arrays).collect();
+    let header = SaveHeader { format_version: 2, game_version: "unknown".to_owned(), seed: None, timestamp: None, summary: None };
+    *save = serde_json::json!({ "header": header, "components": components });
+    Ok(())
+}
+
+// v2 -> v3: ресурсы сохраняются отдельно; карта переезжает туда из SerializationHelper,
+// сиды восстанавливаются из заголовка
+fn migrate_v2_to_v3(save: &mut Value) -> Result<(), String> {
+    let helper = save["components"].as_object_mut()
+        .ok_or("expected components")?
+        .remove("SerializationHelper")
+        .ok_or("no SerializationHelper with the map")?;
+    let map = helper.get(0)
+        .and_then(|entity| entity["components"].get(0))
+        .and_then(|helper| helper.get("map"))
+        .cloned()
+        .ok_or("SerializationHelper has no map")?;
+
+    let mut resources = JsonMap::new();
+    resources.insert("Map".to_owned(), map);
+    if let Some(seed) = save["header"]["seed"].as_u64() {
+        resources.insert("RunSeed".to_owned(), serde_json::json!(seed));
+        resources.insert("AlchemySeed".to_owned(), serde_json::json!(rng::alchemy_seed(RunSeed(seed))));
+    }
+
+    save["resources"] = Value::Object(resources);
+    save["header"]["format_version"] = serde_json::json!(3);
+    Ok(())
+}
+
+// v3 -> v4: лужи стали слоем карты вместо сущностей с Puddle. Они живут
+// несколько ходов, так что старые просто высыхают
+fn migrate_v3_to_v4(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    add_fluid_layer(resources.get_mut("Map").ok_or("no map")?)?;
+    if let Some(Value::Object(levels)) = resources.get_mut("LevelStore").and_then(|store| store.get_mut("levels")) {
+        for level in levels.values_mut() {
+            add_fluid_layer(&mut level["map"])?;
+            if let Value::Object(components) = &mut level["entities"] {
+                remove_puddles(components);
+            }
+        }
+    }
+
+    let components = save["components"].as_object_mut().ok_or("expected components")?;
+    remove_puddles(components);
+
+    save["header"]["format_version"] = serde_json::json!(4);
+    Ok(())
+}
+
+fn add_fluid_layer(map: &mut Value) -> Result<(), String> {
+    map.as_object_mut().ok_or("the map is not an object")?.remove("puddles");
+    add_empty_layer(map, "fluids")
+}
+
+// v4 -> v5: на карте появился слой газа, в старых сейвах он пустой
+fn migrate_v4_to_v5(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    add_empty_layer(resources.get_mut("Map").ok_or("no map")?, "gases")?;
+    if let Some(Value::Object(levels)) = resources.get_mut("LevelStore").and_then(|store| store.get_mut("levels")) {
+        for level in levels.values_mut() {
+            add_empty_layer(&mut level["map"], "gases")?;
+        }
+    }
+
+    save["header"]["format_version"] = serde_json::json!(5);
+    Ok(())
+}
+
+// v5 -> v6: на карте появился слой огня, в старых сейвах ничего не горит
+fn migrate_v5_to_v6(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    fill_layer(resources.get_mut("Map").ok_or("no map")?, "fire", serde_json::json!(0))?;
+    if let Some(Value::Object(levels)) = resources.get_mut("LevelStore").and_then(|store| store.get_mut("levels")) {
+        for level in levels.values_mut() {
+            fill_layer(&mut level["map"], "fire", serde_json::json!(0))?;
+        }
+    }
+
+    save["header"]["format_version"] = serde_json::json!(6);
+    Ok(())
+}
+
+// v6 -> v7: зелья надо узнавать. В старых сейвах все зелья подписаны
+// настоящими именами, так что там всё уже известно
+fn migrate_v6_to_v7(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    let seed = resources.get("RunSeed").and_then(Value::as_u64).unwrap_or(0);
+    let appearances = serde_json::to_value(PotionAppearances::all_known(rng::appearance_seed(RunSeed(seed)))).map_err(|e| e.to_string())?;
+    resources.insert("PotionAppearances".to_owned(), appearances);
+
+    save["header"]["format_version"] = serde_json::json!(7);
+    Ok(())
+}
+
+// v7 -> v8: появился журнал алхимика, старые забеги начинают его с чистого листа
+fn migrate_v7_to_v8(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    let journal = serde_json::to_value(AlchemyJournal::default()).map_err(|e| e.to_string())?;
+    resources.insert("AlchemyJournal".to_owned(), journal);
+
+    save["header"]["format_version"] = serde_json::json!(8);
+    Ok(())
+}
+
+// v8 -> v9: рецепт может дать сразу несколько зелий, в журнале у каждого теперь список видов
+fn migrate_v8_to_v9(save: &mut Value) -> Result<(), String> {
+    let journal = save["resources"]["AlchemyJournal"].as_object_mut().ok_or("expected the alchemy journal")?;
+    for entry in journal.get_mut("entries").and_then(Value::as_array_mut).ok_or("the journal has no entries")? {
+        if let Some(special) = entry["result"].get_mut("Special") {
+            *special = serde_json::json!([special.take()]);
+        }
+    }
+    for recipe in journal.get_mut("recipes").and_then(Value::as_array_mut).ok_or("the journal has no recipes")? {
+        let result = recipe["result"].take();
+        recipe["result"] = serde_json::json!([result]);
+    }
+
+    save["header"]["format_version"] = serde_json::json!(9);
+    Ok(())
+}
+
+// v9 -> v10: на станциях мешают до трёх фляг и греют или студят их.
+// В журнале вместо двух фляг список и температура, старые смеси шли при комнатной
+fn migrate_v9_to_v10(save: &mut Value) -> Result<(), String> {
+    let journal = save["resources"]["AlchemyJournal"].as_object_mut().ok_or("expected the alchemy journal")?;
+    for entry in journal.get_mut("entries").and_then(Value::as_array_mut).ok_or("the journal has no entries")? {
+        let entry = entry.as_object_mut().ok_or("a journal entry is not an object")?;
+        let first = entry.remove("first").ok_or("a journal entry has no first flask")?;
+        let second = entry.remove("second").ok_or("a journal entry has no second flask")?;
+        entry.insert("ingredients".to_owned(), serde_json::json!([first, second]));
+        entry.insert("temperature".to_owned(), serde_json::json!(Temperature::Room));
+    }
+    for recipe in journal.get_mut("recipes").and_then(Value::as_array_mut).ok_or("the journal has no recipes")? {
+        recipe["temperature"] = serde_json::json!(Temperature::Room);
+    }
+
+    save["header"]["format_version"] = serde_json::json!(10);
+    Ok(())
+}
+
+// v10 -> v11: у зелий появилась крепость, все старые зелья обычные
+fn migrate_v10_to_v11(save: &mut Value) -> Result<(), String> {
+    let resources = save["resources"].as_object_mut().ok_or("expected resources")?;
+    if let Some(Value::Object(levels)) = resources.get_mut("LevelStore").and_then(|store| store.get_mut("levels")) {
+        for level in levels.values_mut() {
+            if let Value::Object(components) = &mut level["entities"] {
+                add_standard_potency(components)?;
+            }
         }
-        for (e,_p,pos) in (&entities, &player, &position).join() {
-            let mut ppos = ecs.write_resource::<rltk::Point>();
-            *ppos = rltk::Point::new(pos.x, pos.y);
-            let mut player_resource = ecs.write_resource::<Entity>();
-            *player_resource = e;
+    }
+
+    let components = save["components"].as_object_mut().ok_or("expected components")?;
+    add_standard_potency(components)?;
+
+    save["header"]["format_version"] = serde_json::json!(11);
+    Ok(())
+}
+
+// v11 -> v12: журнал помнит крепость фляг, в старых записях все фляги обычные
+fn migrate_v11_to_v12(save: &mut Value) -> Result<(), String> {
+    let journal = save["resources"]["AlchemyJournal"].as_object_mut().ok_or("expected the alchemy journal")?;
+    for entry in journal.get_mut("entries").and_then(Value::as_array_mut).ok_or("the journal has no entries")? {
+        let flasks = entry["ingredients"].as_array().map(Vec::len).ok_or("a journal entry has no ingredients")?;
+        entry["potencies"] = serde_json::json!(vec![STANDARD_POTENCY; flasks]);
+    }
+    for recipe in journal.get_mut("recipes").and_then(Value::as_array_mut).ok_or("the journal has no recipes")? {
+        recipe["potency"] = serde_json::json!(STANDARD_POTENCY);
+    }
+
+    save["header"]["format_version"] = serde_json::json!(12);
+    Ok(())
+}
+
+// v12 -> v13: рецепт помнит, сколько фляг каждого вида ушло в смесь.
+// Берём из записей журнала, где рецепт получался; если таких нет, по фляге
+fn migrate_v12_to_v13(save: &mut Value) -> Result<(), String> {
+    let journal = save["resources"]["AlchemyJournal"].as_object_mut().ok_or("expected the alchemy journal")?;
+    let entries: Vec<JournalEntry> = serde_json::from_value(journal.get("entries").cloned().ok_or("the journal has no entries")?)
+        .map_err(|e| e.to_string())?;
+    for recipe in journal.get_mut("recipes").and_then(Value::as_array_mut).ok_or("the journal has no recipes")? {
+        let ingredients: Vec<PotionKind> = serde_json::from_value(recipe["ingredients"].clone()).map_err(|e| e.to_string())?;
+        let result: Vec<PotionKind> = serde_json::from_value(recipe["result"].clone()).map_err(|e| e.to_string())?;
+        let temperature: Temperature = serde_json::from_value(recipe["temperature"].clone()).map_err(|e| e.to_string())?;
+        let counts = entries.iter()
+            .filter(|entry| entry.temperature == temperature && entry.result == MixResult::Special(result.clone()))
+            .filter(|entry| {
+                let mut kinds: Vec<PotionKind> = entry.ingredients.concat();
+                kinds.sort();
+                kinds.dedup();
+                kinds == ingredients
+            })
+            .map(|entry| flasks_holding(&ingredients, &entry.ingredients))
+            .reduce(|fewest, counts| fewest.iter().zip(counts.iter()).map(|(a, b)| *a.min(b)).collect())
+            .unwrap_or_else(|| vec![1; ingredients.len()]);
+        recipe["counts"] = serde_json::json!(counts);
+    }
+
+    save["header"]["format_version"] = serde_json::json!(13);
+    Ok(())
+}
+
+fn add_standard_potency(components: &mut JsonMap<String, Value>) -> Result<(), String> {
+    let Some(Value::Array(potions)) = components.get_mut("Potion") else { return Ok(()); };
+    let standard = serde_json::to_value(Potion::standard()).map_err(|e| e.to_string())?;
+    for potion in potions.iter_mut().filter_map(|entry| entry["components"].get_mut(0)).filter(|potion| potion.is_object()) {
+        *potion = standard.clone();
+    }
+    Ok(())
+}
+
+fn add_empty_layer(map: &mut Value, layer: &str) -> Result<(), String> {
+    fill_layer(map, layer, Value::Null)
+}
+
+fn fill_layer(map: &mut Value, layer: &str, value: Value) -> Result<(), String> {
+    let map = map.as_object_mut().ok_or("the map is not an object")?;
+    let tiles = map.get("tiles").and_then(Value::as_array).map(Vec::len).ok_or("the map has no tiles")?;
+    map.insert(layer.to_owned(), Value::Array(vec![value; tiles]));
+    Ok(())
+}
+
+fn remove_puddles(components: &mut JsonMap<String, Value>) {
+    let Some(Value::Array(puddles)) = components.remove("Puddle") else { return; };
+    let markers: HashSet<u64> = puddles.iter().filter_map(|entry| entry["marker"][0].as_u64()).collect();
+    for entries in components.values_mut() {
+        if let Value::Array(entries) = entries {
+            entries.retain(|entry| !entry["marker"][0].as_u64().is_some_and(|id| markers.contains(&id)));
         }
     }
+}
+
+/// Removes the save together with its safety copy.
+pub fn delete_save(slot: SaveSlot) -> Result<(), SaveError> {
+    for path in [slot.path(), slot.backup_path()] {
+        if path.exists() { std::fs::remove_file(path).map_err(|e| SaveError::Io(e.to_string()))? }
+    }
+    Ok(())
+}
 
-    ecs.delete_entity(deleteme.unwrap()).expect("Unable to delete helper");
+/// Removes the safety copy once the save has loaded; the save itself stays.
+pub fn delete_backup(slot: SaveSlot) -> Result<(), SaveError> {
+    let path = slot.backup_path();
+    if path.exists() && slot.path().exists() { std::fs::remove_file(path).map_err(|e| SaveError::Io(e.to_string()))? }
+    Ok(())
 }
 
-pub fn delete_save() {
-    if Path::new("./savegame.json").exists() { std::fs::remove_file("./savegame.json").expect("Unable to remove savefile")}
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use specs::saveload::ConvertSaveload;
+
+    use super::*;
+    use crate::raws;
+    use crate::simulation::{PlayerCommand, Simulation};
+
+    /// What a run consists of, with entity references replaced by save markers
+    /// so that a world can be compared with the same world loaded from a save.
+    struct Snapshot {
+        /// Component name -> marker -> value
+        components: BTreeMap<String, BTreeMap<u64, Value>>,
+        resources: BTreeMap<String, Value>,
+        player: Option<u64>,
+        player_pos: (i32, i32)
+    }
+
+    macro_rules! snapshot_components {
+        ($ecs:expr, $snapshot:expr, $( $type:ty ),*) => {
+            $(
+            let entities = $ecs.entities();
+            let markers = $ecs.read_storage::<SimpleMarker<SerializeMe>>();
+            let particles = $ecs.read_storage::<ParticleLifetime>();
+            let mut values = BTreeMap::new();
+            for (entity, component, _) in (&entities, &$ecs.read_storage::<$type>(), !&particles).join() {
+                let marker = markers.get(entity).expect("Unmarked entity").id();
+                let data = component.convert_into(|e| markers.get(e).cloned()).expect("Unable to convert component");
+                values.insert(marker, serde_json::to_value(data).expect("Unable to serialize component"));
+            }
+            $snapshot.insert(stringify!($type).to_owned(), values);
+            )*
+        };
+    }
+
+    macro_rules! snapshot_resources {
+        ($ecs:expr, $snapshot:expr, $( $type:ty ),*) => {
+            $(
+            let value = serde_json::to_value(&*$ecs.fetch::<$type>()).expect("Unable to serialize resource");
+            $snapshot.insert(stringify!($type).to_owned(), value);
+            )*
+        };
+    }
+
+    fn snapshot(ecs: &World) -> Snapshot {
+        let mut components = BTreeMap::new();
+        all_components!(snapshot_components!(ecs, components,));
+        let mut resources = BTreeMap::new();
+        saved_resources!(snapshot_resources!(ecs, resources,));
+
+        let player = ecs.read_storage::<SimpleMarker<SerializeMe>>().get(*ecs.fetch::<Entity>()).map(|m| m.id());
+        let pos = *ecs.fetch::<rltk::Point>();
+        Snapshot { components, resources, player, player_pos: (pos.x, pos.y) }
+    }
+
+    /// A run with a level in the `LevelStore` and some turns played on the next one.
+    fn played_run(raws: raws::Raws) -> Simulation {
+        let mut sim = Simulation::new(RunSeed(7), raws);
+        sim.run_until_input();
+        sim.descend_to(2);
+        sim.run_until_input();
+        for _ in 0..30 {
+            if sim.runstate() != RunState::AwaitingInput { break; }
+            sim.play(PlayerCommand::Wait);
+        }
+        sim
+    }
+
+    #[test]
+    fn loading_a_save_restores_the_world() {
+        let raws = raws::load().expect("Unable to load raws");
+        let mut sim = played_run(raws.clone());
+        let data = serde_json::to_string(&world_to_value(&mut sim.ecs).expect("Unable to save")).expect("Unable to write save");
+        let before = snapshot(&sim.ecs);
+        assert!(sim.ecs.fetch::<LevelStore>().levels.contains_key(&1), "the first level should be stored");
+
+        // Загружаем поверх другого забега, как это делает меню
+        let mut loaded = Simulation::new(RunSeed(8), raws);
+        let mut save = parse_save(&data).expect("Unable to parse save");
+        migrate(&mut save).expect("Unable to migrate save");
+        value_to_world(&mut loaded.ecs, save).expect("Unable to load save");
+        let after = snapshot(&loaded.ecs);
+
+        for (name, values) in before.components.iter() {
+            assert_eq!(Some(values), after.components.get(name), "{} differs after loading", name);
+        }
+        for (name, value) in before.resources.iter() {
+            assert_eq!(Some(value), after.resources.get(name), "{} differs after loading", name);
+        }
+        assert_eq!(before.player, after.player);
+        assert_eq!(before.player_pos, after.player_pos);
+        assert!(*loaded.ecs.fetch::<RunState>() == *sim.ecs.fetch::<RunState>());
+    }
+
+    /// A component left out of `all_components!` would be missing on both sides
+    /// of the round trip, so the registry is checked against `components.rs`.
+    #[test]
+    fn every_component_is_saved() {
+        macro_rules! names {
+            ($( $type:ty ),*) => { vec![$( stringify!($type) ),*] };
+        }
+        let saved: Vec<&str> = all_components!(names!());
+        // Намерение смешать живёт один ход, см. `register_components`
+        let unsaved = ["WantsToMixPotions"];
+
+        let source = include_str!("components.rs");
+        let mut lines = source.lines();
+        while let Some(line) = lines.next() {
+            let is_component = line.starts_with("#[derive(")
+                && line.split(|c: char| !c.is_alphanumeric()).any(|word| word == "Component");
+            if !is_component {
+                continue;
+            }
+            let name = lines.find_map(|l| l.strip_prefix("pub struct "))
+                .and_then(|rest| rest.split(|c: char| !c.is_alphanumeric()).next())
+                .expect("Component derive without a struct");
+            assert!(saved.contains(&name) || unsaved.contains(&name), "{} is not in all_components!", name);
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use rltk::Point;
+use specs::prelude::*;
+use specs::saveload::SimpleMarkerAllocator;
+
+use crate::alchemy_system::{AlchemySystem, DilutionSystem};
+use crate::components::*;
+use crate::damage_system::{self, DamageSystem};
+use crate::effects::{self, EffectQueue};
+use crate::fire_system::FireSystem;
+use crate::fluid_system::FluidSystem;
+use crate::gameevent::{self, GameEvent, GameEvents};
+use crate::gamelog::GameLog;
+use crate::gas_system::GasSystem;
+use crate::identify_system::{IdentifySystem, PotionAppearances};
+use crate::journal::AlchemyJournal;
+use crate::inventory_system::{InventorySystem, ItemDropSystem, ItemThrowSystem, ItemUseSystem};
+use crate::level_store::{LevelStore, StoredLevel};
+use crate::map::{Map, TileType, MAPCOUNT};
+use crate::map_builders::{self, MapgenHistory};
+use crate::map_indexing_system::MapIndexingSystem;
+use crate::melee_combat_system::MeleeCombatSystem;
+use crate::monster_ai_system::{BossAI, LobberAI, MonsterAI};
+use crate::particle_system::{self, ParticleSpawnSystem};
+use crate::player::{get_item, try_move_player, try_next_level, try_previous_level};
+use crate::raws::{Raws, MAX_STATION_SLOTS};
+use crate::replay::Replay;
+use crate::rng::{self, RandomStreams, RunSeed};
+use crate::saveload_system::{self, SaveSlot};
+use crate::staineffect_system::StainEffect;
+use crate::terrain_system::TerrainSystem;
+use crate::visibility_system::VisibilitySystem;
+use crate::{registry, spawner, AlchemySeed, RunState};
+
+/// Действие игрока, не привязанное к клавиатуре или мыши.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PlayerCommand {
+    Move { dx: i32, dy: i32 },
+    PickUp,
+    Use { item: Entity, target: Option<Point> },
+    Drop { item: Entity },
+    Throw { item: Entity, target: Point },
+    Mix { first: Entity, second: Entity },
+    /// Brew at the station the player stands on; empty slots are `None`
+    Brew { station: Entity, ingredients: [Option<Entity>; STATION_SLOTS], temperature: Temperature },
+    /// Split a potion in two weaker ones, next to water
+    Dilute { item: Entity },
+    Descend,
+    Ascend,
+    Wait
+}
+
+pub const STATION_SLOTS: usize = MAX_STATION_SLOTS as usize;
+
+/// Commands the player has given this run.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default)]
+pub struct TurnCounter(pub u32);
+
+/// The game world and its turn loop, without any window or input device.
+/// The rltk frontend in `main.rs` is a thin adapter over this.
+pub struct Simulation {
+    pub ecs: World,
+    /// Commands played so far; `None` once the run stops being replayable (e.g. after loading a save)
+    pub recording: Option<Replay>,
+    /// Save to `SaveSlot::Auto` on every level change. Off for headless runs and replays.
+    pub autosave: bool,
+}
+
+impl Simulation {
+    pub fn new(seed: RunSeed, raws: Raws) -> Simulation {
+        let mut ecs = World::new();
+
+        registry::register_components(&mut ecs);
+
+        ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+
+        let mut streams = RandomStreams::new(seed);
+        let mut history = MapgenHistory::default();
+        let level = map_builders::build_level(1, &raws, &mut streams.mapgen, &mut history);
+        ecs.insert(history);
+        ecs.insert(raws);
+        let (player_x, player_y) = (level.start.x, level.start.y);
+        let start_idx = level.map.xy_idx(player_x, player_y);
+        let mut map = level.map;
+
+        ecs.insert(seed);
+        ecs.insert(AlchemySeed(rng::alchemy_seed(seed)));
+        ecs.insert(PotionAppearances::new(rng::appearance_seed(seed)));
+        ecs.insert(AlchemyJournal::default());
+        ecs.insert(streams);
+
+        ecs.insert(particle_system::ParticleBuilder::new());
+        ecs.insert(EffectQueue::new());
+        ecs.insert(GameEvents::new());
+
+        ecs.insert(Point::new(player_x, player_y));
+        let player_entity = spawner::player(&mut ecs, player_x, player_y);
+        ecs.insert(player_entity);
+
+        // Рядом с игроком в начале забега никого нет
+        for region in level.spawn_regions.iter().filter(|r| !r.contains(&start_idx)) {
+            spawner::spawn_region(&mut ecs, region, &mut map, 1);
+        }
+        spawner::spawn_prefab_entities(&mut ecs, &level.spawns, 1);
+        record_spawns(&ecs, &map);
+
+        ecs.insert(map);
+        ecs.insert(GameLog { entries: vec!["Welcome to the dungeon of doom!".to_string()] });
+        ecs.insert(RunState::PreRun);
+        ecs.insert(TurnCounter::default());
+        ecs.insert(LevelStore::new());
+        ecs.insert(false);
+
+        Simulation { ecs, recording: Some(Replay::new(seed)), autosave: false }
+    }
+
+    pub fn runstate(&self) -> RunState {
+        if *self.ecs.fetch::<bool>() {
+            return RunState::Win;
+        }
+        *self.ecs.fetch::<RunState>()
+    }
+
+    pub fn set_runstate(&mut self, runstate: RunState) {
+        let mut runwriter = self.ecs.write_resource::<RunState>();
+        *runwriter = runstate;
+    }
+
+    /// Turns a command into intents for the systems. Only valid while awaiting input.
+    pub fn apply_command(&mut self, command: PlayerCommand) -> RunState {
+        // Частицы прошлого хода удаляются здесь, а не по таймеру кадра
+        particle_system::remove_all_particles(&mut self.ecs);
+        self.ecs.maintain();
+
+        // Без лестницы под ногами это не ход: ни в запись, ни в счётчик
+        let accepted = match command {
+            PlayerCommand::Descend => try_next_level(&self.ecs),
+            PlayerCommand::Ascend => try_previous_level(&self.ecs),
+            _ => true
+        };
+        if !accepted {
+            return RunState::AwaitingInput;
+        }
+
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record_command(command);
+        }
+        self.ecs.write_resource::<TurnCounter>().0 += 1;
+
+        let player = *self.ecs.fetch::<Entity>();
+        match command {
+            PlayerCommand::Move { dx, dy } => try_move_player(dx, dy, &mut self.ecs),
+            PlayerCommand::PickUp => get_item(&mut self.ecs),
+            PlayerCommand::Wait => {},
+            PlayerCommand::Descend => return RunState::NextLevel,
+            PlayerCommand::Ascend => return RunState::PreviousLevel,
+            PlayerCommand::Use { item, target } => {
+                let mut intent = self.ecs.write_storage::<WantsToUseItem>();
+                intent.insert(player, WantsToUseItem { item, target }).expect("Unable to insert use intent");
+            }
+            PlayerCommand::Drop { item } => {
+                let mut intent = self.ecs.write_storage::<WantsToDropItem>();
+                intent.insert(player, WantsToDropItem { item }).expect("Unable to insert drop intent");
+            }
+            PlayerCommand::Throw { item, target } => {
+                let mut intent = self.ecs.write_storage::<WantsToThrowItem>();
+                intent.insert(player, WantsToThrowItem { item, target }).expect("Unable to insert throw intent");
+            }
+            PlayerCommand::Mix { first, second } => {
+                let mut intent = self.ecs.write_storage::<WantsToMixPotions>();
+                let mix = WantsToMixPotions { ingredients: vec![first, second], station: None, temperature: Temperature::Room };
+                intent.insert(player, mix).expect("Unable to insert intent to mix");
+            }
+            PlayerCommand::Brew { station, ingredients, temperature } => {
+                let mut intent = self.ecs.write_storage::<WantsToMixPotions>();
+                let mix = WantsToMixPotions { ingredients: ingredients.into_iter().flatten().collect(), station: Some(station), temperature };
+                intent.insert(player, mix).expect("Unable to insert intent to brew");
+            }
+            PlayerCommand::Dilute { item } => {
+                let mut intent = self.ecs.write_storage::<WantsToDilute>();
+                intent.insert(player, WantsToDilute { item }).expect("Unable to insert intent to dilute");
+            }
+        }
+
+        RunState::PlayerTurn
+    }
+
+    /// One step of the turn loop. States that need a player (menus, input) are returned unchanged.
+    pub fn advance(&mut self, runstate: RunState) -> RunState {
+        match runstate {
+            RunState::PreRun => {
+                self.run_systems();
+                self.ecs.maintain();
+                // Пока игрок варит, ходы идут без него
+                if self.wait_if_busy() { RunState::PlayerTurn } else { RunState::AwaitingInput }
+            }
+            RunState::PlayerTurn => {
+                self.run_systems();
+                self.ecs.maintain();
+                if self.over_chasm() { RunState::NextLevel } else { RunState::MonsterTurn }
+            }
+            RunState::MonsterTurn => {
+                self.run_systems();
+                self.ecs.maintain();
+                if self.over_chasm() { RunState::NextLevel } else { RunState::PreRun }
+            }
+            RunState::NextLevel | RunState::PreviousLevel => {
+                let depth = self.ecs.fetch::<Map>().depth;
+                self.goto_level(if runstate == RunState::NextLevel { depth + 1 } else { depth - 1 });
+                if self.autosave {
+                    // В сейв должен попасть уже новый уровень, а не переход на него
+                    self.set_runstate(RunState::PreRun);
+                    if let Err(e) = saveload_system::save_game(&mut self.ecs, SaveSlot::Auto) {
+                        self.ecs.write_resource::<GameEvents>().emit(GameEvent::AutosaveFailed { message: e.to_string() });
+                    }
+                }
+                RunState::PreRun
+            }
+            RunState::GameOver => {
+                self.run_systems();
+                self.ecs.maintain();
+                RunState::GameOver
+            }
+            _ => runstate
+        }
+    }
+
+    /// Removes the dead and stores the new state. Called once per frame by the frontend.
+    pub fn commit(&mut self, newrunstate: RunState) {
+        let newrunstate = damage_system::clean_up_dead(&mut self.ecs, newrunstate);
+        gameevent::publish_events(&mut self.ecs);
+        self.set_runstate(newrunstate);
+
+        // Ход закончен, когда игра снова ждёт игрока
+        if newrunstate == RunState::AwaitingInput || newrunstate == RunState::GameOver {
+            if let Some(recording) = self.recording.as_mut() {
+                recording.record_checksum(&self.ecs);
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> RunState {
+        let newrunstate = self.advance(self.runstate());
+        self.commit(newrunstate);
+        self.runstate()
+    }
+
+    /// Runs the turn loop until the player has to act again or the run is over.
+    pub fn run_until_input(&mut self) -> RunState {
+        loop {
+            match self.runstate() {
+                RunState::PreRun | RunState::PlayerTurn | RunState::MonsterTurn | RunState::NextLevel | RunState::PreviousLevel
+                    => { self.step(); },
+                runstate => return runstate
+            }
+        }
+    }
+
+    /// Headless counterpart of a key press: applies the command and plays out the turn.
+    pub fn play(&mut self, command: PlayerCommand) -> RunState {
+        let newrunstate = self.apply_command(command);
+        self.commit(newrunstate);
+        self.run_until_input()
+    }
+
+    fn run_systems(&mut self) {
+        let mut vis = VisibilitySystem {};
+        vis.run_now(&self.ecs);
+        let mut mob = MonsterAI {};
+        mob.run_now(&self.ecs);
+        let mut mapindex = MapIndexingSystem {};
+        mapindex.run_now(&self.ecs);
+        let mut melee = MeleeCombatSystem {};
+        melee.run_now(&self.ecs);
+        effects::run_effects_queue(&mut self.ecs);
+        let mut damage = DamageSystem {};
+        damage.run_now(&self.ecs);
+        let mut inventory = InventorySystem {};
+        inventory.run_now(&self.ecs);
+        let mut itemuse = ItemUseSystem {};
+        itemuse.run_now(&self.ecs);
+        let mut drop = ItemDropSystem {};
+        drop.run_now(&self.ecs);
+        let mut throw = ItemThrowSystem {};
+        throw.run_now(&self.ecs);
+        let mut boss = BossAI {};
+        boss.run_now(&self.ecs);
+        let mut alchemy = AlchemySystem {};
+        alchemy.run_now(&self.ecs);
+        let mut dilution = DilutionSystem {};
+        dilution.run_now(&self.ecs);
+        let mut identify = IdentifySystem {};
+        identify.run_now(&self.ecs);
+
+        let runstate;
+        {
+            let runstwriter = self.ecs.fetch::<RunState>();
+            runstate = *runstwriter;
+        }
+        if runstate == RunState::PlayerTurn {
+            let mut lobber = LobberAI {};
+            lobber.run_now(&self.ecs);
+            let mut fluids = FluidSystem {};
+            fluids.run_now(&self.ecs);
+            let mut gases = GasSystem {};
+            gases.run_now(&self.ecs);
+            let mut fire = FireSystem {};
+            fire.run_now(&self.ecs);
+            let mut terrain = TerrainSystem {};
+            terrain.run_now(&self.ecs);
+            let mut stain = StainEffect {};
+            stain.run_now(&self.ecs);
+        }
+
+        effects::run_effects_queue(&mut self.ecs);
+
+        let mut particles = ParticleSpawnSystem {};
+        particles.run_now(&self.ecs);
+
+        self.ecs.maintain();
+    }
+
+    fn entities_to_remove_on_level_change(&mut self) -> Vec<Entity> {
+        let entities = self.ecs.entities();
+        let player_entity = self.ecs.fetch::<Entity>();
+        let player = self.ecs.read_storage::<Player>();
+        let inbackpack = self.ecs.read_storage::<InBackpack>();
+
+        let mut to_delete: Vec<Entity> = vec![];
+        for entity in entities.join() {
+            let mut should_delete = true;
+
+            if let Some(_player) = player.get(entity) {
+                should_delete = false;
+            }
+
+            if let Some(backpack_item) = inbackpack.get(entity) {
+                if backpack_item.owner == *player_entity {
+                    should_delete = false;
+                }
+            }
+
+            if should_delete {
+                to_delete.push(entity);
+            }
+        }
+
+        to_delete
+    }
+
+    /// Goes straight down to `depth` as if the player took every staircase
+    /// at once. Levels come out the same as in play, since map generation and
+    /// spawning have their own random streams. Not recorded in the replay.
+    pub fn descend_to(&mut self, depth: i32) {
+        self.recording = None;
+        let current = self.ecs.fetch::<Map>().depth;
+        for next in current + 1..=depth {
+            self.goto_level(next);
+        }
+    }
+
+    /// Leaves the current level for `new_depth`. The level left behind goes
+    /// into the `LevelStore` as it is; a level that was visited before is
+    /// restored from there, otherwise a new one is generated.
+    fn goto_level(&mut self, new_depth: i32) {
+        let old_depth = self.ecs.fetch::<Map>().depth;
+        let fell = new_depth > old_depth && self.over_chasm();
+        if fell {
+            let player = *self.ecs.fetch::<Entity>();
+            let name = self.ecs.read_storage::<Name>().get(player).map_or("someone".to_owned(), |n| n.name.clone());
+            self.ecs.write_resource::<GameEvents>().emit(GameEvent::FellIntoChasm { target: player, name });
+        }
+
+        let to_store = self.entities_to_remove_on_level_change();
+        let stored = saveload_system::entities_to_value(&mut self.ecs, &to_store).expect("Unable to store the level");
+        self.ecs.delete_entities(&to_store).expect("Unable to delete entities");
+        {
+            let map = Map::clone(&self.ecs.fetch::<Map>());
+            let mut store = self.ecs.write_resource::<LevelStore>();
+            store.levels.insert(old_depth, StoredLevel { map, entities: stored });
+        }
+
+        let revisited = self.ecs.write_resource::<LevelStore>().levels.remove(&new_depth);
+        let Point { x: player_x, y: player_y } = match revisited {
+            Some(level) => {
+                let mut worldmap = level.map;
+                worldmap.tile_content = vec![vec![]; MAPCOUNT];
+                *self.ecs.write_resource::<Map>() = worldmap.clone();
+                saveload_system::value_to_entities(&mut self.ecs, level.entities).expect("Unable to restore the level");
+                self.ecs.write_resource::<GameEvents>().emit(GameEvent::LevelRevisited { depth: new_depth, ascended: new_depth < old_depth });
+
+                // Игрок появляется на лестнице, по которой пришёл
+                let arrival = if new_depth < old_depth { TileType::DownStairs } else { TileType::UpStairs };
+                let player = *self.ecs.fetch::<Point>();
+                worldmap.find_tile(arrival).map_or(player, |(x, y)| Point::new(x, y))
+            }
+            None => self.generate_level(new_depth)
+        };
+        let Point { x: player_x, y: player_y } = if fell {
+            self.land_after_fall(Point::new(player_x, player_y))
+        } else {
+            Point::new(player_x, player_y)
+        };
+        let mut player_pos = self.ecs.write_resource::<Point>();
+        *player_pos = Point::new(player_x, player_y);
+        let mut pos_components = self.ecs.write_storage::<Position>();
+        let player_entity = self.ecs.fetch::<Entity>();
+
+        if let Some(player_pos_comp) = pos_components.get_mut(*player_entity) {
+            player_pos_comp.x = player_x;
+            player_pos_comp.y = player_y;
+        }
+
+        let mut viewsheds = self.ecs.write_storage::<Viewshed>();
+
+        if let Some(player_vs) = viewsheds.get_mut(*player_entity) {
+            player_vs.dirty = true;
+        }
+    }
+
+    /// Spends one of the turns the player is busy for, if they are.
+    fn wait_if_busy(&mut self) -> bool {
+        let player = *self.ecs.fetch::<Entity>();
+        let mut busy = self.ecs.write_storage::<Busy>();
+        let Some(b) = busy.get_mut(player) else { return false };
+        b.turns -= 1;
+        if b.turns <= 0 {
+            busy.remove(player);
+        }
+        // Занятый ход считается как обычный
+        self.ecs.write_resource::<TurnCounter>().0 += 1;
+        true
+    }
+
+    /// The living player stands over a chasm and is about to fall.
+    fn over_chasm(&self) -> bool {
+        let player = *self.ecs.fetch::<Entity>();
+        let Point { x, y } = *self.ecs.fetch::<Point>();
+        let map = self.ecs.fetch::<Map>();
+        let alive = self.ecs.read_storage::<CombatStats>().get(player).is_some_and(|stats| stats.hp > 0);
+        alive && map.tiles[map.xy_idx(x, y)] == TileType::Chasm
+    }
+
+    /// Somewhere reachable from `arrival` where nobody stands; the fall hurts.
+    fn land_after_fall(&mut self, arrival: Point) -> Point {
+        let player = *self.ecs.fetch::<Entity>();
+        let map = self.ecs.fetch::<Map>();
+        let positions = self.ecs.read_storage::<Position>();
+        let blockers = self.ecs.read_storage::<BlocksTile>();
+        let occupied: Vec<usize> = (&positions, &blockers).join().map(|(pos, _)| map.xy_idx(pos.x, pos.y)).collect();
+        let free: Vec<usize> = map_builders::distances_from(&map, map.xy_idx(arrival.x, arrival.y)).iter().enumerate()
+            .filter(|(idx, distance)| distance.is_some() && map.tiles[*idx] == TileType::Floor && !occupied.contains(idx))
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let mut rng = self.ecs.write_resource::<RandomStreams>();
+        let damage = rng.combat.roll_dice(1, 6);
+        SufferDamage::new_damage(&mut self.ecs.write_storage::<SufferDamage>(), player, damage);
+        if free.is_empty() {
+            arrival
+        } else {
+            let idx = free[rng.combat.roll_dice(1, free.len() as i32) as usize - 1];
+            Point::new(idx as i32 % map.width, idx as i32 / map.width)
+        }
+    }
+
+    /// Builds and populates a new level; returns where the player starts on it.
+    fn generate_level(&mut self, new_depth: i32) -> Point {
+        let level = {
+            let raws = self.ecs.fetch::<Raws>();
+            let mut rng = self.ecs.write_resource::<RandomStreams>();
+            let mut history = self.ecs.write_resource::<MapgenHistory>();
+            map_builders::build_level(new_depth, &raws, &mut rng.mapgen, &mut history)
+        };
+        let mut worldmap = level.map;
+        *self.ecs.write_resource::<Map>() = worldmap.clone();
+
+        for region in level.spawn_regions.iter() {
+            spawner::spawn_region(&mut self.ecs, region, &mut worldmap, new_depth);
+        }
+        spawner::spawn_prefab_entities(&mut self.ecs, &level.spawns, new_depth);
+        record_spawns(&self.ecs, &worldmap);
+
+        {   // Костыли мои костыли
+            let mut worldmap_res = self.ecs.write_resource::<Map>();
+            *worldmap_res = worldmap.clone();
+        }
+
+        self.ecs.write_resource::<GameEvents>().emit(GameEvent::LevelEntered { depth: new_depth });
+
+        let player_entity = self.ecs.fetch::<Entity>();
+        let mut stats = self.ecs.write_storage::<CombatStats>();
+
+        if let Some(player_stats) = stats.get_mut(*player_entity) {
+            player_stats.hp = i32::max(player_stats.hp, player_stats.max_hp / 2);
+        }
+
+        level.start
+    }
+}
+
+/// Last step of the mapgen history: the finished map with what was spawned on it.
+/// The player is left out, they are moved to the start only after this.
+fn record_spawns(ecs: &World, map: &Map) {
+    let positions = ecs.read_storage::<Position>();
+    let renderables = ecs.read_storage::<Renderable>();
+    let players = ecs.read_storage::<Player>();
+    let spawns = (&positions, &renderables, !&players).join()
+        .map(|(pos, render, _)| (Point::new(pos.x, pos.y), render.glyph))
+        .collect();
+    ecs.write_resource::<MapgenHistory>().snapshot_spawns("spawns placed", map, spawns);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raws;
+    use crate::replay::world_checksum;
+
+    fn new_run(seed: u64) -> Simulation {
+        let mut sim = Simulation::new(RunSeed(seed), raws::load().expect("Unable to load raws"));
+        sim.run_until_input();
+        sim
+    }
+
+    #[test]
+    fn a_new_run_waits_for_the_player() {
+        let sim = new_run(1);
+        assert!(sim.runstate() == RunState::AwaitingInput);
+        assert_eq!(sim.ecs.fetch::<TurnCounter>().0, 0);
+    }
+
+    #[test]
+    fn waiting_plays_out_a_turn() {
+        let mut sim = new_run(1);
+        let runstate = sim.play(PlayerCommand::Wait);
+        assert!(runstate == RunState::AwaitingInput || runstate == RunState::GameOver);
+        assert_eq!(sim.ecs.fetch::<TurnCounter>().0, 1);
+        assert_eq!(sim.recording.as_ref().map(|r| r.turns.len()), Some(1));
+    }
+
+    #[test]
+    fn stairs_commands_off_the_stairs_are_not_turns() {
+        let mut sim = new_run(1);
+        let Point { x, y } = *sim.ecs.fetch::<Point>();
+        {
+            let map = sim.ecs.fetch::<Map>();
+            assert!(map.tiles[map.xy_idx(x, y)] == TileType::Floor);
+        }
+
+        assert!(sim.play(PlayerCommand::Descend) == RunState::AwaitingInput);
+        assert!(sim.play(PlayerCommand::Ascend) == RunState::AwaitingInput);
+        assert_eq!(sim.ecs.fetch::<TurnCounter>().0, 0);
+        assert_eq!(sim.recording.as_ref().map(|r| r.turns.len()), Some(0));
+    }
+
+    #[test]
+    fn the_same_seed_plays_the_same_run() {
+        let mut first = new_run(5);
+        let mut second = new_run(5);
+        for _ in 0..50 {
+            first.play(PlayerCommand::Wait);
+            second.play(PlayerCommand::Wait);
+        }
+        assert_eq!(world_checksum(&first.ecs), world_checksum(&second.ecs));
+    }
+}
@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use rltk::{to_cp437, RandomNumberGenerator, RGB};
+use rltk::{to_cp437, RGB};
 use specs::{prelude::*, saveload::{MarkedBuilder, SimpleMarker}};
 
-use crate::{components::{BlocksTile, Bomber, Boss, CombatStats, Confusion, Consumable, Explosion, InstantHarm, Item, LingerType, LingeringEffect, Lobber, MacGuffin, Monster, Name, Player, Position, Potion, ProvidesHealing, Renderable, SerializeMe, Teleport, Viewshed, Weight}, map::{self, Map, TileType, MAPWIDTH}, random_table::{RandomTable, SpawnEntry}, rect::Rect};
+use crate::{components::{AlchemyStation, BlocksTile, Bomber, Boss, CombatStats, Consumable, Exhales, Identifies, Item, Lobber, MacGuffin, Monster, Name, Player, Position, Potion, Renderable, SerializeMe, Unidentified, Viewshed, Weight}, effects::PotionEffect, identify_system::PotionAppearances, map::{self, Map, TileType, MAPWIDTH}, prefab::PrefabSpawn, random_table::RandomTable, raws::{parse_color, RawAi, RawItem, RawMonster, RawStation, Raws, BOSS, FINAL_LEVEL_TABLE, MACGUFFIN, ROOM_TABLE}, rng::RandomStreams};
 
 pub const MAX_MONSTERS: i32 = 4;
 
@@ -30,210 +30,183 @@ pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
         .build()
 }
 
-fn ork(ecs: &mut World, x: i32, y: i32) { monster(ecs, x, y, rltk::to_cp437('o'), "Ork"); }
-fn goblin(ecs: &mut World, x: i32, y: i32) { monster(ecs, x, y, rltk::to_cp437('g'), "Goblin"); }
+/// Spawns an item, a monster or a station described in the raws. A group id spawns one of its members.
+pub fn spawn_named(ecs: &mut World, id: &str, x: i32, y: i32) -> Option<Entity> {
+    let id = resolve_group(ecs, id);
 
-fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name: S) {
-    ecs
-        .create_entity()
-        .with(Position { x, y })
-        .with(Renderable {
-            glyph,
-            fg: RGB::named(rltk::RED),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 1
-        })
-        .with(Viewshed { visible_tiles: vec![], range: 8, dirty: true })
-        .with(Monster {})
-        .with(Name { name: name.to_string() })
-        .with(BlocksTile {})
-        .with(CombatStats {
-            max_hp: 10,
-            hp: 10,
-            defence: 1,
-            power: 8
-        })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-
-fn lobber(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position { x, y })
-        .with(Renderable {
-            glyph: to_cp437('a'),
-            fg: RGB::named(rltk::RED),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 1
-        })
-        .with(Viewshed { visible_tiles: vec![], range: 8, dirty: true })
-        .with(Lobber { turns: 4, targetpos: None })
-        .with(Name { name: "Thrall".to_owned() })
-        .with(BlocksTile {})
-        .with(CombatStats {
-            max_hp: 6,
-            hp: 6,
-            defence: 0,
-            power: 6
-        })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-
-fn bomber(ecs: &mut World, x: i32, y: i32) {
-    let potion;
-    let color;
-    {
-        let choice;
-        let choice_color;
-        {
-            let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
-            choice = rng.roll_dice(1, 16);
-            choice_color = rng.roll_dice(1, 2);
-        }
+    let item = ecs.fetch::<Raws>().item(&id).cloned();
+    if let Some(item) = item {
+        return Some(build_item(ecs, &item, Some((x, y))));
+    }
 
-        let mut potion_build = ecs
-            .create_entity()
-            .with(Item {})
-            .with(Potion {});
+    let monster = ecs.fetch::<Raws>().monster(&id).cloned();
+    if let Some(monster) = monster {
+        return Some(build_monster(ecs, &monster, x, y));
+    }
 
-        match choice {
-            1..=4 => {
-                potion_build = potion_build.with(Explosion { maxdmg: 10, radius: 4});
-                color = RGB::named(rltk::ORANGE);
-            }
-            5..=12 => {
-                potion_build = potion_build.with(InstantHarm { dmg: 5 });
-                color = RGB::named(rltk::DARKRED);
-            }
-            _ => {
-                let etype = match choice_color {
-                    1 => {
-                        color = RGB::named(rltk::RED);
-                        LingerType::Fire
-                    },
-                    _ => {
-                        color = RGB::named(rltk::GREEN);
-                        LingerType::Poison
-                    },
-                };
-                potion_build = potion_build.with(LingeringEffect { etype, duration: 3, dmg: 3 });
-            }
-        }
+    let station = ecs.fetch::<Raws>().station(&id).cloned();
+    if let Some(station) = station {
+        return Some(build_station(ecs, &station, x, y));
+    }
 
-        potion_build = potion_build.with(Renderable { 
-            glyph: rltk::to_cp437('!'), 
-            fg: color,
-            bg: RGB::named(rltk::BLACK), 
-            render_order: 2 
-        });
+    None
+}
 
-        potion = potion_build.build();
+fn resolve_group(ecs: &mut World, id: &str) -> String {
+    let mut id = id.to_owned();
+    loop {
+        let table = match ecs.fetch::<Raws>().group(&id) {
+            Some(group) => RandomTable::from_group(group),
+            None => return id
+        };
+        let mut rng = ecs.write_resource::<RandomStreams>();
+        id = table.roll(&mut rng.spawns).expect("Raws groups are never empty");
     }
+}
+
+fn build_item(ecs: &mut World, raw: &RawItem, pos: Option<(i32, i32)>) -> Entity {
+    let fg = parse_color(&raw.fg).expect("Raws colours are checked on load");
+    // Зелья, которые пьют, узнаются не сразу; эссенции бомберов и так безымянные
+    let effects: Vec<PotionEffect> = raw.effects.iter().map(|e| e.to_potion_effect()).collect();
+    let look = if raw.potion && raw.consumable { ecs.fetch::<PotionAppearances>().describe(&effects) } else { None };
+    let (name, shown_fg) = look.clone().unwrap_or((raw.name.clone(), fg));
 
-    ecs 
+    let mut builder = ecs
         .create_entity()
-        .with(Position { x, y })
         .with(Renderable {
-            glyph: rltk::to_cp437('¿'),
-            fg: color,
+            glyph: to_cp437(raw.glyph),
+            fg: shown_fg,
             bg: RGB::named(rltk::BLACK),
-            render_order: 1
-        })
-        .with(Viewshed { visible_tiles: vec![], range: 8, dirty: true })
-        .with(Monster {})
-        .with(Bomber { effect: potion })
-        .with(Name { name: "Living potion".to_owned() })
-        .with(BlocksTile {})
-        .with(CombatStats {
-            max_hp: 5,
-            hp: 5,
-            defence: 0,
-            power: 0
+            render_order: 2
         })
+        .with(Name { name })
+        .with(Item {});
+
+    if let Some((x, y)) = pos { builder = builder.with(Position { x, y }); }
+    if let Some(weight) = raw.weight { builder = builder.with(Weight(weight)); }
+    if raw.potion { builder = builder.with(Potion::standard()); }
+    if raw.consumable { builder = builder.with(Consumable {}); }
+    if raw.macguffin { builder = builder.with(MacGuffin {}); }
+    if raw.identifies { builder = builder.with(Identifies {}); }
+    if look.is_some() { builder = builder.with(Unidentified { name: raw.name.clone(), fg }); }
+    for effect in effects {
+        builder = effect.attach_to(builder);
+    }
+
+    builder
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-fn finalboss(ecs: &mut World, x: i32, y: i32) {
-    ecs
+fn build_monster(ecs: &mut World, raw: &RawMonster, x: i32, y: i32) -> Entity {
+    // Живое зелье носит в себе зелье, которым взрывается, и окрашено в его цвет
+    let mut payload = None;
+    if let Some(payload_id) = &raw.payload {
+        let payload_id = resolve_group(ecs, payload_id);
+        let item = ecs.fetch::<Raws>().item(&payload_id).cloned().expect("Raws payloads are checked on load");
+        payload = Some((build_item(ecs, &item, None), item.fg));
+    }
+
+    let fg = raw.fg.as_ref()
+                   .or(payload.as_ref().map(|p| &p.1))
+                   .map(|fg| parse_color(fg).expect("Raws colours are checked on load"))
+                   .expect("Raws monster colours are checked on load");
+
+    let mut builder = ecs
         .create_entity()
         .with(Position { x, y })
         .with(Renderable {
-            glyph: rltk::to_cp437('A'),
-            fg: RGB::named(rltk::VIOLET),
+            glyph: to_cp437(raw.glyph),
+            fg,
             bg: RGB::named(rltk::BLACK),
             render_order: 1
         })
-        .with(Viewshed { visible_tiles: vec![], range: 12, dirty: true })
-        .with(Boss { state: crate::components::BossState::ClosingIn(10), targetpos: None })
-        .with(Name { name: "The Cursed Alchemist".to_string() })
+        .with(Viewshed { visible_tiles: vec![], range: raw.vision, dirty: true })
+        .with(Name { name: raw.name.clone() })
         .with(BlocksTile {})
         .with(CombatStats {
-            max_hp: 70,
-            hp: 70,
-            defence: 2,
-            power: 12
-        })
+            max_hp: raw.hp,
+            hp: raw.hp,
+            defence: raw.defence,
+            power: raw.power
+        });
+
+    builder = match raw.ai {
+        RawAi::Melee => builder.with(Monster {}),
+        RawAi::Lobber => builder.with(Lobber { turns: 4, targetpos: None }),
+        RawAi::Boss => builder.with(Boss { state: crate::components::BossState::ClosingIn(10), targetpos: None })
+    };
+    if let Some((potion, _)) = payload {
+        builder = builder.with(Bomber { effect: potion });
+    }
+    if let Some(exhale) = raw.exhales {
+        builder = builder.with(Exhales { gas: exhale.gas, density: exhale.density });
+    }
+
+    builder
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-fn spawn_mcguffin(ecs: &mut World, x: i32, y: i32) {
+// Станция не мешает ходить: на неё встают, чтобы варить
+fn build_station(ecs: &mut World, raw: &RawStation, x: i32, y: i32) -> Entity {
     ecs
         .create_entity()
         .with(Position { x, y })
         .with(Renderable {
-            glyph: rltk::to_cp437('☼'), 
-            fg: RGB::named(rltk::GOLD),
+            glyph: to_cp437(raw.glyph),
+            fg: parse_color(&raw.fg).expect("Raws colours are checked on load"),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: 3
         })
-        .with(Item {})
-        .with(MacGuffin {})
-        .with(Weight(3))
-        .with(Name { name: "The Philosopher's Stone".to_owned() })
+        .with(Name { name: raw.name.clone() })
+        .with(AlchemyStation { slots: raw.slots, heat: raw.heat, cool: raw.cool, turns: raw.turns })
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-pub fn spawn_room(ecs: &mut World, room: &Rect, map: &mut Map, map_depth: i32) {
+/// Rolls the spawn table for one spawn region reported by the map builder.
+pub fn spawn_region(ecs: &mut World, region: &[usize], map: &mut Map, map_depth: i32) {
     let spawntable; 
-    let mut spawn_points: HashMap<(i32, i32), SpawnEntry> = HashMap::new();
+    // BTreeMap, а не HashMap: порядок спавна должен зависеть только от сида
+    let mut spawn_points: BTreeMap<(i32, i32), String> = BTreeMap::new();
     let mut boss_coords = None;
     let mut mcguffin_coords = None;
 
     {
         if map_depth == map::LEVELNUM {
-            spawntable = boss_table();
+            spawntable = spawn_table(ecs, FINAL_LEVEL_TABLE, map_depth);
+        } else {
+            spawntable = spawn_table(ecs, ROOM_TABLE, map_depth);
+        }
 
-            for (i, tile) in map.tiles.iter_mut().enumerate() {
-                if *tile == TileType::BossSpawner {
-                    *tile = TileType::Floor;
-                    boss_coords = Some(((i % MAPWIDTH) as i32, (i / MAPWIDTH) as i32));
-                } else if *tile == TileType::MacGuffinSpawner {
-                    *tile = TileType::Floor;
-                    mcguffin_coords = Some(((i % MAPWIDTH) as i32, (i / MAPWIDTH) as i32));
-                }
+        // Спавнеры бывают в любом вольте, не только в лаборатории
+        for (i, tile) in map.tiles.iter_mut().enumerate() {
+            if *tile == TileType::BossSpawner {
+                *tile = TileType::Floor;
+                boss_coords = Some(((i % MAPWIDTH) as i32, (i / MAPWIDTH) as i32));
+            } else if *tile == TileType::MacGuffinSpawner {
+                *tile = TileType::Floor;
+                mcguffin_coords = Some(((i % MAPWIDTH) as i32, (i / MAPWIDTH) as i32));
             }
-        } else {
-            spawntable = room_table(map_depth);
         }
 
-        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+        let mut rng = ecs.write_resource::<RandomStreams>();
+        let rng = &mut rng.spawns;
         let num_spawns = rng.roll_dice(1, MAX_MONSTERS + 3) + (map_depth - 1) - 3;
 
         #[allow(clippy::map_entry)]
         for _ in 0..num_spawns {
             let mut added = false;
             let mut tries = 0;
-            while !added && tries < 20 {
-                let x = room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1));
-                let y = room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1));
+            while !added && tries < 20 && !region.is_empty() {
+                let idx = region[rng.roll_dice(1, region.len() as i32) as usize - 1];
+                let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
 
                 if !spawn_points.contains_key(&(x, y)) && !map.blocked[map.xy_idx(x, y)] {
-                    spawn_points.insert((x, y), spawntable.roll(&mut rng));
+                    if let Some(id) = spawntable.roll(rng) {
+                        spawn_points.insert((x, y), id);
+                    }
                     added = true;
                 }
 
@@ -243,272 +216,45 @@ pub fn spawn_room(ecs: &mut World, room: &Rect, map: &mut Map, map_depth: i32) {
     }
 
     if let Some((x, y)) = boss_coords {
-        finalboss(ecs, x, y);
+        spawn_named(ecs, BOSS, x, y);
     }
     if let Some((x, y)) = mcguffin_coords {
-        spawn_mcguffin(ecs, x, y);
+        spawn_named(ecs, MACGUFFIN, x, y);
     }
 
-    for spawn in spawn_points.iter() {
-        let (x, y) = (spawn.0.0, spawn.0.1);
-        match spawn.1 {
-            SpawnEntry::Goblin  
-                => goblin(ecs, x, y),
-            SpawnEntry::Ork 
-                => ork(ecs, x, y),
-            SpawnEntry::Bomber
-                => bomber(ecs, x, y),
-            SpawnEntry::Lobber
-                => lobber(ecs, x, y),
-            // SpawnEntry::MissileScroll
-            //     => magic_missile_scroll(ecs, spawn.0.0, spawn.0.1),
-            SpawnEntry::HealingPotion
-                => healing_potion(ecs, x, y),
-            SpawnEntry::ConfusionPotion
-                => confusion_potion(ecs, x, y),
-            // SpawnEntry::FireballScroll
-            //     => fireball_scroll(ecs, spawn.0.0, spawn.0.1),
-            SpawnEntry::TeleportPotion
-                => teleport_potion(ecs, x, y),
-            SpawnEntry::LingeringPotion
-                => lingering_potion(ecs, x, y),
-            SpawnEntry::HarmingPotion
-                => harm_potion(ecs, x, y),
-            SpawnEntry::ExplosionPotion
-                => explosion_potion(ecs, x, y),
-            SpawnEntry::None
-                => {},
-        }
-    }
-}
-
-fn healing_potion(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position { x, y })
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::MAGENTA),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Health potion".to_string() })
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        .with(ProvidesHealing { heal_amount: 2 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-/*
-fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position { x, y })
-        .with(Renderable {
-            glyph: rltk::to_cp437('?'),
-            fg: RGB::named(rltk::LIGHT_YELLOW),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Scroll of magic missile".to_string() })
-        .with(Item {})
-        .with(Consumable {})
-        .with(Ranged { range: 6 })
-        .with(InflictsDamage { damage: 7 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-
-fn fireball_scroll(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('?'),
-            fg: RGB::named(rltk::ORANGE),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Scroll of fireball".to_string()})
-        .with(Item {})
-        .with(Consumable {})
-        .with(Ranged { range: 6 })
-        .with(InflictsDamage { damage: 20 })
-        .with(AreaOfEffect { radius: 3 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-*/
-fn confusion_potion(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::PINK),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Potion of Confusion".to_string()})
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        // .with(Ranged { range: 6 })
-        .with(Confusion { turns: 4 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-
-fn teleport_potion(ecs: &mut World, x: i32, y: i32) {
-    let safe;
-    {
-        let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
-        safe = rng.roll_dice(1, 6) != 1;
+    for ((x, y), id) in spawn_points.iter() {
+        spawn_named(ecs, id, *x, *y);
     }
-
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::VIOLET),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Potion of Teleportation".to_string()})
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        .with(Teleport { safe })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
 }
 
-fn lingering_potion(ecs: &mut World, x: i32, y: i32) {
-    let name: &str;
-    let color: (u8, u8, u8);
-    let etype: LingerType;
-    {
-        let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
-        etype = match rng.roll_dice(1, 2) {
-            1 => {
-                name = "Potion of Fire";
-                color = rltk::RED;
-                LingerType::Fire
-            }, 
-            _ => {
-                name = "Potion of Poison";
-                color = rltk::GREEN;
-                LingerType::Poison
+/// Spawns what the prefabs of a new level ask for. `M` and `!` roll the
+/// monsters or the items of the depth's room table.
+pub fn spawn_prefab_entities(ecs: &mut World, spawns: &[(usize, PrefabSpawn)], map_depth: i32) {
+    for (idx, spawn) in spawns.iter() {
+        let (x, y) = ((idx % MAPWIDTH) as i32, (idx / MAPWIDTH) as i32);
+        let id = match spawn {
+            PrefabSpawn::Named(id) => Some(id.clone()),
+            PrefabSpawn::Monster | PrefabSpawn::Item => {
+                let table = {
+                    let raws = ecs.fetch::<Raws>();
+                    let table = raws.spawn_table(ROOM_TABLE).expect("Raws spawn tables are checked on load");
+                    RandomTable::from_raws_where(table, map_depth, |id| match spawn {
+                        PrefabSpawn::Monster => raws.only_monsters(id),
+                        _ => raws.only_items(id)
+                    })
+                };
+                let mut rng = ecs.write_resource::<RandomStreams>();
+                table.roll(&mut rng.spawns)
             }
         };
+        if let Some(id) = id {
+            spawn_named(ecs, &id, x, y);
+        }
     }
-
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(color),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: name.to_string() })
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        .with(LingeringEffect { etype, duration: 5, dmg: 3 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
 }
 
-fn harm_potion(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::VIOLET_RED),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Potion of Harm".to_string() })
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        .with(InstantHarm { dmg: 7 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
-}
-
-fn explosion_potion(ecs: &mut World, x: i32, y: i32) {
-    ecs
-        .create_entity()
-        .with(Position {x, y})
-        .with(Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::ORANGE),
-            bg: RGB::named(rltk::BLACK),
-            render_order: 2
-        })
-        .with(Name { name: "Potion of Explosion".to_string() })
-        .with(Item {})
-        .with(Potion {})
-        .with(Consumable {})
-        .with(Weight(1))
-        .with(Explosion { maxdmg: 10, radius: 4 })
-        .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+fn spawn_table(ecs: &World, id: &str, map_depth: i32) -> RandomTable {
+    let raws = ecs.fetch::<Raws>();
+    let table = raws.spawn_table(id).expect("Raws spawn tables are checked on load");
+    RandomTable::from_raws(table, map_depth)
 }
-
-fn room_table(map_depth: i32) -> RandomTable {
-    // #[cfg(debug_assertions)]
-    // return RandomTable::new()
-    //             // Enemies
-    //             .add(SpawnEntry::Goblin, 30 + map_depth)
-    //             .add(SpawnEntry::Ork, 5 + map_depth)
-    //             // Items
-    //             .add(SpawnEntry::HealingPotion, 7)
-    //             .add(SpawnEntry::LingeringPotion, 20 + map_depth)
-    //             .add(SpawnEntry::HarmingPotion, 20 + map_depth)
-    //             .add(SpawnEntry::ExplosionPotion, 20 + map_depth / 2)
-    //             // .add(SpawnEntry::FireballScroll, 2 + map_depth)
-    //             .add(SpawnEntry::ConfusionPotion, 20 + map_depth)
-    //             .add(SpawnEntry::TeleportPotion, 20 + map_depth);
-    //             // .add(SpawnEntry::MissileScroll, 4)
-
-    // #[cfg(not(debug_assertions))]
-    RandomTable::new()
-                // Enemies
-                .add(SpawnEntry::Goblin, 12)
-                .add(SpawnEntry::Ork, 1 + map_depth)
-                .add(SpawnEntry::Bomber, -3 + map_depth)
-                .add(SpawnEntry::Lobber, (map_depth + 4) / 2)
-                // Items
-                .add(SpawnEntry::HealingPotion, 7)
-                .add(SpawnEntry::LingeringPotion, 2 + map_depth)
-                .add(SpawnEntry::HarmingPotion, 4 + map_depth)
-                .add(SpawnEntry::ExplosionPotion, 3 + map_depth / 2)
-                // .add(SpawnEntry::FireballScroll, 2 + map_depth)
-                .add(SpawnEntry::ConfusionPotion, 2 + map_depth)
-                .add(SpawnEntry::TeleportPotion, 1 + map_depth / 2)
-                // .add(SpawnEntry::MissileScroll, 4)
-}
-
-fn boss_table() -> RandomTable {
-    RandomTable::new()
-                .add(SpawnEntry::HealingPotion, 5)
-                .add(SpawnEntry::LingeringPotion, 7)
-                .add(SpawnEntry::HarmingPotion, 10)
-                .add(SpawnEntry::ExplosionPotion, 3)
-                .add(SpawnEntry::ConfusionPotion, 5)
-                .add(SpawnEntry::TeleportPotion, 2)
-                .add(SpawnEntry::Bomber, 2)
-                .add(SpawnEntry::Lobber, 2)
-}
\ No newline at end of file
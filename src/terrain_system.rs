@@ -0,0 +1,54 @@
+use specs::prelude::*;
+
+use crate::{components::{CombatStats, LingerType, LingeringEffect, Name, Player, Position}, effects::{EffectQueue, EffectSource, EffectType, PotionEffect, Targets}, gameevent::{GameEvent, GameEvents}, map::{Map, TileType}};
+
+// Лава жжёт как зелье огня
+const LAVA_FIRE: LingeringEffect = LingeringEffect { etype: LingerType::Fire, duration: 3, dmg: 3 };
+
+/// What the ground does to those standing on it: lava sets them on fire,
+/// water puts the fire out, monsters over a chasm fall. A falling player is
+/// handled by `Simulation`, since they go down a level. Burning grass is
+/// `FireSystem`'s business.
+pub struct TerrainSystem {}
+
+impl<'a> System<'a> for TerrainSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = ( Entities<'a>,
+                        ReadExpect<'a, Map>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, CombatStats>,
+                        ReadStorage<'a, Player>,
+                        ReadStorage<'a, Name>,
+                        WriteStorage<'a, LingeringEffect>,
+                        WriteExpect<'a, GameEvents>,
+                        WriteExpect<'a, EffectQueue>
+                      );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, map, positions, combat, players, names, mut linger, mut events, mut effects) = data;
+
+        for (ent, pos, _stats) in (&entities, &positions, &combat).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            let name = names.get(ent).map_or("someone".to_owned(), |n| n.name.clone());
+            let on_fire = linger.get(ent).is_some_and(|l| l.etype == LingerType::Fire);
+
+            match map.tiles[idx] {
+                TileType::Lava => {
+                    if !on_fire {
+                        events.emit(GameEvent::Ignited { target: ent, name });
+                    }
+                    effects.push(EffectType::Potion(PotionEffect::Linger(LAVA_FIRE)), EffectSource::Terrain, None, Targets::Single(ent));
+                }
+                TileType::ShallowWater | TileType::DeepWater if on_fire => {
+                    linger.remove(ent);
+                    events.emit(GameEvent::Extinguished { target: ent, name });
+                }
+                TileType::Chasm if !players.contains(ent) => {
+                    entities.delete(ent).expect("Unable to delete a monster that fell into a chasm");
+                    events.emit(GameEvent::FellIntoChasm { target: ent, name });
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use rltk::{field_of_view, Point};
 use specs::prelude::*;
-use crate::{components::Player, map::Map};
+use crate::{components::Player, map::{Map, MAPWIDTH}};
 
 use super::{Viewshed, Position};
 
@@ -30,6 +30,18 @@ impl<'a> System<'a> for VisibilitySystem {
                         map.revealed_tiles[idx] = true;
                         map.visible_tiles[idx] = true;
                     }
+
+                    // Огонь освещает округу: её видно издалека, если ничто не заслоняет
+                    if map.fire.iter().any(|fuel| *fuel > 0) {
+                        for lit in field_of_view(Point::new(pos.x, pos.y), MAPWIDTH as i32, &*map) {
+                            if lit.x < 0 || lit.x >= map.width || lit.y < 0 || lit.y >= map.height { continue; }
+                            let idx = map.xy_idx(lit.x, lit.y);
+                            if map.is_lit(idx) {
+                                map.revealed_tiles[idx] = true;
+                                map.visible_tiles[idx] = true;
+                            }
+                        }
+                    }
                 }
             }
         }